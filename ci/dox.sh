@@ -3,6 +3,6 @@
 set -ex
 
 export RUSTDOCFLAGS="--cfg jemallocator_docs"
-cargo doc --features alloc_trait
+cargo doc --features allocator_api
 cargo doc -p tikv-jemalloc-sys
 cargo doc -p tikv-jemalloc-ctl
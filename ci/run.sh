@@ -30,6 +30,7 @@ cargo test --target "${TARGET}" --features profiling
 cargo test --target "${TARGET}" --features debug
 cargo test --target "${TARGET}" --features stats
 cargo test --target "${TARGET}" --features 'debug profiling'
+cargo test --target "${TARGET}" --features allocator-api2
 
 cargo test --target "${TARGET}" \
     --features override_allocator_on_supported_platforms
@@ -59,6 +60,32 @@ case "${TARGET}" in
         cargo test --target "${TARGET}" \
                    --manifest-path jemalloc-ctl/Cargo.toml \
                    --no-default-features
+        if [ "${MSRV}" = "true" ]
+        then
+            # The symbolizing features (pprof, flamegraph and top_sites)
+            # depend on `backtrace`, which needs a newer Rust than the MSRV:
+            echo "the symbolizing features of jemalloc-ctl are not tested"
+            cargo test --target "${TARGET}" \
+                       --manifest-path jemalloc-ctl/Cargo.toml \
+                       --features 'stats profiling use_std heap_profile prof_recent'
+        else
+            cargo test --target "${TARGET}" \
+                       --manifest-path jemalloc-ctl/Cargo.toml \
+                       --features 'stats profiling use_std heap_profile pprof flamegraph top_sites prof_recent'
+            # The frame pointer walker needs frame pointers in all the code:
+            RUSTFLAGS="${RUSTFLAGS} -C force-frame-pointers=yes" \
+                cargo test --target "${TARGET}" \
+                           --manifest-path jemalloc-ctl/Cargo.toml \
+                           --features 'frame_pointers top_sites' \
+                           --release --test frame_pointers
+        fi
+        if [ "${MSRV}" = "true" ]
+        then
+            echo "jeprof-rs has its own rust-version and is not tested"
+        else
+            cargo test --target "${TARGET}" \
+                       --manifest-path jeprof-rs/Cargo.toml
+        fi
         # FIXME: cross fails to pass features to jemalloc-ctl
         # ${CARGO_CMD} test --target "${TARGET}" \
         #             --manifest-path jemalloc-ctl \
@@ -71,12 +98,11 @@ cargo test --target "${TARGET}" \
              --manifest-path jemallocator-global/Cargo.toml \
              --features force_global_jemalloc
 
-# FIXME: Re-enable following test when allocator API is stable again.
-# if [ "${TRAVIS_RUST_VERSION}" = "nightly"  ]
-# then
-#     # The Alloc trait is unstable:
-#     ${CARGO_CMD} test --target "${TARGET}" --features alloc_trait
-# fi
+if rustc --version | grep -q nightly
+then
+    # The Allocator trait is unstable:
+    cargo test --target "${TARGET}" --features allocator_api
+fi
 
 # Test that overriding works in dylibs.
 case "$TARGET" in
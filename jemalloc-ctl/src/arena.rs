@@ -0,0 +1,249 @@
+//! Per-arena operations.
+//!
+//! The `arena.<i>.*` keys of the _MALLCTL NAMESPACE_ address a single arena
+//! through its index `<i>`, or all arenas at once through the index
+//! `MALLCTL_ARENAS_ALL`. The [`Arena`] handle fills in the index of the keys.
+
+use crate::error::Result;
+use crate::keys::{Access, AsName, Mib, MibStr};
+use crate::raw;
+use libc::c_uint;
+use tikv_jemalloc_sys::{extent_hooks_t, MALLCTL_ARENAS_ALL};
+
+/// Handle to a jemalloc arena, or to all of them.
+///
+/// The handle does not own the arena: it can be freely copied, and creating a
+/// handle for an index that does not refer to an initialized arena is safe,
+/// but its operations then fail.
+///
+/// # Example
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::arena::Arena;
+///
+/// let arena = Arena::create().unwrap();
+/// arena.set_dirty_decay_ms(0).unwrap();
+/// assert_eq!(arena.dirty_decay_ms().unwrap(), 0);
+///
+/// // Purge the unused dirty pages of all arenas:
+/// Arena::ALL.purge().unwrap();
+/// # }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Arena(c_uint);
+
+impl Arena {
+    /// Handle addressing all arenas (`MALLCTL_ARENAS_ALL`).
+    ///
+    /// Only some operations support it, e.g. [`purge`](Self::purge),
+    /// [`decay`](Self::decay) and [`set_dss`](Self::set_dss).
+    pub const ALL: Arena = Arena(MALLCTL_ARENAS_ALL);
+
+    /// Returns a handle to the arena with index `index`.
+    pub const fn new(index: c_uint) -> Self {
+        Arena(index)
+    }
+
+    /// Creates a new arena through `arenas.create` and returns a handle to it.
+    ///
+    /// The arena uses the default extent hooks.
+    pub fn create() -> Result<Self> {
+        let index: c_uint = unsafe { raw::read(b"arenas.create\0")? };
+        Ok(Arena(index))
+    }
+
+    /// Returns the index of the arena.
+    pub const fn index(self) -> c_uint {
+        self.0
+    }
+
+    /// Returns the MIB of the `arena.0.*` key `name` for this arena.
+    fn mib(self, name: &[u8]) -> Result<Mib<[usize; 3]>> {
+        let mut mib: Mib<[usize; 3]> = name.name().mib()?;
+        mib[1] = self.0 as usize;
+        Ok(mib)
+    }
+
+    /// Purges all unused dirty pages of the arena (`arena.<i>.purge`).
+    pub fn purge(self) -> Result<()> {
+        let mib = self.mib(b"arena.0.purge\0")?;
+        // Purging only releases unused pages, the allocations are unaffected:
+        unsafe { raw::call_mib(mib.as_ref()) }
+    }
+
+    /// Triggers decay-based purging of the unused dirty and muzzy pages of the
+    /// arena (`arena.<i>.decay`).
+    pub fn decay(self) -> Result<()> {
+        let mib = self.mib(b"arena.0.decay\0")?;
+        // Decay-based purging only releases unused pages:
+        unsafe { raw::call_mib(mib.as_ref()) }
+    }
+
+    /// Discards all the allocations of the arena (`arena.<i>.reset`).
+    ///
+    /// This is only supported by arenas created with `arenas.create`. The
+    /// arena remains usable afterwards.
+    ///
+    /// # Safety
+    ///
+    /// None of the memory allocated from the arena may be used or deallocated
+    /// afterwards.
+    pub unsafe fn reset(self) -> Result<()> {
+        raw::call_mib(self.mib(b"arena.0.reset\0")?.as_ref())
+    }
+
+    /// Destroys the arena, discarding all of its allocations
+    /// (`arena.<i>.destroy`).
+    ///
+    /// This is only supported by arenas created with `arenas.create`. The
+    /// index may be reused by arenas created afterwards.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`reset`](Self::reset). Additionally, the arena must not be
+    /// used afterwards.
+    pub unsafe fn destroy(self) -> Result<()> {
+        raw::call_mib(self.mib(b"arena.0.destroy\0")?.as_ref())
+    }
+
+    /// Returns the precedence of the `sbrk(2)` allocation relative to the
+    /// `mmap(2)` allocation of the arena (`arena.<i>.dss`).
+    ///
+    /// See [`crate::opt::dss`] for the possible values.
+    pub fn dss(self) -> Result<&'static str> {
+        let mut mib: MibStr<[usize; 3]> = b"arena.0.dss\0".name().mib_str()?;
+        mib[1] = self.0 as usize;
+        mib.read()
+    }
+
+    /// Sets the precedence of the `sbrk(2)` allocation relative to the
+    /// `mmap(2)` allocation of the arena (`arena.<i>.dss`).
+    ///
+    /// # Panics
+    ///
+    /// If `dss` is not null-terminated (e.g. `"primary\0"`).
+    pub fn set_dss(self, dss: &'static str) -> Result<()> {
+        let mut mib: MibStr<[usize; 3]> = b"arena.0.dss\0".name().mib_str()?;
+        mib[1] = self.0 as usize;
+        mib.write(dss)
+    }
+
+    /// Returns the approximate time in milliseconds from the creation of a set
+    /// of unused dirty pages until they are purged (`arena.<i>.dirty_decay_ms`).
+    ///
+    /// A value of `-1` disables purging.
+    pub fn dirty_decay_ms(self) -> Result<isize> {
+        self.mib(b"arena.0.dirty_decay_ms\0")?.read()
+    }
+
+    /// Sets the dirty decay time of the arena (`arena.<i>.dirty_decay_ms`).
+    ///
+    /// See [`dirty_decay_ms`](Self::dirty_decay_ms).
+    pub fn set_dirty_decay_ms(self, value: isize) -> Result<()> {
+        self.mib(b"arena.0.dirty_decay_ms\0")?.write(value)
+    }
+
+    /// Returns the approximate time in milliseconds from the creation of a set
+    /// of unused muzzy pages until they are purged (`arena.<i>.muzzy_decay_ms`).
+    ///
+    /// A value of `-1` disables purging.
+    pub fn muzzy_decay_ms(self) -> Result<isize> {
+        self.mib(b"arena.0.muzzy_decay_ms\0")?.read()
+    }
+
+    /// Sets the muzzy decay time of the arena (`arena.<i>.muzzy_decay_ms`).
+    ///
+    /// See [`muzzy_decay_ms`](Self::muzzy_decay_ms).
+    pub fn set_muzzy_decay_ms(self, value: isize) -> Result<()> {
+        self.mib(b"arena.0.muzzy_decay_ms\0")?.write(value)
+    }
+
+    /// Returns the maximum size by which the retained virtual memory of the
+    /// arena grows at once (`arena.<i>.retain_grow_limit`).
+    ///
+    /// Only meaningful if `opt.retain` is enabled.
+    pub fn retain_grow_limit(self) -> Result<usize> {
+        self.mib(b"arena.0.retain_grow_limit\0")?.read()
+    }
+
+    /// Sets the maximum size by which the retained virtual memory of the
+    /// arena grows at once (`arena.<i>.retain_grow_limit`).
+    pub fn set_retain_grow_limit(self, value: usize) -> Result<()> {
+        self.mib(b"arena.0.retain_grow_limit\0")?.write(value)
+    }
+
+    /// Returns the extent hooks of the arena (`arena.<i>.extent_hooks`).
+    pub fn extent_hooks(self) -> Result<*mut extent_hooks_t> {
+        let mib = self.mib(b"arena.0.extent_hooks\0")?;
+        unsafe { raw::read_mib(mib.as_ref()) }
+    }
+
+    /// Replaces the extent hooks of the arena, returning the previous ones
+    /// (`arena.<i>.extent_hooks`).
+    ///
+    /// # Safety
+    ///
+    /// `hooks` must point to a valid `extent_hooks_t` that outlives the arena,
+    /// whose functions uphold the contracts documented by jemalloc.
+    pub unsafe fn set_extent_hooks(
+        self,
+        hooks: *mut extent_hooks_t,
+    ) -> Result<*mut extent_hooks_t> {
+        let mib = self.mib(b"arena.0.extent_hooks\0")?;
+        raw::update_mib(mib.as_ref(), hooks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Arena;
+
+    #[test]
+    fn purge_and_decay() {
+        Arena::ALL.purge().unwrap();
+        Arena::ALL.decay().unwrap();
+        Arena::new(0).purge().unwrap();
+        Arena::new(0).decay().unwrap();
+    }
+
+    #[test]
+    fn decay_ms() {
+        let arena = Arena::create().unwrap();
+        let dirty = arena.dirty_decay_ms().unwrap();
+        arena.set_dirty_decay_ms(dirty + 1).unwrap();
+        assert_eq!(arena.dirty_decay_ms().unwrap(), dirty + 1);
+
+        let muzzy = arena.muzzy_decay_ms().unwrap();
+        arena.set_muzzy_decay_ms(-1).unwrap();
+        assert_eq!(arena.muzzy_decay_ms().unwrap(), -1);
+        arena.set_muzzy_decay_ms(muzzy).unwrap();
+        assert_eq!(arena.muzzy_decay_ms().unwrap(), muzzy);
+    }
+
+    #[test]
+    fn dss_and_hooks() {
+        let arena = Arena::create().unwrap();
+        let dss = arena.dss().unwrap();
+        let name = dss.trim_end_matches('\0');
+        assert!(["disabled", "primary", "secondary"].contains(&name));
+        arena.set_dss(dss).unwrap();
+        assert!(!arena.extent_hooks().unwrap().is_null());
+        if let Ok(limit) = arena.retain_grow_limit() {
+            arena.set_retain_grow_limit(limit).unwrap();
+        }
+    }
+
+    #[test]
+    fn reset_and_destroy() {
+        let arena = Arena::create().unwrap();
+        unsafe {
+            arena.reset().unwrap();
+            arena.destroy().unwrap();
+        }
+        assert!(Arena::new(u32::MAX >> 4).purge().is_err());
+    }
+}
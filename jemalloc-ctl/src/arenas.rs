@@ -1,5 +1,10 @@
 //! Arena operations.
 
+use crate::error::{check_index, Result};
+use crate::keys::Access;
+use crate::std::sync::atomic::{AtomicU32, Ordering};
+use libc::c_uint;
+
 option! {
     narenas[ str: b"arenas.narenas\0", non_str: 2 ] => libc::c_uint |
     ops: r |
@@ -23,3 +28,533 @@ option! {
     /// ```
     mib_docs: /// See [`narenas`].
 }
+
+option! {
+    quantum[ str: b"arenas.quantum\0", non_str: 2 ] => libc::size_t |
+    ops: r |
+    docs:
+    /// Quantum size, i.e. the minimum alignment of all allocations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("quantum: {}", arenas::quantum::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`quantum`].
+}
+
+option! {
+    page[ str: b"arenas.page\0", non_str: 2 ] => libc::size_t |
+    ops: r |
+    docs:
+    /// Page size used by jemalloc.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("page size: {}", arenas::page::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`page`].
+}
+
+option! {
+    tcache_max[ str: b"arenas.tcache_max\0", non_str: 2 ] => libc::size_t |
+    ops: r |
+    docs:
+    /// Maximum size class cached by the thread caches.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("tcache_max: {}", arenas::tcache_max::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`tcache_max`].
+}
+
+option! {
+    nbins[ str: b"arenas.nbins\0", non_str: 2 ] => libc::c_uint |
+    ops: r |
+    docs:
+    /// Number of bin size classes, i.e. of small size classes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("number of bins: {}", arenas::nbins::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`nbins`].
+}
+
+option! {
+    nhbins[ str: b"arenas.nhbins\0", non_str: 2 ] => libc::c_uint |
+    ops: r |
+    docs:
+    /// Total number of thread cache bin size classes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("number of tcache bins: {}", arenas::nhbins::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`nhbins`].
+}
+
+option! {
+    nlextents[ str: b"arenas.nlextents\0", non_str: 2 ] => libc::c_uint |
+    ops: r |
+    docs:
+    /// Number of large size classes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("number of large size classes: {}", arenas::nlextents::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`nlextents`].
+}
+
+/// Returns the index of the arena that owns the allocation `ptr`
+/// (`arenas.lookup`).
+///
+/// # Safety
+///
+/// `ptr` must be a pointer returned by jemalloc whose allocation has not been
+/// deallocated yet.
+///
+/// jemalloc looks the pointer up in its radix tree of extents assuming that it
+/// was allocated by jemalloc, and does not check that the nodes of the tree on
+/// the path to the pointer exist. Passing any other pointer, including a
+/// pointer into a large allocation, whose interior pages are not registered,
+/// may fail or crash the process. For the same reason there is no way to check
+/// whether an arbitrary pointer is owned by jemalloc, e.g. in a process that
+/// also uses the system allocator: such checks need to be done by the caller,
+/// e.g. by tracking which allocator made each allocation.
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::arenas;
+/// let buf = vec![0_u8; 1024];
+/// let arena = unsafe { arenas::lookup(buf.as_ptr()).unwrap() };
+/// assert!(arena < arenas::narenas::read().unwrap());
+/// # }
+/// ```
+pub unsafe fn lookup<T>(ptr: *const T) -> Result<c_uint> {
+    crate::raw::write_read(b"arenas.lookup\0", ptr as *const libc::c_void)
+}
+
+/// Returns the number of size classes read by `read`, which is fixed, caching
+/// it in `cache`.
+fn size_classes(
+    cache: &AtomicU32,
+    read: fn() -> Result<c_uint>,
+) -> Result<c_uint> {
+    match cache.load(Ordering::Relaxed) {
+        0 => {
+            let n = read()?;
+            cache.store(n, Ordering::Relaxed);
+            Ok(n)
+        }
+        n => Ok(n),
+    }
+}
+
+/// Fails with `ENOENT` unless `index` is the index of a bin.
+///
+/// jemalloc accepts `nbins` as a bin index and reads past its tables.
+pub(crate) fn check_bin(index: c_uint) -> Result<()> {
+    static NBINS: AtomicU32 = AtomicU32::new(0);
+    check_index(index, size_classes(&NBINS, nbins::read)?)
+}
+
+/// Fails with `ENOENT` unless `index` is the index of a large size class.
+///
+/// jemalloc accepts `nlextents` as a large size class index and reads past
+/// its tables.
+pub(crate) fn check_lextent(index: c_uint) -> Result<()> {
+    static NLEXTENTS: AtomicU32 = AtomicU32::new(0);
+    check_index(index, size_classes(&NLEXTENTS, nlextents::read)?)
+}
+
+/// Implements `read(index)` for a key whose name contains the index of a bin
+/// or of a large size class as its third component, checking the index with
+/// `$check`.
+macro_rules! indexed {
+    ($id:ident, $check:ident => $ret_ty:ty) => {
+        paste::paste! {
+            impl $id {
+                /// Reads the value of the size class with index `index`.
+                pub fn read(index: c_uint) -> Result<$ret_ty> {
+                    Self::mib()?.read(index)
+                }
+            }
+
+            impl [<$id _mib>] {
+                /// Reads the value of the size class with index `index` using
+                /// the MIB API.
+                pub fn read(self, index: c_uint) -> Result<$ret_ty> {
+                    $check(index)?;
+                    let mut mib = self.0;
+                    mib[2] = index as usize;
+                    mib.read()
+                }
+            }
+        }
+    };
+}
+
+option! {
+    bin_size[ str: b"arenas.bin.0.size\0", non_str: 4 ] => libc::size_t |
+    ops: |
+    docs:
+    /// Maximum size supported by the bin size class with index `i`
+    /// (`arenas.bin.<i>.size`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// let bin_size = arenas::bin_size::mib().unwrap();
+    /// for i in 0..arenas::nbins::read().unwrap() {
+    ///     println!("bin {} has size {}", i, bin_size.read(i).unwrap());
+    /// }
+    /// # }
+    /// ```
+    mib_docs: /// See [`bin_size`].
+}
+
+indexed!(bin_size, check_bin => libc::size_t);
+
+option! {
+    bin_nregs[ str: b"arenas.bin.0.nregs\0", non_str: 4 ] => u32 |
+    ops: |
+    docs:
+    /// Number of regions per slab of the bin size class with index `i`
+    /// (`arenas.bin.<i>.nregs`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("regions per slab of bin 0: {}", arenas::bin_nregs::read(0).unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`bin_nregs`].
+}
+
+indexed!(bin_nregs, check_bin => u32);
+
+option! {
+    bin_slab_size[ str: b"arenas.bin.0.slab_size\0", non_str: 4 ] => libc::size_t |
+    ops: |
+    docs:
+    /// Number of bytes per slab of the bin size class with index `i`
+    /// (`arenas.bin.<i>.slab_size`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("slab size of bin 0: {}", arenas::bin_slab_size::read(0).unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`bin_slab_size`].
+}
+
+indexed!(bin_slab_size, check_bin => libc::size_t);
+
+option! {
+    bin_nshards[ str: b"arenas.bin.0.nshards\0", non_str: 4 ] => u32 |
+    ops: |
+    docs:
+    /// Number of shards per arena of the bin size class with index `i`
+    /// (`arenas.bin.<i>.nshards`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("shards of bin 0: {}", arenas::bin_nshards::read(0).unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`bin_nshards`].
+}
+
+indexed!(bin_nshards, check_bin => u32);
+
+option! {
+    lextent_size[ str: b"arenas.lextent.0.size\0", non_str: 4 ] => libc::size_t |
+    ops: |
+    docs:
+    /// Maximum size supported by the large size class with index `i`
+    /// (`arenas.lextent.<i>.size`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::arenas;
+    /// println!("smallest large size class: {}", arenas::lextent_size::read(0).unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`lextent_size`].
+}
+
+indexed!(lextent_size, check_lextent => libc::size_t);
+
+/// Kind of a size class.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SizeClassKind {
+    /// Small size class, whose allocations are regions of slabs.
+    Small {
+        /// Number of regions per slab.
+        nregs: u32,
+        /// Number of bytes per slab.
+        slab_size: usize,
+        /// Number of shards per arena.
+        nshards: u32,
+    },
+    /// Large size class, whose allocations are extents of their own.
+    Large,
+}
+
+/// A jemalloc size class.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SizeClass {
+    /// Maximum size supported by the size class.
+    ///
+    /// Allocations are rounded up to the size of the smallest size class that
+    /// can hold them.
+    pub size: usize,
+    /// Kind of the size class.
+    pub kind: SizeClassKind,
+}
+
+/// Iterator over all size classes, from the smallest to the largest.
+///
+/// It yields the small size classes (`arenas.bin.<i>.*`) followed by the large
+/// ones (`arenas.lextent.<i>.*`).
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::arenas::SizeClasses;
+///
+/// // Round a buffer size up to its size class:
+/// let size = 1000;
+/// let class = SizeClasses::new()
+///     .unwrap()
+///     .map(Result::unwrap)
+///     .find(|class| class.size >= size)
+///     .unwrap();
+/// println!("a buffer of {} bytes uses {} bytes", size, class.size);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SizeClasses {
+    nbins: c_uint,
+    nlextents: c_uint,
+    next: c_uint,
+    bin_size: bin_size_mib,
+    bin_nregs: bin_nregs_mib,
+    bin_slab_size: bin_slab_size_mib,
+    bin_nshards: bin_nshards_mib,
+    lextent_size: lextent_size_mib,
+}
+
+impl SizeClasses {
+    /// Returns an iterator over all size classes.
+    pub fn new() -> Result<Self> {
+        Ok(SizeClasses {
+            nbins: nbins::read()?,
+            nlextents: nlextents::read()?,
+            next: 0,
+            bin_size: bin_size::mib()?,
+            bin_nregs: bin_nregs::mib()?,
+            bin_slab_size: bin_slab_size::mib()?,
+            bin_nshards: bin_nshards::mib()?,
+            lextent_size: lextent_size::mib()?,
+        })
+    }
+
+    fn read(&self, index: c_uint) -> Result<SizeClass> {
+        if index < self.nbins {
+            Ok(SizeClass {
+                size: self.bin_size.read(index)?,
+                kind: SizeClassKind::Small {
+                    nregs: self.bin_nregs.read(index)?,
+                    slab_size: self.bin_slab_size.read(index)?,
+                    nshards: self.bin_nshards.read(index)?,
+                },
+            })
+        } else {
+            Ok(SizeClass {
+                size: self.lextent_size.read(index - self.nbins)?,
+                kind: SizeClassKind::Large,
+            })
+        }
+    }
+}
+
+impl Iterator for SizeClasses {
+    type Item = Result<SizeClass>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.nbins + self.nlextents {
+            return None;
+        }
+        let class = self.read(self.next);
+        self.next += 1;
+        Some(class)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.nbins + self.nlextents - self.next) as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for SizeClasses {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexed_keys() {
+        let n = nbins::read().unwrap();
+        assert!(n > 0);
+        assert!(nlextents::read().unwrap() > 0);
+        assert!(nhbins::read().unwrap() > 0);
+        assert_eq!(
+            bin_size::read(0).unwrap(),
+            quantum::read().unwrap().min(8)
+        );
+        assert!(bin_nregs::read(0).unwrap() > 0);
+        assert_eq!(bin_slab_size::read(0).unwrap() % page::read().unwrap(), 0);
+        assert!(bin_nshards::read(0).unwrap() > 0);
+        assert!(
+            lextent_size::read(0).unwrap() > bin_size::read(n - 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn indexed_keys_bounds() {
+        let (n, m) = (nbins::read().unwrap(), nlextents::read().unwrap());
+        assert!(bin_size::read(n - 1).is_ok());
+        assert!(bin_size::read(n).is_err());
+        assert!(bin_nregs::read(n).is_err());
+        assert!(bin_slab_size::read(n).is_err());
+        assert!(bin_nshards::read(n).is_err());
+        assert!(lextent_size::read(m - 1).is_ok());
+        assert!(lextent_size::read(m).is_err());
+        assert!(lextent_size::mib().unwrap().read(m).is_err());
+    }
+
+    #[test]
+    fn size_classes() {
+        let classes = SizeClasses::new().unwrap();
+        let n = nbins::read().unwrap() as usize;
+        assert_eq!(classes.len(), n + nlextents::read().unwrap() as usize);
+
+        let mut prev = 0;
+        for (i, class) in classes.enumerate() {
+            let class = class.unwrap();
+            assert!(class.size > prev);
+            prev = class.size;
+            match class.kind {
+                SizeClassKind::Small {
+                    nregs, slab_size, ..
+                } => {
+                    assert!(i < n);
+                    assert_eq!(class.size * nregs as usize, slab_size);
+                }
+                SizeClassKind::Large => assert!(i >= n),
+            }
+        }
+    }
+
+    #[test]
+    fn lookup_arena() {
+        let arena = crate::arena::Arena::create().unwrap();
+        let flags = tikv_jemalloc_sys::MALLOCX_ARENA(arena.index() as usize)
+            | tikv_jemalloc_sys::MALLOCX_TCACHE_NONE;
+        unsafe {
+            for &size in &[8, 1 << 20] {
+                let ptr = tikv_jemalloc_sys::mallocx(size, flags);
+                assert!(!ptr.is_null());
+                assert_eq!(lookup(ptr).unwrap(), arena.index());
+                if size < 1 << 12 {
+                    // the pages of slabs are registered, so interior pointers
+                    // of small allocations can be looked up as well:
+                    let end = (ptr as *const u8).add(size - 1);
+                    assert_eq!(lookup(end).unwrap(), arena.index());
+                }
+                tikv_jemalloc_sys::sdallocx(ptr, size, flags);
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
 //! Error type
 
 use crate::{fmt, num, result};
-use libc::c_int;
+use libc::{c_int, c_uint};
 
 pub trait NonZeroT {
     type T;
@@ -61,6 +61,20 @@ impl StdError for Error {
     }
 }
 
+#[cfg(feature = "use_std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match error.0.get() as c_int {
+            libc::EINVAL => std::io::ErrorKind::InvalidInput,
+            libc::ENOENT => std::io::ErrorKind::NotFound,
+            libc::EPERM => std::io::ErrorKind::PermissionDenied,
+            libc::EAGAIN => std::io::ErrorKind::OutOfMemory,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
 fn description(code: c_int) -> Option<&'static str> {
     match code {
         libc::EINVAL => Some(
@@ -92,6 +106,18 @@ pub(crate) fn cvt(ret: c_int) -> Result<()> {
     }
 }
 
+/// Fails with `ENOENT`, like jemalloc for unknown keys, unless `index < len`.
+///
+/// Some jemalloc keys accept the index one past the end of their table and
+/// read out of bounds, so it must be checked before using them.
+pub(crate) fn check_index(index: c_uint, len: c_uint) -> Result<()> {
+    if index < len {
+        Ok(())
+    } else {
+        cvt(libc::ENOENT)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,6 +154,12 @@ impl<T: MibArg> AsMut<[usize]> for Mib<T> {
     }
 }
 
+impl<T: MibArg> AsRef<[usize]> for MibStr<T> {
+    fn as_ref(&self) -> &[usize] {
+        self.0.as_ref()
+    }
+}
+
 impl<T: MibArg> ops::Index<usize> for Mib<T> {
     type Output = usize;
     fn index(&self, idx: usize) -> &Self::Output {
@@ -355,6 +361,27 @@ impl Access<&'static str> for Name {
     }
 }
 
+pub trait MibArg:
+    Copy
+    + Clone
+    + PartialEq
+    + Default
+    + fmt::Debug
+    + AsRef<[usize]>
+    + AsMut<[usize]>
+{
+}
+impl<T> MibArg for T where
+    T: Copy
+        + Clone
+        + PartialEq
+        + Default
+        + fmt::Debug
+        + AsRef<[usize]>
+        + AsMut<[usize]>
+{
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Access, AsName, Mib, MibStr};
@@ -427,24 +454,3 @@ mod tests {
         assert_eq!(dss, dss2);
     }
 }
-
-pub trait MibArg:
-    Copy
-    + Clone
-    + PartialEq
-    + Default
-    + fmt::Debug
-    + AsRef<[usize]>
-    + AsMut<[usize]>
-{
-}
-impl<T> MibArg for T where
-    T: Copy
-        + Clone
-        + PartialEq
-        + Default
-        + fmt::Debug
-        + AsRef<[usize]>
-        + AsMut<[usize]>
-{
-}
@@ -81,24 +81,28 @@ static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 use crate::std::{fmt, mem, num, ops, ptr, result, slice, str};
 #[cfg(not(feature = "use_std"))]
 use core as std;
+// Re-exported at the crate root so that `crate::std` works either way.
 #[cfg(feature = "use_std")]
+#[allow(clippy::single_component_path_imports)]
 use std;
 
 #[macro_use]
 mod macros;
 
+pub mod arena;
 pub mod arenas;
 pub mod config;
 mod error;
 mod keys;
 pub mod opt;
-#[cfg(feature = "profiling")]
+#[cfg(any(feature = "profiling", feature = "heap_profile"))]
 pub mod profiling;
 pub mod raw;
 #[cfg(feature = "stats")]
 pub mod stats;
 #[cfg(feature = "use_std")]
 pub mod stats_print;
+pub mod tcache;
 pub mod thread;
 
 pub use error::{Error, Result};
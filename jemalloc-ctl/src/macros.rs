@@ -110,8 +110,53 @@ macro_rules! u {
     };
 }
 
+/// Call
+///
+/// Only for keys without value whose action cannot invalidate memory (e.g.
+/// `thread.tcache.flush`).
+macro_rules! c {
+    ($id:ident => $ret_ty:ty) => {
+        paste::paste! {
+            impl $id {
+                /// Triggers the action of the key using string API.
+                pub fn call() -> crate::error::Result<()> {
+                    unsafe { crate::raw::call(Self::NAME.as_bytes()) }
+                }
+            }
+
+            impl [<$id _mib>] {
+                /// Triggers the action of the key using MIB API.
+                pub fn call(self) -> crate::error::Result<()> {
+                    unsafe { crate::raw::call_mib(self.0.as_ref()) }
+                }
+            }
+        }
+    };
+}
+
+/// Returns whether profiling is enabled (`opt.prof`), which the `prof` keys
+/// require.
+#[cfg(test)]
+pub(crate) fn prof_enabled() -> bool {
+    use crate::keys::{Access, AsName};
+    b"opt.prof\0".name().read().unwrap_or(false)
+}
+
 macro_rules! make_test {
     ($id:ident, $ret_ty:ty, ()) => {};
+    // `prof.dump` writes files, see `tests/profiling.rs`:
+    (dump, $ret_ty:ty, ($($ops:ident),+)) => {};
+    // `prof.log_*` write files, see `tests/profiling.rs`:
+    (log_start, $ret_ty:ty, ($($ops:ident),+)) => {};
+    (log_stop, $ret_ty:ty, ($($ops:ident),+)) => {};
+    // Clearing the mutex statistics races with `stats::mutexes` tests:
+    (mutexes_reset, $ret_ty:ty, ($($ops:ident),+)) => {};
+    (reset, $ret_ty:ty, ($($ops:ident),+)) => {
+        make_test!(reset, $ret_ty, |_| 19, $($ops),+);
+    };
+    (prof_name, $ret_ty:ty, ($($ops:ident),+)) => {
+        make_test!(prof_name, $ret_ty, |_| "jemalloc_ctl_test\0", $($ops),+);
+    };
     (max_background_threads, $ret_ty:ty, ($($ops:ident),+)) => {
         make_test!(max_background_threads, $ret_ty, |_| 1, $($ops),+);
     };
@@ -130,6 +175,9 @@ macro_rules! make_test {
                     "background_thread" |
                     "max_background_threads"
                         if cfg!(target_os = "macos") => return,
+                    "prof_active" | "active" | "thread_active_init" | "gdump"
+                    | "recent_alloc_max"
+                        if !crate::macros::prof_enabled() => return,
                     _ => (),
                 }
 
@@ -167,6 +215,71 @@ macro_rules! make_test {
             }
         }
     };
+    ($id:ident, $ret_ty:ty, $test_val:expr, r,w) => {
+        paste::paste! {
+            #[cfg(test)]
+            #[test]
+            fn [<$id _read_write_test>]() {
+                let a = $id::read().unwrap();
+                let b = $test_val(a);
+                let _ = $id::write(b).unwrap();
+                let c = $id::read().unwrap();
+                assert_eq!(b, c);
+
+                let mib = $id::mib().unwrap();
+                let d = mib.read().unwrap();
+                assert_eq!(c, d);
+                let _ = mib.write(b).unwrap();
+                let e = mib.read().unwrap();
+                assert_eq!(b, e);
+            }
+        }
+    };
+    ($id:ident, $ret_ty:ty, $test_val:expr, w) => {
+        paste::paste! {
+            #[cfg(test)]
+            #[test]
+            fn [<$id _write_test>]() {
+                match stringify!($id) {
+                    "prof_name" if !crate::macros::prof_enabled() => return,
+                    _ => (),
+                }
+
+                $id::write(($test_val)(<$ret_ty as Default>::default())).unwrap();
+                let mib = $id::mib().unwrap();
+                mib.write(($test_val)(<$ret_ty as Default>::default())).unwrap();
+            }
+        }
+    };
+    ($id:ident, $ret_ty:ty, $test_val:expr, c,w) => {
+        paste::paste! {
+            #[cfg(test)]
+            #[test]
+            fn [<$id _call_write_test>]() {
+                match stringify!($id) {
+                    "reset" if !crate::macros::prof_enabled() => return,
+                    _ => (),
+                }
+
+                $id::call().unwrap();
+                $id::write(($test_val)(<$ret_ty as Default>::default())).unwrap();
+                let mib = $id::mib().unwrap();
+                mib.call().unwrap();
+                mib.write(($test_val)(<$ret_ty as Default>::default())).unwrap();
+            }
+        }
+    };
+    ($id:ident, $ret_ty:ty, $test_val:expr, c) => {
+        paste::paste! {
+            #[cfg(test)]
+            #[test]
+            fn [<$id _call_test>]() {
+                $id::call().unwrap();
+                let mib = $id::mib().unwrap();
+                mib.call().unwrap();
+            }
+        }
+    };
     ($id:ident, $ret_ty:ty, $test_val:expr, r) => {
         paste::paste! {
             #[cfg(test)]
@@ -1,7 +1,90 @@
 //! `jemalloc`'s run-time configuration for profiling-specific settings.
 //!
-//! These settings are controlled by the `MALLOC_CONF` environment variable.
+//! The `opt.prof*` settings are controlled by the `MALLOC_CONF` environment
+//! variable. The `prof.*` keys control heap profiling at run-time; they fail
+//! with `ENOENT` unless profiling is enabled through `opt.prof`.
+//!
+//! The keys need `jemalloc` built with `--enable-prof`, i.e. the `profiling`
+//! feature. Without it, the `heap_profile` feature only provides the parser
+//! of heap profiles.
+
+#[cfg(feature = "profiling")]
+use crate::error::Result;
+
+#[cfg(feature = "use_std")]
+mod heap_dump;
+#[cfg(all(feature = "profiling", feature = "use_std"))]
+mod prof_log;
+#[cfg(all(feature = "profiling", feature = "use_std"))]
+pub use self::prof_log::{
+    LogAllocation, LogInfo, LogReader, LogThread, ProfLog,
+};
+#[cfg(all(feature = "profiling", feature = "use_std"))]
+mod recent_alloc;
+#[cfg(feature = "use_std")]
+pub use self::heap_dump::{
+    dump_to_path, dump_to_vec, HeapDump, ProfileHeader,
+};
+#[cfg(all(feature = "profiling", feature = "use_std"))]
+pub use self::recent_alloc::recent_alloc_dump;
+#[cfg(feature = "prof_recent")]
+pub use self::recent_alloc::{
+    recent_allocs, AllocEvent, RecentAlloc, RecentAllocs,
+};
+#[cfg(feature = "profiling")]
+mod hooks;
+#[cfg(all(
+    feature = "profiling",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use self::hooks::{
+    frame_pointer_backtrace, set_frame_pointer_backtrace_hook,
+};
+#[cfg(feature = "profiling")]
+pub use self::hooks::{
+    raw_backtrace_hook, raw_dump_hook, reset_backtrace_hook,
+    set_backtrace_hook, set_dump_hook, set_raw_backtrace_hook,
+    set_raw_dump_hook, BacktraceHook, DumpHook, RawBacktraceHook, RawDumpHook,
+};
+#[cfg(feature = "profiling")]
+mod size_class_stats;
+#[cfg(feature = "profiling")]
+pub use self::size_class_stats::{
+    bins_accum, bins_accum_mib, bins_live, bins_live_mib, lextents_accum,
+    lextents_accum_mib, lextents_live, lextents_live_mib, ProfStats,
+    SizeClassProfStats, SizeClassesProfStats,
+};
+#[cfg(feature = "heap_profile")]
+mod heap_profile;
+#[cfg(feature = "heap_profile")]
+pub use self::heap_profile::{
+    Counts, Estimate, HeapProfile, Mapping, ParseError, Stack, Thread,
+    ThreadCounts,
+};
+#[cfg(feature = "heap_profile")]
+mod allocator_frames;
+#[cfg(feature = "heap_profile")]
+pub use self::allocator_frames::trim_allocator_frames;
+#[cfg(feature = "heap_profile")]
+mod diff;
+#[cfg(feature = "heap_profile")]
+pub use self::diff::{ProfileDiff, StackDelta};
+#[cfg(feature = "pprof")]
+mod pprof;
+#[cfg(any(feature = "pprof", feature = "flamegraph", feature = "top_sites"))]
+mod symbolize;
+#[cfg(feature = "pprof")]
+pub use self::pprof::{dump_pprof, PprofOptions};
+#[cfg(feature = "flamegraph")]
+mod flamegraph;
+#[cfg(feature = "flamegraph")]
+pub use self::flamegraph::FlamegraphOptions;
+#[cfg(feature = "top_sites")]
+mod top_sites;
+#[cfg(feature = "top_sites")]
+pub use self::top_sites::{top_sites, AllocationSite, SiteFrame};
 
+#[cfg(feature = "profiling")]
 option! {
     lg_prof_interval[ str: b"opt.lg_prof_interval\0", non_str: 2 ] => libc::ssize_t |
     ops: r |
@@ -32,6 +115,7 @@ option! {
     mib_docs: /// See [`lg_prof_interval`].
 }
 
+#[cfg(feature = "profiling")]
 option! {
     lg_prof_sample[ str: b"opt.lg_prof_sample\0", non_str: 2 ] => libc::size_t |
     ops: r |
@@ -57,6 +141,7 @@ option! {
     mib_docs: /// See [`lg_prof_sample`].
 }
 
+#[cfg(feature = "profiling")]
 option! {
     prof_final[ str: b"opt.prof_final\0", non_str: 2 ] => bool |
     ops: r |
@@ -87,6 +172,7 @@ option! {
     mib_docs: /// See [`prof_final`].
 }
 
+#[cfg(feature = "profiling")]
 option! {
     prof[ str: b"opt.prof\0", non_str: 2 ] => bool |
     ops: r |
@@ -124,6 +210,7 @@ option! {
     mib_docs: /// See [`prof`].
 }
 
+#[cfg(feature = "profiling")]
 option! {
     prof_leak[ str: b"opt.prof_leak\0", non_str: 2 ] => bool |
     ops: r |
@@ -153,3 +240,318 @@ option! {
     /// ```
     mib_docs: /// See [`prof_leak`].
 }
+
+#[cfg(feature = "profiling")]
+option! {
+    prof_stats[ str: b"opt.prof_stats\0", non_str: 2 ] => bool |
+    ops: r |
+    docs:
+    /// Exact per-size-class statistics of the sampled allocations
+    /// enabled/disabled.
+    ///
+    /// If enabled, the counts of the sampled allocations of each size class
+    /// are kept, see [`SizeClassesProfStats`]. Works only when combined with
+    /// `opt.prof`.
+    ///
+    /// This option is disabled by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// let prof_stats = profiling::prof_stats::read().unwrap();
+    /// println!("are per-size-class profiling statistics enabled: {}", prof_stats);
+    /// # }
+    /// ```
+    mib_docs: /// See [`prof_stats`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    active[ str: b"prof.active\0", non_str: 2 ] => bool |
+    ops: r,w,u |
+    docs:
+    /// Whether sampling is currently active.
+    ///
+    /// This is a global switch on top of the per-thread switch
+    /// ([`crate::thread::prof_active`]). See [`ProfActiveGuard`] to activate
+    /// sampling for a scope.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// let active = profiling::active::read().unwrap();
+    /// println!("is sampling active: {}", active);
+    /// # }
+    /// ```
+    mib_docs: /// See [`active`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    thread_active_init[ str: b"prof.thread_active_init\0", non_str: 2 ] => bool |
+    ops: r,w,u |
+    docs:
+    /// Initial value of [`crate::thread::prof_active`] for newly created
+    /// threads.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// let init = profiling::thread_active_init::read().unwrap();
+    /// println!("sampling active for new threads: {}", init);
+    /// # }
+    /// ```
+    mib_docs: /// See [`thread_active_init`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    dump[ str: b"prof.dump\0", str: 2 ] => &'static str |
+    ops: c,w |
+    docs:
+    /// Dumps a memory profile.
+    ///
+    /// `call` dumps it to a file named according to the pattern
+    /// \<prefix\>.\<pid\>.\<seq\>.m\<mseq\>.heap, where \<prefix\> is controlled
+    /// by the `opt.prof_prefix` and `prof.prefix` options. `write` dumps it to
+    /// the given null-terminated file name instead.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// profiling::dump::write("/tmp/app.heap\0").unwrap();
+    /// # }
+    /// ```
+    mib_docs: /// See [`dump`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    log_start[ str: b"prof.log_start\0", str: 2 ] => &'static str |
+    ops: c,w |
+    docs:
+    /// Starts logging the sampled allocations that are freed, until
+    /// [`log_stop`].
+    ///
+    /// `call` logs to a file named according to the pattern
+    /// \<prefix\>.\<pid\>.\<seq\>.json, `write` to the given null-terminated
+    /// file name instead. See [`ProfLog`] for a guard that accepts any path.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// profiling::log_start::write("/tmp/app.json\0").unwrap();
+    /// // ... run the workload ...
+    /// profiling::log_stop::call().unwrap();
+    /// # }
+    /// ```
+    mib_docs: /// See [`log_start`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    log_stop[ str: b"prof.log_stop\0", non_str: 2 ] => () |
+    ops: c |
+    docs:
+    /// Stops the logging started by [`log_start`] and writes the log.
+    ///
+    /// The log is a JSON object, see [`LogReader`] to read it.
+    mib_docs: /// See [`log_stop`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    gdump[ str: b"prof.gdump\0", non_str: 2 ] => bool |
+    ops: r,w,u |
+    docs:
+    /// Whether a memory profile is dumped every time the total virtual memory
+    /// exceeds its previous maximum.
+    ///
+    /// The profiles are named according to the pattern
+    /// \<prefix\>.\<pid\>.\<seq\>.u\<useq\>.heap.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// println!("dump on new maximum: {}", profiling::gdump::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`gdump`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    reset[ str: b"prof.reset\0", non_str: 2 ] => libc::size_t |
+    ops: c,w |
+    docs:
+    /// Resets all memory profile statistics.
+    ///
+    /// `call` keeps the current sample rate, `write` sets it to the given
+    /// value (log base 2) (see [`lg_sample`]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// if profiling::prof::read().unwrap() {
+    ///     profiling::reset::write(19).unwrap();
+    /// }
+    /// # }
+    /// ```
+    mib_docs: /// See [`reset`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    lg_sample[ str: b"prof.lg_sample\0", non_str: 2 ] => libc::size_t |
+    ops: r |
+    docs:
+    /// Current average interval (log base 2) between allocation samples.
+    ///
+    /// It is initialized from `opt.lg_prof_sample` and changed by [`reset`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// println!("sample interval: 2^{} bytes", profiling::lg_sample::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`lg_sample`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    interval[ str: b"prof.interval\0", non_str: 2 ] => u64 |
+    ops: r |
+    docs:
+    /// Average number of bytes allocated between interval-triggered profile
+    /// dumps, or `0` if they are disabled (see [`lg_prof_interval`]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// println!("dump interval: {} bytes", profiling::interval::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`interval`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    recent_alloc_max[ str: b"experimental.prof_recent.alloc_max\0", non_str: 3 ] => libc::ssize_t |
+    ops: r,w,u |
+    docs:
+    /// Maximum number of recently sampled allocations kept in the log dumped
+    /// by [`recent_alloc_dump`], or `-1` for no limit.
+    ///
+    /// It is initialized from `opt.prof_recent_alloc_max`. Lowering it drops
+    /// the oldest records. This key is experimental in `jemalloc`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// if profiling::prof::read().unwrap() {
+    ///     profiling::recent_alloc_max::write(1000).unwrap();
+    /// }
+    /// # }
+    /// ```
+    mib_docs: /// See [`recent_alloc_max`].
+}
+
+/// Scope guard that activates sampling ([`active`]) and restores its previous
+/// state when dropped.
+///
+/// Profiling must be enabled (`opt.prof`), e.g. by starting the program with
+/// `_RJEM_MALLOC_CONF=prof:true,prof_active:false`.
+///
+/// `prof.active` is process-wide and the guard is not reference-counted:
+/// guards only nest correctly if they are dropped in the reverse order of
+/// their creation. If the guards of two threads overlap, the first one to be
+/// dropped deactivates sampling for the other one too, and the last one may
+/// leave it active. Serialize such guards, or use one for the whole process.
+/// [`crate::thread::prof_active`] controls sampling per thread instead.
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling::{self, ProfActiveGuard};
+/// if profiling::prof::read().unwrap() {
+///     let _guard = ProfActiveGuard::new().unwrap();
+///     // allocations are sampled here...
+/// }
+/// # }
+/// ```
+#[cfg(feature = "profiling")]
+#[derive(Debug)]
+pub struct ProfActiveGuard {
+    previous: bool,
+}
+
+#[cfg(feature = "profiling")]
+impl ProfActiveGuard {
+    /// Activates sampling until the guard is dropped.
+    pub fn new() -> Result<Self> {
+        let previous = active::update(true)?;
+        Ok(ProfActiveGuard { previous })
+    }
+}
+
+#[cfg(feature = "profiling")]
+impl Drop for ProfActiveGuard {
+    fn drop(&mut self) {
+        let _ = active::write(self.previous);
+    }
+}
@@ -0,0 +1,150 @@
+//! Recognition of the frames of the allocator in symbolized stacks.
+
+/// Removes the frames of the allocator, which are at the top of every stack,
+/// from `frames`, innermost frame first.
+///
+/// `function` returns the name of the function of a frame. Every frame up to
+/// the outermost one of the allocator is removed: those of `jemalloc`, of
+/// the Rust allocator shims, of the implementations of `GlobalAlloc` and
+/// `Allocator` (such as `tikv_jemallocator`'s `Jemalloc`, `JemallocArena` and
+/// `JemallocTcache`), and any frame above them, such as the static functions
+/// of `jemalloc` and the backtrace hooks. The outermost frame of the
+/// allocator is kept if nothing else is left, so that the stack is still
+/// accounted for.
+///
+/// # Examples
+///
+/// ```
+/// use tikv_jemalloc_ctl::profiling::trim_allocator_frames;
+///
+/// let mut frames = vec!["_rjem_malloc", "__rust_alloc", "app::parse", "main"];
+/// trim_allocator_frames(&mut frames, |f| f);
+/// assert_eq!(frames, ["app::parse", "main"]);
+/// ```
+pub fn trim_allocator_frames<T>(
+    frames: &mut Vec<T>,
+    function: impl Fn(&T) -> &str,
+) {
+    let skip = frames
+        .iter()
+        .rposition(|frame| is_allocator(function(frame)))
+        .map_or(0, |i| i + 1);
+    frames.drain(..skip.min(frames.len().saturating_sub(1)));
+}
+
+/// Returns `true` for the functions of `jemalloc`, of the Rust allocator
+/// shims, and of the implementations of the allocator traits.
+fn is_allocator(function: &str) -> bool {
+    const FUNCTIONS: &[&str] = &[
+        "malloc",
+        "calloc",
+        "realloc",
+        "free",
+        "posix_memalign",
+        "aligned_alloc",
+        "memalign",
+        "valloc",
+        "mallocx",
+        "rallocx",
+    ];
+    const PREFIXES: &[&str] = &[
+        "_rjem_",
+        "je_",
+        "__rust_alloc",
+        "__rust_realloc",
+        "__rustc::__rust_alloc",
+        "__rustc::__rust_realloc",
+        "__rdl_",
+        "__rg_",
+        "__rustc::__rdl_",
+        "__rustc::__rg_",
+        "alloc::alloc::",
+        "tikv_jemallocator::",
+    ];
+    // Implementations for any type, e.g. `<alloc::alloc::Global as
+    // core::alloc::Allocator>::allocate`.
+    const TRAITS: &[&str] = &[
+        " as core::alloc::global::GlobalAlloc>::",
+        " as core::alloc::Allocator>::",
+        " as allocator_api2::stable::alloc::Allocator>::",
+        " as allocator_api2::alloc::Allocator>::",
+    ];
+    FUNCTIONS.contains(&function)
+        || PREFIXES.iter().any(|p| function.starts_with(p))
+        || (function.starts_with('<')
+            && TRAITS.iter().any(|t| function.contains(t)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocator_functions() {
+        for function in &[
+            "_rjem_malloc",
+            "_rjem_je_prof_backtrace",
+            "__rust_alloc",
+            "__rustc::__rust_alloc",
+            "__rustc::__rdl_alloc",
+            "alloc::alloc::alloc",
+            "tikv_jemallocator::sized::allocate",
+            "<tikv_jemallocator::Jemalloc as core::alloc::global::GlobalAlloc>::alloc",
+            "<tikv_jemallocator::arena::JemallocArena as core::alloc::global::GlobalAlloc>::alloc",
+            "<tikv_jemallocator::tcache::JemallocTcache as core::alloc::global::GlobalAlloc>::dealloc",
+            "<tikv_jemallocator::arena::JemallocArena as core::alloc::Allocator>::allocate",
+            "<tikv_jemallocator::tcache::JemallocTcache as allocator_api2::stable::alloc::Allocator>::grow",
+            "<alloc::alloc::Global as core::alloc::Allocator>::allocate",
+            "<allocator_api2::stable::alloc::global::Global as allocator_api2::stable::alloc::Allocator>::allocate",
+            "<app::Tracking as core::alloc::global::GlobalAlloc>::alloc",
+        ] {
+            assert!(is_allocator(function), "{}", function);
+        }
+        for function in &[
+            "app::parse",
+            "prof_backtrace_impl",
+            "alloc::vec::from_elem",
+            "<u8 as alloc::vec::spec_from_elem::SpecFromElem>::from_elem",
+            "std::sys::backtrace::__rust_begin_short_backtrace",
+        ] {
+            assert!(!is_allocator(function), "{}", function);
+        }
+    }
+
+    #[test]
+    fn trim() {
+        // The frames above the outermost frame of the allocator are removed
+        // even if they are not recognized.
+        let mut frames = vec![
+            "tikv_jemalloc_ctl::profiling::hooks::backtrace_trampoline",
+            "prof_backtrace_impl",
+            "_rjem_je_prof_backtrace",
+            "imalloc_body",
+            "_rjem_mallocx",
+            "<tikv_jemallocator::arena::JemallocArena as core::alloc::Allocator>::allocate",
+            "alloc::raw_vec::RawVecInner<A>::try_allocate_in",
+            "app::parse",
+            "main",
+        ];
+        trim_allocator_frames(&mut frames, |f| f);
+        assert_eq!(
+            frames,
+            [
+                "alloc::raw_vec::RawVecInner<A>::try_allocate_in",
+                "app::parse",
+                "main"
+            ]
+        );
+
+        // The outermost frame is kept if all frames are the allocator's.
+        let mut frames = vec!["_rjem_malloc", "__rust_alloc"];
+        trim_allocator_frames(&mut frames, |f| f);
+        assert_eq!(frames, ["__rust_alloc"]);
+        let mut frames: Vec<&str> = Vec::new();
+        trim_allocator_frames(&mut frames, |f| f);
+        assert!(frames.is_empty());
+        let mut frames = vec!["app::parse", "main"];
+        trim_allocator_frames(&mut frames, |f| f);
+        assert_eq!(frames, ["app::parse", "main"]);
+    }
+}
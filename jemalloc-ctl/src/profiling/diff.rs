@@ -0,0 +1,316 @@
+//! Differences between two heap profiles.
+
+use super::heap_profile::pcs;
+use super::{Estimate, HeapProfile, Mapping};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Change of the live memory allocated by a stack between two profiles.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StackDelta {
+    /// Return addresses, innermost frame first, in the address space of the
+    /// current profile when they could be lined up with it.
+    pub addresses: Vec<u64>,
+    /// Estimated counts in the base profile.
+    pub base: Estimate,
+    /// Estimated counts in the current profile.
+    pub current: Estimate,
+}
+
+impl StackDelta {
+    /// Estimated change of the number of live bytes.
+    pub fn bytes(&self) -> f64 {
+        self.current.bytes - self.base.bytes
+    }
+
+    /// Estimated change of the number of live objects.
+    pub fn objects(&self) -> f64 {
+        self.current.objects - self.base.objects
+    }
+
+    /// Returns the addresses of the instructions of the stack, see
+    /// [`Stack::pcs`](super::Stack::pcs).
+    pub fn pcs(&self) -> impl Iterator<Item = u64> + '_ {
+        pcs(&self.addresses)
+    }
+}
+
+/// Differences between a base and a current heap profile, computed by
+/// [`HeapProfile::diff`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct ProfileDiff {
+    /// Stacks whose live memory changed, by decreasing growth of live bytes.
+    pub stacks: Vec<StackDelta>,
+    /// Memory mappings of the current profile.
+    pub mappings: Vec<Mapping>,
+    /// Sample period of the current profile.
+    pub sample_period: u64,
+}
+
+impl HeapProfile {
+    /// Computes what changed between `base` and this profile, which must
+    /// have been dumped by the same binary.
+    ///
+    /// Counts are adjusted for the sampling rate of their own profile, so the
+    /// profiles may use different sample periods. Addresses are lined up
+    /// through the mapped files and offsets of `MAPPED_LIBRARIES`, so that
+    /// stacks match even if the libraries were mapped at different
+    /// addresses; addresses outside of mapped files must be equal.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// let before = profiling::dump_to_vec().unwrap().profile().unwrap();
+    /// // ... run the workload ...
+    /// let after = profiling::dump_to_vec().unwrap().profile().unwrap();
+    /// after.diff(&before).write_report(std::io::stdout(), 10).unwrap();
+    /// # }
+    /// ```
+    pub fn diff(&self, base: &HeapProfile) -> ProfileDiff {
+        let mut stacks: Vec<StackDelta> = Vec::new();
+        let mut index: HashMap<Vec<Frame>, usize> = HashMap::new();
+        for stack in &self.stacks {
+            let key = key(&self.mappings, &stack.addresses);
+            let estimate = self.estimate(&stack.counts);
+            match index.get(&key) {
+                Some(&i) => stacks[i].current += estimate,
+                None => {
+                    index.insert(key, stacks.len());
+                    stacks.push(StackDelta {
+                        addresses: stack.addresses.clone(),
+                        base: Estimate::default(),
+                        current: estimate,
+                    });
+                }
+            }
+        }
+        for stack in &base.stacks {
+            let key = key(&base.mappings, &stack.addresses);
+            let estimate = base.estimate(&stack.counts);
+            match index.get(&key) {
+                Some(&i) => stacks[i].base += estimate,
+                None => {
+                    let addresses = key
+                        .iter()
+                        .zip(&stack.addresses)
+                        .map(|(frame, &address)| {
+                            frame.rebase(&self.mappings).unwrap_or(address)
+                        })
+                        .collect();
+                    index.insert(key, stacks.len());
+                    stacks.push(StackDelta {
+                        addresses,
+                        base: estimate,
+                        current: Estimate::default(),
+                    });
+                }
+            }
+        }
+        stacks.retain(|s| s.bytes() != 0.0 || s.objects() != 0.0);
+        stacks.sort_by(|a, b| b.bytes().total_cmp(&a.bytes()));
+        ProfileDiff {
+            stacks,
+            mappings: self.mappings.clone(),
+            sample_period: self.header.sample_period,
+        }
+    }
+}
+
+impl ProfileDiff {
+    /// Estimated change of the total number of live bytes.
+    pub fn bytes(&self) -> f64 {
+        self.stacks.iter().map(StackDelta::bytes).sum()
+    }
+
+    /// Estimated change of the total number of live objects.
+    pub fn objects(&self) -> f64 {
+        self.stacks.iter().map(StackDelta::objects).sum()
+    }
+
+    /// Writes a text report of the `limit` stacks that grew the most, with
+    /// their addresses innermost frame first.
+    pub fn write_report<W: Write>(
+        &self,
+        mut writer: W,
+        limit: usize,
+    ) -> io::Result<()> {
+        writeln!(
+            writer,
+            "Total: {:+.0} bytes, {:+.0} objects",
+            self.bytes(),
+            self.objects()
+        )?;
+        writeln!(writer, "{:>16} {:>12}  stack", "bytes", "objects")?;
+        for stack in self.stacks.iter().take(limit) {
+            write!(
+                writer,
+                "{:>+16.0} {:>+12.0} ",
+                stack.bytes(),
+                stack.objects()
+            )?;
+            for address in &stack.addresses {
+                write!(writer, " {:#x}", address)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Converts the differences to a gzip-compressed `profile.proto`, with
+    /// negative values for the stacks that shrank.
+    #[cfg(feature = "pprof")]
+    pub fn to_pprof(
+        &self,
+        options: &super::PprofOptions,
+    ) -> io::Result<Vec<u8>> {
+        let mut builder = super::pprof::Builder::new(&self.mappings, options);
+        for stack in &self.stacks {
+            builder.sample(
+                stack.pcs(),
+                stack.objects().round() as i64,
+                stack.bytes().round() as i64,
+            );
+        }
+        builder.finish(self.sample_period)
+    }
+}
+
+/// Address independent of where libraries were mapped.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum Frame {
+    /// Offset in a mapped file.
+    Mapped(String, u64),
+    /// Address outside of mapped files.
+    Unmapped(u64),
+}
+
+impl Frame {
+    fn new(mappings: &[Mapping], address: u64) -> Self {
+        match mappings.iter().find(|m| m.contains(address)) {
+            Some(Mapping {
+                path: Some(path),
+                start,
+                offset,
+                ..
+            }) => Frame::Mapped(path.clone(), address - start + offset),
+            _ => Frame::Unmapped(address),
+        }
+    }
+
+    /// Returns the address of the frame in a process with `mappings`.
+    fn rebase(&self, mappings: &[Mapping]) -> Option<u64> {
+        match *self {
+            Frame::Mapped(ref path, file_offset) => mappings
+                .iter()
+                .find(|m| {
+                    m.path.as_ref() == Some(path)
+                        && m.offset <= file_offset
+                        && file_offset - m.offset < m.end - m.start
+                })
+                .map(|m| m.start + file_offset - m.offset),
+            Frame::Unmapped(address) => Some(address),
+        }
+    }
+}
+
+fn key(mappings: &[Mapping], addresses: &[u64]) -> Vec<Frame> {
+    addresses.iter().map(|&a| Frame::new(mappings, a)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The library is mapped at 0x10000 in the base and at 0x20000 in the
+    // current profile.
+    const BASE: &[u8] = b"heap_v2/1
+  t*: 3: 3072 [0: 0]
+@ 0x10010 0x10020
+  t*: 1: 1024 [0: 0]
+@ 0x10030 0x10020
+  t*: 2: 2048 [0: 0]
+@ 0x5000
+  t*: 1: 4096 [0: 0]
+
+MAPPED_LIBRARIES:
+00010000-00011000 r-xp 00001000 08:01 1 /lib/libapp.so
+";
+    const CURRENT: &[u8] = b"heap_v2/2
+  t*: 5: 5120 [0: 0]
+@ 0x20010 0x20020
+  t*: 4: 4096 [0: 0]
+@ 0x20030 0x20020
+  t*: 2: 2048 [0: 0]
+@ 0x6000
+  t*: 1: 1024 [0: 0]
+
+MAPPED_LIBRARIES:
+00020000-00021000 r-xp 00001000 08:01 1 /lib/libapp.so
+";
+
+    #[test]
+    fn diff() {
+        let base = HeapProfile::parse(BASE).unwrap();
+        let current = HeapProfile::parse(CURRENT).unwrap();
+        let diff = current.diff(&base);
+        assert_eq!(diff.sample_period, 2);
+
+        // Unchanged stacks are left out; the others are sorted by growth.
+        let stacks: Vec<(Vec<u64>, f64, f64)> = diff
+            .stacks
+            .iter()
+            .map(|s| (s.addresses.clone(), s.bytes(), s.objects()))
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                (vec![0x20010, 0x20020], 3072.0, 3.0),
+                (vec![0x6000], 1024.0, 1.0),
+                (vec![0x5000], -4096.0, -1.0),
+            ]
+        );
+        assert_eq!(diff.bytes(), 0.0);
+        assert_eq!(diff.objects(), 3.0);
+    }
+
+    #[test]
+    fn rebase_base_only_stacks() {
+        let base = HeapProfile::parse(BASE).unwrap();
+        let current = HeapProfile::parse(
+            b"heap_v2/1
+  t*: 0: 0 [0: 0]
+
+MAPPED_LIBRARIES:
+00020000-00021000 r-xp 00001000 08:01 1 /lib/libapp.so
+",
+        )
+        .unwrap();
+        let diff = current.diff(&base);
+        let addresses: Vec<&[u64]> =
+            diff.stacks.iter().map(|s| &s.addresses[..]).collect();
+        assert_eq!(
+            addresses,
+            [&[0x20010, 0x20020][..], &[0x20030, 0x20020], &[0x5000]]
+        );
+    }
+
+    #[test]
+    fn report() {
+        let base = HeapProfile::parse(BASE).unwrap();
+        let current = HeapProfile::parse(CURRENT).unwrap();
+        let mut out = Vec::new();
+        current.diff(&base).write_report(&mut out, 1).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Total: +0 bytes, +3 objects
+           bytes      objects  stack
+           +3072           +3  0x20010 0x20020
+"
+        );
+    }
+}
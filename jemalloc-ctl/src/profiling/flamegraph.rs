@@ -0,0 +1,168 @@
+//! Collapsed stacks and flamegraphs of heap profiles.
+
+use super::{symbolize, trim_allocator_frames, HeapProfile};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+/// Options for [`HeapProfile::write_collapsed`] and
+/// [`HeapProfile::write_flamegraph`].
+///
+/// All options default to `false`.
+#[derive(Copy, Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct FlamegraphOptions {
+    /// If set, frames are named after the functions resolved with the debug
+    /// information of the current process; otherwise, and for addresses
+    /// that cannot be resolved, they are named after their hexadecimal
+    /// address.
+    ///
+    /// This is only meaningful if the profile was dumped by the current
+    /// process, which is not the case of `prof_final` dumps.
+    pub symbolize: bool,
+}
+
+impl HeapProfile {
+    /// Writes the stacks of the profile in the collapsed format used by
+    /// Brendan Gregg's `flamegraph.pl`: one `root;...;leaf <bytes>` line per
+    /// stack, weighted by the estimated live bytes.
+    ///
+    /// Inlined functions get frames of their own.
+    pub fn write_collapsed<W: Write>(
+        &self,
+        mut writer: W,
+        options: &FlamegraphOptions,
+    ) -> io::Result<()> {
+        for (stack, bytes) in self.collapse(options) {
+            writeln!(writer, "{} {}", stack, bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a standalone SVG flamegraph of the live bytes of the profile.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use std::fs::File;
+    /// use tikv_jemalloc_ctl::profiling::{self, FlamegraphOptions};
+    /// let profile = profiling::dump_to_vec().unwrap().profile().unwrap();
+    /// let mut options = FlamegraphOptions::default();
+    /// options.symbolize = true;
+    /// let file = File::create("heap.svg").unwrap();
+    /// profile.write_flamegraph(file, &options).unwrap();
+    /// # }
+    /// ```
+    pub fn write_flamegraph<W: Write>(
+        &self,
+        writer: W,
+        options: &FlamegraphOptions,
+    ) -> io::Result<()> {
+        let lines: Vec<String> = self
+            .collapse(options)
+            .into_iter()
+            .map(|(stack, bytes)| format!("{} {}", stack, bytes))
+            .collect();
+        let mut flamegraph = inferno::flamegraph::Options::default();
+        flamegraph.title = "Live heap".to_owned();
+        flamegraph.count_name = "bytes".to_owned();
+        inferno::flamegraph::from_lines(
+            &mut flamegraph,
+            lines.iter().map(String::as_str),
+            writer,
+        )
+        // `io::Error::other` is not available on the minimum supported Rust
+        // version.
+        .map_err(
+            #[allow(clippy::io_other_error)]
+            |e| io::Error::new(io::ErrorKind::Other, e),
+        )
+    }
+
+    /// Returns the collapsed stacks, sorted and with the bytes of identical
+    /// stacks added up. Stacks without live bytes are left out, and so are
+    /// the frames of the allocator, see [`trim_allocator_frames`].
+    fn collapse(&self, options: &FlamegraphOptions) -> BTreeMap<String, u64> {
+        let mut names: HashMap<u64, Vec<String>> = HashMap::new();
+        let mut stacks = BTreeMap::new();
+        for stack in &self.stacks {
+            let bytes = self.estimate(&stack.counts).bytes.round() as u64;
+            if bytes == 0 {
+                continue;
+            }
+            for pc in stack.pcs() {
+                names.entry(pc).or_insert_with(|| frame_names(pc, options));
+            }
+            let mut frames: Vec<&str> = stack
+                .pcs()
+                .flat_map(|pc| names[&pc].iter().map(String::as_str))
+                .collect();
+            trim_allocator_frames(&mut frames, |frame| frame);
+            frames.reverse();
+            *stacks.entry(frames.join(";")).or_insert(0) += bytes;
+        }
+        stacks
+    }
+}
+
+/// Returns the names of the frames of `pc`, innermost inlined frame first.
+fn frame_names(pc: u64, options: &FlamegraphOptions) -> Vec<String> {
+    let names: Vec<String> = if options.symbolize {
+        symbolize::resolve(pc)
+            .into_iter()
+            // `;` separates frames, but appears in names such as `[u8; 4]`.
+            .map(|frame| frame.name.replace(';', ","))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if names.is_empty() {
+        vec![format!("{:#x}", pc)]
+    } else {
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &[u8] = b"heap_v2/1
+  t*: 3: 3072 [0: 0]
+@ 0x10 0x21 0x31
+  t*: 1: 1024 [0: 0]
+@ 0x11 0x21 0x31
+  t*: 2: 2048 [0: 0]
+@ 0x12 0x31
+  t*: 0: 0 [0: 0]
+";
+
+    #[test]
+    fn collapsed() {
+        let profile = HeapProfile::parse(PROFILE).unwrap();
+        let mut out = Vec::new();
+        profile
+            .write_collapsed(&mut out, &FlamegraphOptions::default())
+            .unwrap();
+        // With a sample period of 1 byte, estimates are the sampled counts.
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0x30;0x20;0x10 1024\n0x30;0x20;0x11 2048\n"
+        );
+    }
+
+    #[test]
+    fn flamegraph() {
+        let profile = HeapProfile::parse(PROFILE).unwrap();
+        let mut out = Vec::new();
+        profile
+            .write_flamegraph(&mut out, &FlamegraphOptions::default())
+            .unwrap();
+        let svg = String::from_utf8(out).unwrap();
+        assert!(svg.contains("<svg"));
+        assert!(svg.contains("0x20"));
+    }
+}
@@ -0,0 +1,211 @@
+//! Heap profile dumps to arbitrary paths and to memory.
+
+use crate::error::Result as CtlResult;
+use crate::raw;
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Dumps a memory profile to `path` (`prof.dump`).
+///
+/// Unlike [`dump::write`](super::dump::write), `path` does not need to be
+/// `'static` nor null-terminated.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling;
+/// let path = std::env::temp_dir().join("app.heap");
+/// profiling::dump_to_path(&path).unwrap();
+/// # }
+/// ```
+pub fn dump_to_path(path: &Path) -> io::Result<()> {
+    let path = path_to_cstring(path)?;
+    dump_to_cstring(&path)?;
+    Ok(())
+}
+
+fn dump_to_cstring(path: &CString) -> CtlResult<()> {
+    // This is safe because the key expects a pointer to a null-terminated
+    // string, which jemalloc does not retain.
+    unsafe { raw::write(b"prof.dump\0", path.as_ptr()) }
+}
+
+#[cfg(unix)]
+pub(super) fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(not(unix))]
+pub(super) fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    let path = path.to_str().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "path is not valid UTF-8")
+    })?;
+    CString::new(path)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Header of a heap profile in the `heap_v2` format.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ProfileHeader {
+    /// Average number of bytes allocated between samples.
+    pub sample_period: u64,
+    /// Number of sampled objects live at the time of the dump.
+    pub objects: u64,
+    /// Number of sampled bytes live at the time of the dump.
+    pub bytes: u64,
+    /// Number of sampled objects allocated since profiling started, if
+    /// `opt.prof_accum` is enabled.
+    pub accum_objects: u64,
+    /// Number of sampled bytes allocated since profiling started, if
+    /// `opt.prof_accum` is enabled.
+    pub accum_bytes: u64,
+}
+
+impl ProfileHeader {
+    /// Parses the header at the start of the heap profile `profile`.
+    ///
+    /// Returns `None` if `profile` does not start with a `heap_v2` header.
+    pub fn parse(profile: &[u8]) -> Option<Self> {
+        let mut lines = profile.split(|&b| b == b'\n');
+        let first = std::str::from_utf8(lines.next()?).ok()?;
+        let sample_period =
+            first.strip_prefix("heap_v2/")?.trim().parse().ok()?;
+        // "  t*: <objects>: <bytes> [<accum_objects>: <accum_bytes>]"
+        let totals = std::str::from_utf8(lines.next()?).ok()?;
+        let totals = totals.trim().strip_prefix("t*:")?;
+        let (live, accum) = totals.split_once('[')?;
+        let (objects, bytes) = live.split_once(':')?;
+        let (accum_objects, accum_bytes) =
+            accum.strip_suffix(']')?.split_once(':')?;
+        Some(ProfileHeader {
+            sample_period,
+            objects: objects.trim().parse().ok()?,
+            bytes: bytes.trim().parse().ok()?,
+            accum_objects: accum_objects.trim().parse().ok()?,
+            accum_bytes: accum_bytes.trim().parse().ok()?,
+        })
+    }
+}
+
+/// Heap profile dumped to memory by [`dump_to_vec`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HeapDump {
+    /// Parsed header of the profile.
+    pub header: ProfileHeader,
+    /// Raw profile, in the `heap_v2` format understood by `jeprof`.
+    pub data: Vec<u8>,
+}
+
+/// Dumps a memory profile and returns it.
+///
+/// The profile is dumped to a temporary file that only the current user can
+/// access, read back, and removed.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling;
+/// let dump = profiling::dump_to_vec().unwrap();
+/// println!("{} bytes live in sampled objects", dump.header.bytes);
+/// # }
+/// ```
+pub fn dump_to_vec() -> io::Result<HeapDump> {
+    let file = TempFile::new()?;
+    dump_to_path(&file.0)?;
+    let data = fs::read(&file.0)?;
+    let header = ProfileHeader::parse(&data).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid heap profile header",
+        )
+    })?;
+    Ok(HeapDump { header, data })
+}
+
+/// Private temporary file, removed on drop.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new() -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let mut attempts = 0;
+        loop {
+            let path = dir.join(format!(
+                "tikv-jemalloc-ctl-{}-{}-{}.heap",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed),
+                nanos,
+            ));
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            match options.open(&path) {
+                Ok(_) => return Ok(TempFile(path)),
+                Err(e)
+                    if e.kind() == io::ErrorKind::AlreadyExists
+                        && attempts < 16 =>
+                {
+                    attempts += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProfileHeader;
+
+    #[test]
+    fn parse_header() {
+        let profile = b"heap_v2/524288\n  t*: 28106: 56637512 [0: 0]\n  \
+                        t0: 1: 2 [0: 0]\n";
+        assert_eq!(
+            ProfileHeader::parse(profile),
+            Some(ProfileHeader {
+                sample_period: 524288,
+                objects: 28106,
+                bytes: 56637512,
+                accum_objects: 0,
+                accum_bytes: 0,
+            })
+        );
+        assert_eq!(ProfileHeader::parse(b"heap_v2/524288\n"), None);
+        assert_eq!(
+            ProfileHeader::parse(b"heap/524288\n  t*: 1: 2 [0: 0]"),
+            None
+        );
+    }
+}
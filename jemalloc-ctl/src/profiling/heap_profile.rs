@@ -0,0 +1,519 @@
+//! Parser for heap profiles in jemalloc's `heap_v2` text format.
+//!
+//! A profile written by `prof.dump` looks like:
+//!
+//! ```text
+//! heap_v2/524288
+//!   t*: 28106: 56637512 [0: 0]
+//!   t0: 352: 16777344 [0: 0] main
+//!   t1: 17754: 29341640 [0: 0]
+//! @ 0x55d0c5d6ab12 0x55d0c5d6a9f0 0x55d0c5d0b3c7
+//!   t*: 13: 6688 [0: 0]
+//!   t0: 12: 6496 [0: 0]
+//!   t1: 1: 192 [0: 0]
+//!
+//! MAPPED_LIBRARIES:
+//! 55d0c5c00000-55d0c5e00000 r-xp 00000000 08:01 1234 /usr/bin/app
+//! ```
+
+use super::{HeapDump, ProfileHeader};
+use std::error::Error as StdError;
+use std::fmt;
+use std::str;
+
+/// Sampled object and byte counts.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Counts {
+    /// Number of sampled objects live at the time of the dump.
+    pub objects: u64,
+    /// Number of sampled bytes live at the time of the dump.
+    pub bytes: u64,
+    /// Number of sampled objects allocated since profiling started, if
+    /// `opt.prof_accum` is enabled.
+    pub accum_objects: u64,
+    /// Number of sampled bytes allocated since profiling started, if
+    /// `opt.prof_accum` is enabled.
+    pub accum_bytes: u64,
+}
+
+impl Counts {
+    /// Estimates the real counts from the sampled ones.
+    ///
+    /// An allocation of `size` bytes is sampled with probability
+    /// `1 - exp(-size / sample_period)`, so every sampled allocation stands
+    /// for `1 / (1 - exp(-size / sample_period))` allocations. This is the
+    /// adjustment `jeprof` applies to `heap_v2` profiles.
+    pub fn estimate(&self, sample_period: u64) -> Estimate {
+        let (objects, bytes) = scale(self.objects, self.bytes, sample_period);
+        let (accum_objects, accum_bytes) =
+            scale(self.accum_objects, self.accum_bytes, sample_period);
+        Estimate {
+            objects,
+            bytes,
+            accum_objects,
+            accum_bytes,
+        }
+    }
+}
+
+fn scale(objects: u64, bytes: u64, sample_period: u64) -> (f64, f64) {
+    let (objects, bytes) = (objects as f64, bytes as f64);
+    if objects == 0.0 || sample_period == 0 {
+        return (objects, bytes);
+    }
+    let ratio = bytes / objects / sample_period as f64;
+    let factor = 1.0 / (1.0 - (-ratio).exp());
+    if !factor.is_finite() {
+        // Zero-sized objects: there is nothing to scale.
+        return (objects, bytes);
+    }
+    (objects * factor, bytes * factor)
+}
+
+/// Sampling-rate-adjusted counts, see [`Counts::estimate`].
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Estimate {
+    /// Estimated number of live objects.
+    pub objects: f64,
+    /// Estimated number of live bytes.
+    pub bytes: f64,
+    /// Estimated number of objects allocated since profiling started.
+    pub accum_objects: f64,
+    /// Estimated number of bytes allocated since profiling started.
+    pub accum_bytes: f64,
+}
+
+impl std::ops::AddAssign for Estimate {
+    fn add_assign(&mut self, other: Self) {
+        self.objects += other.objects;
+        self.bytes += other.bytes;
+        self.accum_objects += other.accum_objects;
+        self.accum_bytes += other.accum_bytes;
+    }
+}
+
+/// Per-thread summary from the header of a profile.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Thread {
+    /// Thread id assigned by jemalloc (not the OS thread id).
+    pub id: u64,
+    /// Name set through `thread.prof.name`, if any.
+    pub name: Option<String>,
+    /// Counts of all samples allocated by the thread.
+    pub counts: Counts,
+}
+
+/// Counts of the samples of a stack allocated by one thread.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ThreadCounts {
+    /// Thread id assigned by jemalloc, see [`Thread::id`].
+    pub thread: u64,
+    /// Counts of the samples allocated by the thread.
+    pub counts: Counts,
+}
+
+/// Allocation stack and its samples.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Stack {
+    /// Return addresses, innermost frame first.
+    pub addresses: Vec<u64>,
+    /// Counts of the samples with this stack across all threads.
+    pub counts: Counts,
+    /// Counts of the samples with this stack, per thread.
+    pub threads: Vec<ThreadCounts>,
+}
+
+impl Stack {
+    /// Returns the addresses of the instructions of the stack, innermost
+    /// frame first.
+    ///
+    /// All addresses but the innermost one are return addresses, which point
+    /// after the call instruction; they are moved back by one byte like
+    /// `jeprof` does so that they resolve to the line of the call.
+    pub fn pcs(&self) -> impl Iterator<Item = u64> + '_ {
+        pcs(&self.addresses)
+    }
+}
+
+/// See [`Stack::pcs`].
+pub(super) fn pcs(addresses: &[u64]) -> impl Iterator<Item = u64> + '_ {
+    addresses.iter().enumerate().map(|(i, &address)| {
+        if i == 0 {
+            address
+        } else {
+            address.saturating_sub(1)
+        }
+    })
+}
+
+/// Memory mapping of the profiled process, from `/proc/<pid>/maps`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Mapping {
+    /// Start address of the mapping.
+    pub start: u64,
+    /// End address (exclusive) of the mapping.
+    pub end: u64,
+    /// Permissions, e.g. `r-xp`.
+    pub permissions: String,
+    /// Offset of the mapping in the mapped file.
+    pub offset: u64,
+    /// Mapped file or pseudo-path such as `[stack]`, if any.
+    pub path: Option<String>,
+}
+
+impl Mapping {
+    /// Returns `true` if `address` lies within the mapping.
+    pub fn contains(&self, address: u64) -> bool {
+        self.start <= address && address < self.end
+    }
+}
+
+/// Heap profile in the `heap_v2` format.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling::{self, HeapProfile};
+/// let dump = profiling::dump_to_vec().unwrap();
+/// let profile = HeapProfile::parse(&dump.data).unwrap();
+/// for stack in &profile.stacks {
+///     let estimate = profile.estimate(&stack.counts);
+///     println!("{:x?}: ~{:.0} bytes", stack.addresses, estimate.bytes);
+/// }
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HeapProfile {
+    /// Sample period and sampled totals.
+    pub header: ProfileHeader,
+    /// Per-thread summaries.
+    pub threads: Vec<Thread>,
+    /// Sampled allocation stacks.
+    pub stacks: Vec<Stack>,
+    /// Memory mappings of the profiled process.
+    pub mappings: Vec<Mapping>,
+}
+
+impl HeapProfile {
+    /// Parses a heap profile.
+    pub fn parse(profile: &[u8]) -> Result<Self, ParseError> {
+        Parser::new(profile).parse()
+    }
+
+    /// Estimates the real counts from `counts` sampled in this profile.
+    pub fn estimate(&self, counts: &Counts) -> Estimate {
+        counts.estimate(self.header.sample_period)
+    }
+
+    /// Estimates the real totals by adding up the estimates of every stack.
+    ///
+    /// Adjusting the header totals directly would be wrong since the
+    /// adjustment depends on the average size of the sampled objects.
+    pub fn estimated_totals(&self) -> Estimate {
+        let mut total = Estimate::default();
+        for stack in &self.stacks {
+            total += self.estimate(&stack.counts);
+        }
+        total
+    }
+
+    /// Returns the mapping containing `address`, if any.
+    pub fn mapping(&self, address: u64) -> Option<&Mapping> {
+        self.mappings.iter().find(|m| m.contains(address))
+    }
+}
+
+impl HeapDump {
+    /// Parses the dumped profile.
+    pub fn profile(&self) -> Result<HeapProfile, ParseError> {
+        HeapProfile::parse(&self.data)
+    }
+}
+
+/// Error returned by [`HeapProfile::parse`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseError {
+    line: usize,
+    reason: &'static str,
+}
+
+impl ParseError {
+    /// Line (starting at 1) at which the error occurred.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid heap profile at line {}: {}",
+            self.line, self.reason
+        )
+    }
+}
+
+impl StdError for ParseError {}
+
+type Lines<'a> =
+    std::iter::Enumerate<std::slice::Split<'a, u8, fn(&u8) -> bool>>;
+
+struct Parser<'a> {
+    lines: Lines<'a>,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(profile: &'a [u8]) -> Self {
+        let is_newline: fn(&u8) -> bool = |&b| b == b'\n';
+        Parser {
+            lines: profile.split(is_newline).enumerate(),
+            line: 0,
+        }
+    }
+
+    fn error(&self, reason: &'static str) -> ParseError {
+        ParseError {
+            line: self.line,
+            reason,
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<&'a str>, ParseError> {
+        match self.lines.next() {
+            None => Ok(None),
+            Some((i, line)) => {
+                self.line = i + 1;
+                let line = str::from_utf8(line)
+                    .map_err(|_| self.error("not valid UTF-8"))?;
+                Ok(Some(line.trim_end_matches('\r')))
+            }
+        }
+    }
+
+    fn parse(mut self) -> Result<HeapProfile, ParseError> {
+        let first = self.next_line()?.unwrap_or("");
+        let sample_period = first
+            .strip_prefix("heap_v2/")
+            .and_then(|p| p.trim().parse().ok())
+            .ok_or_else(|| self.error("expected `heap_v2/<sample period>`"))?;
+        let totals = match self.next_line()?.map(parse_thread_line) {
+            Some(Some((None, counts, _))) => counts,
+            _ => return Err(self.error("expected `t*:` totals")),
+        };
+
+        let mut profile = HeapProfile {
+            header: ProfileHeader {
+                sample_period,
+                objects: totals.objects,
+                bytes: totals.bytes,
+                accum_objects: totals.accum_objects,
+                accum_bytes: totals.accum_bytes,
+            },
+            threads: Vec::new(),
+            stacks: Vec::new(),
+            mappings: Vec::new(),
+        };
+
+        while let Some(line) = self.next_line()? {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed == "MAPPED_LIBRARIES:" {
+                while let Some(line) = self.next_line()? {
+                    // Lines jeprof cannot use either, e.g. from platforms
+                    // without `/proc`, are skipped.
+                    if let Some(mapping) = parse_mapping(line) {
+                        profile.mappings.push(mapping);
+                    }
+                }
+                break;
+            }
+            if let Some(addresses) = trimmed.strip_prefix('@') {
+                let addresses = addresses
+                    .split_whitespace()
+                    .map(parse_hex)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| self.error("invalid stack address"))?;
+                let counts = match self.next_line()?.map(parse_thread_line) {
+                    Some(Some((None, counts, _))) => counts,
+                    _ => return Err(self.error("expected `t*:` stack counts")),
+                };
+                profile.stacks.push(Stack {
+                    addresses,
+                    counts,
+                    threads: Vec::new(),
+                });
+                continue;
+            }
+            let (thread, counts, name) = match parse_thread_line(line) {
+                Some((Some(thread), counts, name)) => (thread, counts, name),
+                _ => return Err(self.error("unexpected line")),
+            };
+            match profile.stacks.last_mut() {
+                Some(stack) => {
+                    stack.threads.push(ThreadCounts { thread, counts })
+                }
+                None => profile.threads.push(Thread {
+                    id: thread,
+                    name: name.map(str::to_owned),
+                    counts,
+                }),
+            }
+        }
+        Ok(profile)
+    }
+}
+
+/// Parses `t<id>: <objects>: <bytes> [<accum_objects>: <accum_bytes>] <name>`,
+/// with `None` as the id of `t*` lines.
+fn parse_thread_line(
+    line: &str,
+) -> Option<(Option<u64>, Counts, Option<&str>)> {
+    let (thread, rest) =
+        line.trim_start().strip_prefix('t')?.split_once(':')?;
+    let thread = match thread {
+        "*" => None,
+        id => Some(id.parse().ok()?),
+    };
+    let (live, rest) = rest.split_once('[')?;
+    let (accum, name) = rest.split_once(']')?;
+    let (objects, bytes) = live.split_once(':')?;
+    let (accum_objects, accum_bytes) = accum.split_once(':')?;
+    let counts = Counts {
+        objects: objects.trim().parse().ok()?,
+        bytes: bytes.trim().parse().ok()?,
+        accum_objects: accum_objects.trim().parse().ok()?,
+        accum_bytes: accum_bytes.trim().parse().ok()?,
+    };
+    let name = Some(name.trim()).filter(|n| !n.is_empty());
+    Some((thread, counts, name))
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    u64::from_str_radix(s, 16).ok()
+}
+
+/// Parses `<start>-<end> <perms> <offset> <dev> <inode> [<path>]`.
+fn parse_mapping(line: &str) -> Option<Mapping> {
+    let mut fields = line.splitn(6, char::is_whitespace);
+    let (start, end) = fields.next()?.split_once('-')?;
+    let permissions = fields.next()?.to_owned();
+    let offset = parse_hex(fields.next()?)?;
+    let _dev = fields.next()?;
+    let _inode = fields.next()?;
+    let path = fields
+        .next()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_owned);
+    Some(Mapping {
+        start: parse_hex(start)?,
+        end: parse_hex(end)?,
+        permissions,
+        offset,
+        path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = "heap_v2/524288
+  t*: 14: 7216 [0: 0]
+  t0: 13: 6688 [0: 0] main
+  t1: 1: 528 [0: 0]
+@ 0x55d0c5d6ab12 0x55d0c5d6a9f0
+  t*: 13: 6688 [0: 0]
+  t0: 13: 6688 [0: 0]
+@ 0x55d0c5d6ab12 0x7f00aa001000
+  t*: 1: 528 [0: 0]
+  t1: 1: 528 [0: 0]
+
+MAPPED_LIBRARIES:
+55d0c5c00000-55d0c5e00000 r-xp 00001000 08:01 1234                       /usr/bin/app
+7ffd1b3e0000-7ffd1b401000 rw-p 00000000 00:00 0                          [stack]
+7f00aa000000-7f00aa100000 rw-p 00000000 00:00 0
+";
+
+    #[test]
+    fn parse_profile() {
+        let profile = HeapProfile::parse(PROFILE.as_bytes()).unwrap();
+        assert_eq!(profile.header.sample_period, 524288);
+        assert_eq!(profile.header.objects, 14);
+        assert_eq!(profile.header.bytes, 7216);
+
+        assert_eq!(profile.threads.len(), 2);
+        assert_eq!(profile.threads[0].id, 0);
+        assert_eq!(profile.threads[0].name.as_deref(), Some("main"));
+        assert_eq!(profile.threads[1].name, None);
+        assert_eq!(profile.threads[1].counts.bytes, 528);
+
+        assert_eq!(profile.stacks.len(), 2);
+        let stack = &profile.stacks[0];
+        assert_eq!(stack.addresses, [0x55d0c5d6ab12, 0x55d0c5d6a9f0]);
+        assert_eq!(stack.counts.objects, 13);
+        assert_eq!(stack.threads.len(), 1);
+        assert_eq!(stack.threads[0].thread, 0);
+        assert_eq!(profile.stacks[1].threads[0].thread, 1);
+
+        assert_eq!(profile.mappings.len(), 3);
+        let app = profile.mapping(0x55d0c5d6ab12).unwrap();
+        assert_eq!(app.permissions, "r-xp");
+        assert_eq!(app.offset, 0x1000);
+        assert_eq!(app.path.as_deref(), Some("/usr/bin/app"));
+        assert_eq!(profile.mappings[1].path.as_deref(), Some("[stack]"));
+        assert_eq!(profile.mapping(0x7f00aa001000).unwrap().path, None);
+        assert!(profile.mapping(1).is_none());
+    }
+
+    #[test]
+    fn estimates() {
+        let profile = HeapProfile::parse(PROFILE.as_bytes()).unwrap();
+        // Small objects are rarely sampled, so each sample stands for many.
+        let small = profile.estimate(&profile.stacks[1].counts);
+        let factor = 1.0 / (1.0 - (-528.0f64 / 524288.0).exp());
+        assert!((small.bytes - 528.0 * factor).abs() < 1e-6);
+        assert!(small.objects > 900.0);
+        assert_eq!(small.accum_objects, 0.0);
+
+        // Objects much larger than the sample period are always sampled.
+        let large = Counts {
+            objects: 2,
+            bytes: 2 << 30,
+            ..Counts::default()
+        };
+        assert_eq!(large.estimate(524288).objects, 2.0);
+
+        let totals = profile.estimated_totals();
+        let sum =
+            profile.estimate(&profile.stacks[0].counts).bytes + small.bytes;
+        assert!((totals.bytes - sum).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = HeapProfile::parse(b"heap/524288\n").unwrap_err();
+        assert_eq!(err.line(), 1);
+        let err =
+            HeapProfile::parse(b"heap_v2/1\n  t*: 1: 2 [0: 0]\n@ 0xzz\n")
+                .unwrap_err();
+        assert_eq!(err.line(), 3);
+        let err = HeapProfile::parse(
+            b"heap_v2/1\n  t*: 1: 2 [0: 0]\n@ 0x1\n  t0: 1",
+        )
+        .unwrap_err();
+        assert_eq!(err.line(), 4);
+        assert!(
+            HeapProfile::parse(b"heap_v2/1\r\n  t*: 1: 2 [0: 0]\r\n").is_ok()
+        );
+    }
+}
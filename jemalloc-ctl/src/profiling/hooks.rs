@@ -0,0 +1,382 @@
+//! Profiling hooks (`experimental.hooks.prof_backtrace` and
+//! `experimental.hooks.prof_dump`).
+
+use crate::error::Result;
+use crate::raw;
+use crate::std::ffi::CStr;
+use crate::std::sync::atomic::{AtomicPtr, Ordering};
+use crate::std::{mem, ptr, slice};
+use libc::{c_char, c_uint, c_void};
+
+/// Raw backtrace hook of `jemalloc` (`prof_backtrace_hook_t`).
+///
+/// It is called with a buffer of `max_len` return addresses to fill, and
+/// stores the number of addresses it wrote in `*len`.
+pub type RawBacktraceHook = unsafe extern "C" fn(
+    vec: *mut *mut c_void,
+    len: *mut c_uint,
+    max_len: c_uint,
+);
+
+/// Raw dump hook of `jemalloc` (`prof_dump_hook_t`).
+///
+/// It is called with the name of the file of every heap profile dumped.
+pub type RawDumpHook = unsafe extern "C" fn(filename: *const c_char);
+
+/// Backtrace hook, see [`set_backtrace_hook`].
+///
+/// It fills `frames`, initially null, with the return addresses of the
+/// current call stack, innermost first, and returns how many it wrote.
+pub type BacktraceHook = fn(frames: &mut [*mut c_void]) -> usize;
+
+/// Dump hook, see [`set_dump_hook`].
+///
+/// It is called with the name of the file of every heap profile dumped.
+pub type DumpHook = fn(filename: &CStr);
+
+const BACKTRACE_KEY: &[u8] = b"experimental.hooks.prof_backtrace\0";
+const DUMP_KEY: &[u8] = b"experimental.hooks.prof_dump\0";
+
+/// Hook called by [`backtrace_trampoline`].
+static BACKTRACE_HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+/// Backtrace hook replaced by the first call to [`set_backtrace_hook`].
+static PREVIOUS_BACKTRACE_HOOK: AtomicPtr<()> =
+    AtomicPtr::new(ptr::null_mut());
+/// Hook called by [`dump_trampoline`].
+static DUMP_HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Aborts the process if dropped, i.e. if a hook panics: unwinding into
+/// `jemalloc` is undefined behavior.
+struct AbortOnUnwind;
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        unsafe { libc::abort() }
+    }
+}
+
+unsafe extern "C" fn backtrace_trampoline(
+    vec: *mut *mut c_void,
+    len: *mut c_uint,
+    max_len: c_uint,
+) {
+    let guard = AbortOnUnwind;
+    let hook = BACKTRACE_HOOK.load(Ordering::Acquire);
+    *len = if hook.is_null() {
+        0
+    } else {
+        let hook: BacktraceHook = mem::transmute(hook);
+        // jemalloc does not initialize the buffer, but safe hooks may read it:
+        ptr::write_bytes(vec, 0, max_len as usize);
+        let frames = slice::from_raw_parts_mut(vec, max_len as usize);
+        hook(frames).min(max_len as usize) as c_uint
+    };
+    mem::forget(guard);
+}
+
+unsafe extern "C" fn dump_trampoline(filename: *const c_char) {
+    let guard = AbortOnUnwind;
+    let hook = DUMP_HOOK.load(Ordering::Acquire);
+    if !hook.is_null() {
+        let hook: DumpHook = mem::transmute(hook);
+        hook(CStr::from_ptr(filename));
+    }
+    mem::forget(guard);
+}
+
+/// Returns the backtrace hook used by the profiler
+/// (`experimental.hooks.prof_backtrace`), if any.
+///
+/// It is `jemalloc`'s own unwinder unless it was replaced. There is none
+/// unless profiling is enabled (`opt.prof`).
+pub fn raw_backtrace_hook() -> Result<Option<RawBacktraceHook>> {
+    unsafe { raw::read(BACKTRACE_KEY) }
+}
+
+/// Replaces the backtrace hook used by the profiler, returning the previous
+/// one (`experimental.hooks.prof_backtrace`).
+///
+/// Profiling must be enabled (`opt.prof`).
+///
+/// # Safety
+///
+/// `hook` must write at most `max_len` addresses and be safe to call from
+/// any thread while allocating, with `jemalloc`'s reentrancy guard set:
+/// allocations made from the hook are not sampled.
+pub unsafe fn set_raw_backtrace_hook(
+    hook: RawBacktraceHook,
+) -> Result<Option<RawBacktraceHook>> {
+    raw::update(BACKTRACE_KEY, Some(hook))
+}
+
+/// Returns the hook called after every heap profile dump
+/// (`experimental.hooks.prof_dump`), if any.
+pub fn raw_dump_hook() -> Result<Option<RawDumpHook>> {
+    unsafe { raw::read(DUMP_KEY) }
+}
+
+/// Replaces the hook called after every heap profile dump, returning the
+/// previous one (`experimental.hooks.prof_dump`).
+///
+/// Profiling must be enabled (`opt.prof`).
+///
+/// # Safety
+///
+/// `hook` must be safe to call from any thread with the dump lock of the
+/// profiler held: it must not dump a heap profile itself.
+pub unsafe fn set_raw_dump_hook(
+    hook: Option<RawDumpHook>,
+) -> Result<Option<RawDumpHook>> {
+    raw::update(DUMP_KEY, hook)
+}
+
+/// Makes the profiler unwind the stacks of the sampled allocations with
+/// `hook` instead of `libgcc` or `libunwind`.
+///
+/// The hook is called on the allocating thread with allocation sampling
+/// disabled, so it may allocate, but it should be fast. The process aborts if
+/// it panics. The default unwinder can be restored with
+/// [`reset_backtrace_hook`].
+///
+/// Profiling must be enabled (`opt.prof`). This key is experimental in
+/// `jemalloc`.
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling;
+///
+/// // Attribute every sampled allocation to the same fake stack:
+/// fn fake_stack(frames: &mut [*mut libc::c_void]) -> usize {
+///     frames[0] = 0x1000 as *mut libc::c_void;
+///     1
+/// }
+///
+/// if profiling::prof::read().unwrap() {
+///     profiling::set_backtrace_hook(fake_stack).unwrap();
+///     // ...
+///     profiling::reset_backtrace_hook().unwrap();
+/// }
+/// # }
+/// ```
+pub fn set_backtrace_hook(hook: BacktraceHook) -> Result<()> {
+    BACKTRACE_HOOK.store(hook as *mut (), Ordering::Release);
+    let trampoline: RawBacktraceHook = backtrace_trampoline;
+    let previous = unsafe { set_raw_backtrace_hook(trampoline)? };
+    let previous = previous.map_or(ptr::null_mut(), |hook| hook as *mut ());
+    if previous != trampoline as *mut () {
+        let _ = PREVIOUS_BACKTRACE_HOOK.compare_exchange(
+            ptr::null_mut(),
+            previous,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+    Ok(())
+}
+
+/// Restores the backtrace hook that was used before the first call to
+/// [`set_backtrace_hook`], normally `jemalloc`'s own unwinder.
+///
+/// It does nothing if [`set_backtrace_hook`] was never called.
+pub fn reset_backtrace_hook() -> Result<()> {
+    let previous = PREVIOUS_BACKTRACE_HOOK.load(Ordering::Acquire);
+    if !previous.is_null() {
+        unsafe {
+            let previous: RawBacktraceHook = mem::transmute(previous);
+            set_raw_backtrace_hook(previous)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the hook called with the file name of every heap profile dumped,
+/// or removes it if `hook` is `None`.
+///
+/// The hook is called from the dumping thread with the dump lock of the
+/// profiler held, so a heap profile dump from the hook deadlocks. The process
+/// aborts if it panics.
+///
+/// Profiling must be enabled (`opt.prof`). This key is experimental in
+/// `jemalloc`.
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use std::ffi::CStr;
+/// use tikv_jemalloc_ctl::profiling;
+///
+/// fn on_dump(filename: &CStr) {
+///     eprintln!("heap profile dumped to {:?}", filename);
+/// }
+///
+/// if profiling::prof::read().unwrap() {
+///     profiling::set_dump_hook(Some(on_dump)).unwrap();
+/// }
+/// # }
+/// ```
+pub fn set_dump_hook(hook: Option<DumpHook>) -> Result<()> {
+    match hook {
+        Some(hook) => {
+            DUMP_HOOK.store(hook as *mut (), Ordering::Release);
+            unsafe { set_raw_dump_hook(Some(dump_trampoline))? };
+        }
+        None => {
+            unsafe { set_raw_dump_hook(None)? };
+            DUMP_HOOK.store(ptr::null_mut(), Ordering::Release);
+        }
+    }
+    Ok(())
+}
+
+/// Largest distance between two consecutive frame pointers that
+/// [`frame_pointer_backtrace`] follows.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// Fills `frames` with the return addresses of the current call stack by
+/// following the chain of frame pointers, and returns how many it wrote.
+///
+/// This is much faster than unwinding with the DWARF information, and it
+/// does not need any. It stops at the first frame pointer that is null,
+/// misaligned, or that does not point further up the stack.
+///
+/// # Safety
+///
+/// Every function of the current call stack, including this one, must keep
+/// a frame pointer. Otherwise the walk may read arbitrary memory. This
+/// requires:
+///
+/// * building the program with `RUSTFLAGS="-C force-frame-pointers=yes"`,
+///   since `rustc` omits them by default, even in debug builds;
+/// * enabling the `frame_pointers` feature, which compiles jemalloc with
+///   `-fno-omit-frame-pointer`, when this is called from a backtrace hook;
+/// * linking only other libraries built with `-fno-omit-frame-pointer`.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+#[inline(never)]
+pub unsafe fn frame_pointer_backtrace(frames: &mut [*mut c_void]) -> usize {
+    let fp: usize;
+    #[cfg(target_arch = "x86_64")]
+    crate::std::arch::asm!(
+        "mov {}, rbp",
+        out(reg) fp,
+        options(nomem, nostack, preserves_flags)
+    );
+    #[cfg(target_arch = "aarch64")]
+    crate::std::arch::asm!(
+        "mov {}, x29",
+        out(reg) fp,
+        options(nomem, nostack, preserves_flags)
+    );
+    walk_frame_pointers(fp, frames)
+}
+
+/// Walks the chain of frame pointers starting at `fp`, see
+/// [`frame_pointer_backtrace`].
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+unsafe fn walk_frame_pointers(
+    mut fp: usize,
+    frames: &mut [*mut c_void],
+) -> usize {
+    // Each frame starts with the frame pointer of the caller, followed by
+    // the return address into the caller.
+    let mut len = 0;
+    while len < frames.len()
+        && fp != 0
+        && fp & (mem::align_of::<usize>() - 1) == 0
+    {
+        let frame = fp as *const usize;
+        let return_address = *frame.add(1);
+        if return_address == 0 {
+            break;
+        }
+        frames[len] = return_address as *mut c_void;
+        len += 1;
+        let next = *frame;
+        if next <= fp || next - fp > MAX_FRAME_SIZE {
+            break;
+        }
+        fp = next;
+    }
+    len
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn frame_pointer_hook(frames: &mut [*mut c_void]) -> usize {
+    unsafe { frame_pointer_backtrace(frames) }
+}
+
+/// Makes the profiler unwind the stacks of the sampled allocations with
+/// [`frame_pointer_backtrace`], see [`set_backtrace_hook`].
+///
+/// # Safety
+///
+/// The whole program, including jemalloc, must keep frame pointers: enable
+/// the `frame_pointers` feature and build with
+/// `RUSTFLAGS="-C force-frame-pointers=yes"`, see
+/// [`frame_pointer_backtrace`].
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub unsafe fn set_frame_pointer_backtrace_hook() -> Result<()> {
+    set_backtrace_hook(frame_pointer_hook)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hooks_without_profiling() {
+        if crate::macros::prof_enabled() {
+            return;
+        }
+        // jemalloc only installs its unwinder when profiling is enabled.
+        assert!(raw_backtrace_hook().unwrap().is_none());
+        assert!(raw_dump_hook().unwrap().is_none());
+        fn no_stack(_: &mut [*mut c_void]) -> usize {
+            0
+        }
+        assert!(set_backtrace_hook(no_stack).is_err());
+        assert!(raw_backtrace_hook().unwrap().is_none());
+        reset_backtrace_hook().unwrap();
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    #[test]
+    fn walk_frames() {
+        // Three frames, each holding the caller's frame pointer and the
+        // return address into the caller; the outermost one ends the chain.
+        let mut stack = [0_usize; 6];
+        let base = stack.as_ptr() as usize;
+        let word = mem::size_of::<usize>();
+        stack[0] = base + 2 * word;
+        stack[1] = 0x100;
+        stack[2] = base + 4 * word;
+        stack[3] = 0x200;
+        stack[5] = 0x300;
+
+        let mut frames = [ptr::null_mut(); 4];
+        let walk = |stack: &mut [usize], offset: usize, frames: &mut [_]| unsafe {
+            walk_frame_pointers(stack.as_mut_ptr() as usize + offset, frames)
+        };
+        assert_eq!(walk(&mut stack, 0, &mut frames), 3);
+        assert_eq!(frames[0] as usize, 0x100);
+        assert_eq!(frames[1] as usize, 0x200);
+        assert_eq!(frames[2] as usize, 0x300);
+
+        assert_eq!(walk(&mut stack, 0, &mut frames[..2]), 2);
+        assert_eq!(walk(&mut stack, 1, &mut frames), 0);
+        assert_eq!(unsafe { walk_frame_pointers(0, &mut frames) }, 0);
+
+        // A frame pointer that goes down the stack ends the walk.
+        stack[2] = base;
+        assert_eq!(walk(&mut stack, 0, &mut frames), 2);
+    }
+}
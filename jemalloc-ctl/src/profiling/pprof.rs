@@ -0,0 +1,493 @@
+//! Conversion of heap profiles to pprof's `profile.proto` format.
+//!
+//! The format is described in
+//! <https://github.com/google/pprof/blob/main/proto/profile.proto>. It is
+//! small enough that the encoder below is hand-written rather than generated.
+
+use super::{
+    dump_to_vec, symbolize, trim_allocator_frames, HeapProfile, Mapping,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Options for [`HeapProfile::to_pprof`].
+///
+/// All options default to `false`.
+#[derive(Copy, Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct PprofOptions {
+    /// If set, function names, files and lines are resolved with the debug
+    /// information of the current process.
+    ///
+    /// This is only meaningful if the profile was dumped by the current
+    /// process; otherwise leave it unset and let the viewer symbolize the
+    /// profile against the binaries of the mapping table.
+    pub symbolize: bool,
+}
+
+impl HeapProfile {
+    /// Converts the profile to a gzip-compressed `profile.proto`.
+    ///
+    /// The profile has the `inuse_objects/count` and `inuse_space/bytes`
+    /// sample types, with values adjusted for the sampling rate as described
+    /// in [`Counts::estimate`](super::Counts::estimate).
+    pub fn to_pprof(&self, options: &PprofOptions) -> io::Result<Vec<u8>> {
+        let mut builder = Builder::new(&self.mappings, options);
+        for stack in &self.stacks {
+            let estimate = self.estimate(&stack.counts);
+            builder.sample(
+                stack.pcs(),
+                estimate.objects.round() as i64,
+                estimate.bytes.round() as i64,
+            );
+        }
+        builder.finish(self.header.sample_period)
+    }
+}
+
+/// Dumps a memory profile and converts it to a gzip-compressed
+/// `profile.proto`, e.g. to serve `/debug/pprof/heap`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling::{self, PprofOptions};
+/// let mut options = PprofOptions::default();
+/// options.symbolize = true;
+/// let pprof = profiling::dump_pprof(&options).unwrap();
+/// std::fs::write("heap.pb.gz", pprof).unwrap();
+/// # }
+/// ```
+pub fn dump_pprof(options: &PprofOptions) -> io::Result<Vec<u8>> {
+    let profile = dump_to_vec()?
+        .profile()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    profile.to_pprof(options)
+}
+
+/// Builder of a gzip-compressed `profile.proto` with the `inuse_objects` and
+/// `inuse_space` sample types.
+pub(super) struct Builder<'a> {
+    mappings: &'a [Mapping],
+    symbolize: bool,
+    samples: Vec<u8>,
+    strings: Vec<String>,
+    string_ids: HashMap<String, i64>,
+    locations: Vec<u8>,
+    location_ids: HashMap<u64, u64>,
+    functions: Vec<u8>,
+    function_ids: HashMap<(i64, i64), u64>,
+    function_names: HashMap<u64, String>,
+    symbolized: bool,
+}
+
+impl<'a> Builder<'a> {
+    pub(super) fn new(
+        mappings: &'a [Mapping],
+        options: &PprofOptions,
+    ) -> Self {
+        let mut builder = Builder {
+            mappings,
+            symbolize: options.symbolize,
+            samples: Vec::new(),
+            strings: Vec::new(),
+            string_ids: HashMap::new(),
+            locations: Vec::new(),
+            location_ids: HashMap::new(),
+            functions: Vec::new(),
+            function_ids: HashMap::new(),
+            function_names: HashMap::new(),
+            symbolized: false,
+        };
+        // The first string of the table must be empty.
+        builder.string("");
+        builder
+    }
+
+    fn string(&mut self, s: &str) -> i64 {
+        if let Some(&id) = self.string_ids.get(s) {
+            return id;
+        }
+        let id = self.strings.len() as i64;
+        self.strings.push(s.to_owned());
+        self.string_ids.insert(s.to_owned(), id);
+        id
+    }
+
+    fn value_type(&mut self, ty: &str, unit: &str) -> Vec<u8> {
+        let mut m = Message::default();
+        m.int64(1, self.string(ty));
+        m.int64(2, self.string(unit));
+        m.0
+    }
+
+    /// Adds a sample with the stack `pcs`, innermost frame first.
+    ///
+    /// If symbolizing, the frames of the allocator are left out, see
+    /// [`trim_allocator_frames`].
+    pub(super) fn sample(
+        &mut self,
+        pcs: impl Iterator<Item = u64>,
+        objects: i64,
+        bytes: i64,
+    ) {
+        let mut pcs: Vec<u64> = pcs.collect();
+        if self.symbolize {
+            // A location holds all the frames inlined at its address, so
+            // whole addresses are trimmed, after the function they are in.
+            for &pc in &pcs {
+                self.function_names.entry(pc).or_insert_with(|| {
+                    symbolize::resolve(pc)
+                        .pop()
+                        .map_or_else(|| format!("{:#x}", pc), |f| f.name)
+                });
+            }
+            let mut frames: Vec<(u64, &str)> = pcs
+                .iter()
+                .map(|pc| (*pc, self.function_names[pc].as_str()))
+                .collect();
+            trim_allocator_frames(&mut frames, |frame| frame.1);
+            pcs = frames.into_iter().map(|frame| frame.0).collect();
+        }
+        let ids: Vec<u64> =
+            pcs.into_iter().map(|pc| self.location(pc)).collect();
+        let mut sample = Message::default();
+        sample.packed_uint64(1, &ids);
+        sample.packed_int64(2, &[objects, bytes]);
+        let mut m = Message::default();
+        m.bytes(2, &sample.0);
+        self.samples.extend_from_slice(&m.0);
+    }
+
+    pub(super) fn finish(mut self, sample_period: u64) -> io::Result<Vec<u8>> {
+        let mut out = Message::default();
+
+        let objects = self.value_type("inuse_objects", "count");
+        let space = self.value_type("inuse_space", "bytes");
+        let period_type = self.value_type("space", "bytes");
+        out.bytes(1, &objects);
+        out.bytes(1, &space);
+        out.0.extend_from_slice(&self.samples);
+
+        for (i, mapping) in self.mappings.iter().enumerate() {
+            let filename = self.string(mapping.path.as_deref().unwrap_or(""));
+            let mut m = Message::default();
+            m.uint64(1, i as u64 + 1);
+            m.uint64(2, mapping.start);
+            m.uint64(3, mapping.end);
+            m.uint64(4, mapping.offset);
+            m.int64(5, filename);
+            m.bool(7, self.symbolized);
+            m.bool(8, self.symbolized);
+            m.bool(9, self.symbolized);
+            m.bool(10, self.symbolized);
+            out.bytes(3, &m.0);
+        }
+
+        out.0.extend_from_slice(&self.locations);
+        out.0.extend_from_slice(&self.functions);
+        for s in &self.strings {
+            out.bytes(6, s.as_bytes());
+        }
+
+        let time_nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or(0);
+        out.int64(9, time_nanos);
+        out.bytes(11, &period_type);
+        out.int64(12, sample_period as i64);
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&out.0)?;
+        gz.finish()
+    }
+
+    /// Returns the id of the location of `address`.
+    fn location(&mut self, address: u64) -> u64 {
+        if let Some(&id) = self.location_ids.get(&address) {
+            return id;
+        }
+        let id = self.location_ids.len() as u64 + 1;
+        self.location_ids.insert(address, id);
+
+        let mut location = Message::default();
+        location.uint64(1, id);
+        if let Some(i) = self.mappings.iter().position(|m| m.contains(address))
+        {
+            location.uint64(2, i as u64 + 1);
+        }
+        location.uint64(3, address);
+        if self.symbolize {
+            for line in self.symbolize(address) {
+                location.bytes(4, &line);
+            }
+        }
+        let mut m = Message::default();
+        m.bytes(4, &location.0);
+        self.locations.extend_from_slice(&m.0);
+        id
+    }
+
+    /// Returns the `Line` messages of `pc`, innermost inlined frame first.
+    fn symbolize(&mut self, pc: u64) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        for frame in symbolize::resolve(pc) {
+            let function = self.function(
+                &frame.name,
+                &frame.system_name,
+                frame.filename.as_deref(),
+            );
+            let mut line = Message::default();
+            line.uint64(1, function);
+            line.int64(2, frame.line.unwrap_or(0) as i64);
+            lines.push(line.0);
+            self.symbolized = true;
+        }
+        lines
+    }
+
+    fn function(
+        &mut self,
+        name: &str,
+        system_name: &str,
+        filename: Option<&str>,
+    ) -> u64 {
+        let name = self.string(name);
+        let system_name = self.string(system_name);
+        let filename = self.string(filename.unwrap_or(""));
+        if let Some(&id) = self.function_ids.get(&(name, filename)) {
+            return id;
+        }
+        let id = self.function_ids.len() as u64 + 1;
+        self.function_ids.insert((name, filename), id);
+        let mut function = Message::default();
+        function.uint64(1, id);
+        function.int64(2, name);
+        function.int64(3, system_name);
+        function.int64(4, filename);
+        let mut m = Message::default();
+        m.bytes(5, &function.0);
+        self.functions.extend_from_slice(&m.0);
+        id
+    }
+}
+
+/// Protobuf message encoder. Fields with default values are omitted, as
+/// protobuf encoders do.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(u64::from(field) << 3 | u64::from(wire_type));
+    }
+
+    fn uint64(&mut self, field: u32, v: u64) {
+        if v != 0 {
+            self.key(field, 0);
+            self.varint(v);
+        }
+    }
+
+    fn int64(&mut self, field: u32, v: i64) {
+        self.uint64(field, v as u64);
+    }
+
+    fn bool(&mut self, field: u32, v: bool) {
+        self.uint64(field, v as u64);
+    }
+
+    fn bytes(&mut self, field: u32, v: &[u8]) {
+        self.key(field, 2);
+        self.varint(v.len() as u64);
+        self.0.extend_from_slice(v);
+    }
+
+    fn packed_uint64(&mut self, field: u32, vs: &[u64]) {
+        let mut packed = Message::default();
+        for &v in vs {
+            packed.varint(v);
+        }
+        self.bytes(field, &packed.0);
+    }
+
+    fn packed_int64(&mut self, field: u32, vs: &[i64]) {
+        let vs: Vec<u64> = vs.iter().map(|&v| v as u64).collect();
+        self.packed_uint64(field, &vs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[derive(Clone, PartialEq, Debug)]
+    enum Value {
+        Varint(u64),
+        Bytes(Vec<u8>),
+    }
+
+    impl Value {
+        fn varint(&self) -> u64 {
+            match *self {
+                Value::Varint(v) => v,
+                Value::Bytes(_) => panic!("expected a varint"),
+            }
+        }
+
+        fn bytes(&self) -> &[u8] {
+            match self {
+                Value::Bytes(v) => v,
+                Value::Varint(_) => panic!("expected bytes"),
+            }
+        }
+    }
+
+    fn varint(buf: &mut &[u8]) -> u64 {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let b = buf[0];
+            *buf = &buf[1..];
+            v |= u64::from(b & 0x7f) << shift;
+            if b < 0x80 {
+                break;
+            }
+        }
+        v
+    }
+
+    fn packed(mut buf: &[u8]) -> Vec<u64> {
+        let mut vs = Vec::new();
+        while !buf.is_empty() {
+            vs.push(varint(&mut buf));
+        }
+        vs
+    }
+
+    /// Decodes the fields of a message.
+    fn decode(mut buf: &[u8]) -> Vec<(u64, Value)> {
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let key = varint(&mut buf);
+            let value = match key & 7 {
+                0 => Value::Varint(varint(&mut buf)),
+                2 => {
+                    let len = varint(&mut buf) as usize;
+                    let (value, rest) = buf.split_at(len);
+                    buf = rest;
+                    Value::Bytes(value.to_vec())
+                }
+                t => panic!("unexpected wire type {}", t),
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    fn field(fields: &[(u64, Value)], n: u64) -> Vec<&Value> {
+        fields.iter().filter(|f| f.0 == n).map(|f| &f.1).collect()
+    }
+
+    #[test]
+    fn encode_profile() {
+        let profile = HeapProfile::parse(
+            b"heap_v2/524288
+  t*: 2: 2097152 [0: 0]
+@ 0x1010 0x2020
+  t*: 2: 2097152 [0: 0]
+
+MAPPED_LIBRARIES:
+00001000-00003000 r-xp 00000000 08:01 1234 /usr/bin/app
+",
+        )
+        .unwrap();
+        let gz = profile.to_pprof(&PprofOptions::default()).unwrap();
+        let mut proto = Vec::new();
+        GzDecoder::new(&gz[..]).read_to_end(&mut proto).unwrap();
+        let fields = decode(&proto);
+
+        let strings: Vec<String> = field(&fields, 6)
+            .into_iter()
+            .map(|s| String::from_utf8(s.bytes().to_vec()).unwrap())
+            .collect();
+        assert_eq!(strings[0], "");
+        let string = |v: &Value| strings[v.varint() as usize].clone();
+
+        let types: Vec<_> = field(&fields, 1)
+            .into_iter()
+            .map(|t| decode(t.bytes()))
+            .map(|t| (string(&t[0].1), string(&t[1].1)))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("inuse_objects".to_owned(), "count".to_owned()),
+                ("inuse_space".to_owned(), "bytes".to_owned()),
+            ]
+        );
+        let period_type = decode(field(&fields, 11)[0].bytes());
+        assert_eq!(string(&period_type[0].1), "space");
+        assert_eq!(field(&fields, 12), [&Value::Varint(524288)]);
+
+        let samples = field(&fields, 2);
+        assert_eq!(samples.len(), 1);
+        let sample = decode(samples[0].bytes());
+        assert_eq!(packed(sample[0].1.bytes()), [1, 2]);
+        let estimate = profile.estimate(&profile.stacks[0].counts);
+        assert_eq!(
+            packed(sample[1].1.bytes()),
+            [
+                estimate.objects.round() as u64,
+                estimate.bytes.round() as u64
+            ]
+        );
+
+        let mappings = field(&fields, 3);
+        assert_eq!(mappings.len(), 1);
+        let mapping = decode(mappings[0].bytes());
+        assert_eq!(mapping[0].1, Value::Varint(1));
+        assert_eq!(mapping[1].1, Value::Varint(0x1000));
+        assert_eq!(mapping[2].1, Value::Varint(0x3000));
+        assert_eq!(string(&mapping[3].1), "/usr/bin/app");
+
+        let locations: Vec<_> = field(&fields, 4)
+            .into_iter()
+            .map(|l| decode(l.bytes()))
+            .collect();
+        // The innermost address is kept, callers point into the call.
+        assert_eq!(
+            locations,
+            [
+                vec![
+                    (1, Value::Varint(1)),
+                    (2, Value::Varint(1)),
+                    (3, Value::Varint(0x1010)),
+                ],
+                vec![
+                    (1, Value::Varint(2)),
+                    (2, Value::Varint(1)),
+                    (3, Value::Varint(0x201f)),
+                ],
+            ]
+        );
+        assert!(field(&fields, 5).is_empty());
+    }
+}
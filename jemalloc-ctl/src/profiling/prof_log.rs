@@ -0,0 +1,567 @@
+//! Allocation event logs (`prof.log_start` and `prof.log_stop`).
+
+use super::heap_dump::path_to_cstring;
+use super::log_stop;
+use crate::raw;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+/// Scope guard that logs the sampled allocations freed while it is alive.
+///
+/// The log is written to the path given to [`ProfLog::start`] when the
+/// guard is stopped or dropped. Only one log can be written at a time.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use tikv_jemalloc_ctl::profiling::{LogReader, ProfLog};
+///
+/// let path = std::env::temp_dir().join("app.json");
+/// let log = ProfLog::start(&path).unwrap();
+/// // ... run the workload ...
+/// log.stop().unwrap();
+///
+/// let reader = LogReader::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+/// for allocation in reader {
+///     let allocation = allocation.unwrap();
+///     println!("{} bytes lived {} ns", allocation.usize, allocation.lifetime());
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ProfLog {
+    path: PathBuf,
+    stopped: bool,
+}
+
+impl ProfLog {
+    /// Starts logging to `path` (`prof.log_start`).
+    ///
+    /// Fails if profiling is not enabled (`opt.prof`) or if a log is already
+    /// being written.
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let cpath = path_to_cstring(path)?;
+        // This is safe because the key expects a pointer to a
+        // null-terminated string, which jemalloc copies.
+        unsafe { raw::write(b"prof.log_start\0", cpath.as_ptr())? };
+        Ok(ProfLog {
+            path: path.to_owned(),
+            stopped: false,
+        })
+    }
+
+    /// Path the log is written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stops logging and writes the log (`prof.log_stop`), reporting the
+    /// errors that dropping the guard ignores.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.stopped = true;
+        log_stop::call()?;
+        Ok(())
+    }
+}
+
+impl Drop for ProfLog {
+    fn drop(&mut self) {
+        if !self.stopped {
+            let _ = log_stop::call();
+        }
+    }
+}
+
+/// Metadata of an allocation event log.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct LogInfo {
+    /// Time between the start and the end of logging, in nanoseconds.
+    pub duration: u64,
+    /// Version of `jemalloc`.
+    pub version: String,
+    /// Average interval (log base 2) between allocation samples.
+    pub lg_sample_rate: i64,
+    /// Clock of the timestamps: `default` (monotonic) or `high` (real-time).
+    pub prof_time_resolution: String,
+    /// Process id.
+    pub pid: i64,
+}
+
+/// Thread of an allocation event log.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LogThread {
+    /// Unique id of the thread, as in heap profiles.
+    pub uid: u64,
+    /// Name of the thread, empty if it was not set.
+    pub name: String,
+}
+
+/// Sampled allocation that was freed while logging.
+///
+/// Threads and stacks are indices in [`LogReader::threads`] and
+/// [`LogReader::stacks`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LogAllocation {
+    /// Thread that allocated the memory.
+    pub alloc_thread: usize,
+    /// Thread that freed the memory.
+    pub free_thread: usize,
+    /// Stack of the allocation.
+    pub alloc_stack: usize,
+    /// Stack of the deallocation.
+    pub free_stack: usize,
+    /// Time of the allocation, in nanoseconds.
+    pub alloc_time: u64,
+    /// Time of the deallocation, in nanoseconds.
+    pub free_time: u64,
+    /// Usable size, i.e. the size class the allocation was rounded up to.
+    pub usize: u64,
+}
+
+impl LogAllocation {
+    /// Time the allocation lived, in nanoseconds.
+    pub fn lifetime(&self) -> u64 {
+        self.free_time.saturating_sub(self.alloc_time)
+    }
+}
+
+/// Streaming reader of the JSON logs written by [`log_stop`].
+///
+/// The metadata, thread table and stack table are read by
+/// [`LogReader::new`]; the allocations, which make up most of the log, are
+/// then read one at a time by iterating over the reader.
+pub struct LogReader<R> {
+    json: Json<R>,
+    info: LogInfo,
+    threads: Vec<LogThread>,
+    stacks: Vec<Vec<u64>>,
+    /// Whether the next allocation is the first one, or `None` once all
+    /// allocations were read.
+    first: Option<bool>,
+}
+
+impl<R: BufRead> LogReader<R> {
+    /// Reads the log up to its first allocation.
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut log = LogReader {
+            json: Json { reader },
+            info: LogInfo::default(),
+            threads: Vec::new(),
+            stacks: Vec::new(),
+            first: None,
+        };
+        log.json.expect(b'{')?;
+        let mut first = true;
+        while let Some(key) = log.json.next_key(&mut first)? {
+            match key.as_str() {
+                "info" => log.info = log.json.info()?,
+                "threads" => log.threads = log.json.threads()?,
+                "stack_traces" => log.stacks = log.json.stacks()?,
+                "allocations" => {
+                    log.json.expect(b'[')?;
+                    log.first = Some(true);
+                    break;
+                }
+                _ => log.json.skip_value()?,
+            }
+        }
+        Ok(log)
+    }
+
+    /// Metadata of the log.
+    pub fn info(&self) -> &LogInfo {
+        &self.info
+    }
+
+    /// Threads that allocated or freed memory.
+    pub fn threads(&self) -> &[LogThread] {
+        &self.threads
+    }
+
+    /// Stacks of the allocations and deallocations, as return addresses,
+    /// innermost frame first.
+    pub fn stacks(&self) -> &[Vec<u64>] {
+        &self.stacks
+    }
+
+    fn next_allocation(&mut self) -> io::Result<Option<LogAllocation>> {
+        let first = match self.first {
+            Some(ref mut first) => first,
+            None => return Ok(None),
+        };
+        if self.json.next_element(first)? {
+            return self.json.allocation().map(Some);
+        }
+        // Skip what follows the allocations.
+        self.first = None;
+        let mut first = false;
+        while self.json.next_key(&mut first)?.is_some() {
+            self.json.skip_value()?;
+        }
+        Ok(None)
+    }
+}
+
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = io::Result<LogAllocation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_allocation() {
+            Ok(allocation) => allocation.map(Ok),
+            Err(e) => {
+                self.first = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid allocation log: {}", reason),
+    )
+}
+
+/// Pull parser of the subset of JSON written by `jemalloc`: no floating
+/// point numbers nor `null`.
+struct Json<R> {
+    reader: R,
+}
+
+impl<R: BufRead> Json<R> {
+    /// Returns the next byte, without consuming it.
+    fn peek_raw(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().copied())
+    }
+
+    /// Returns the next byte that is not whitespace, without consuming it.
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        loop {
+            match self.peek_raw()? {
+                Some(b) if b.is_ascii_whitespace() => self.reader.consume(1),
+                b => return Ok(b),
+            }
+        }
+    }
+
+    fn next_raw(&mut self) -> io::Result<u8> {
+        let b = self.peek_raw()?.ok_or_else(|| invalid("unexpected end"))?;
+        self.reader.consume(1);
+        Ok(b)
+    }
+
+    fn expect(&mut self, expected: u8) -> io::Result<()> {
+        match self.peek()? {
+            Some(b) if b == expected => {
+                self.reader.consume(1);
+                Ok(())
+            }
+            _ => Err(invalid(&format!("expected `{}`", expected as char))),
+        }
+    }
+
+    /// Moves to the next member of an object whose `{` was consumed, and
+    /// returns its key, or `None` at the end of the object.
+    fn next_key(&mut self, first: &mut bool) -> io::Result<Option<String>> {
+        if !self.next_element_or(first, b'}')? {
+            return Ok(None);
+        }
+        let key = self.string()?;
+        self.expect(b':')?;
+        Ok(Some(key))
+    }
+
+    /// Moves to the next element of an array whose `[` was consumed, and
+    /// returns `false` at the end of the array.
+    fn next_element(&mut self, first: &mut bool) -> io::Result<bool> {
+        self.next_element_or(first, b']')
+    }
+
+    fn next_element_or(
+        &mut self,
+        first: &mut bool,
+        end: u8,
+    ) -> io::Result<bool> {
+        if self.peek()? == Some(end) {
+            self.reader.consume(1);
+            return Ok(false);
+        }
+        if !*first {
+            self.expect(b',')?;
+        }
+        *first = false;
+        Ok(true)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.next_raw()? {
+                b'"' => break,
+                b'\\' => match self.next_raw()? {
+                    b'n' => bytes.push(b'\n'),
+                    b't' => bytes.push(b'\t'),
+                    b'r' => bytes.push(b'\r'),
+                    b'b' => bytes.push(8),
+                    b'f' => bytes.push(12),
+                    b'u' => {
+                        let mut hex = [0; 4];
+                        for h in &mut hex {
+                            *h = self.next_raw()?;
+                        }
+                        let c = std::str::from_utf8(&hex)
+                            .ok()
+                            .and_then(|h| u32::from_str_radix(h, 16).ok())
+                            .and_then(char::from_u32)
+                            .unwrap_or(char::REPLACEMENT_CHARACTER);
+                        let mut buf = [0; 4];
+                        bytes.extend_from_slice(
+                            c.encode_utf8(&mut buf).as_bytes(),
+                        );
+                    }
+                    b => bytes.push(b),
+                },
+                b => bytes.push(b),
+            }
+        }
+        // Thread names are written as is, and may not be valid UTF-8.
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn number(&mut self) -> io::Result<String> {
+        let mut number = String::new();
+        self.peek()?;
+        while let Some(b) = self.peek_raw()? {
+            if b == b'-' || b.is_ascii_digit() {
+                number.push(b as char);
+                self.reader.consume(1);
+            } else {
+                break;
+            }
+        }
+        Ok(number)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.number()?
+            .parse()
+            .map_err(|_| invalid("expected an unsigned integer"))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        self.number()?
+            .parse()
+            .map_err(|_| invalid("expected an integer"))
+    }
+
+    fn usize(&mut self) -> io::Result<usize> {
+        self.number()?
+            .parse()
+            .map_err(|_| invalid("expected an index"))
+    }
+
+    fn skip_value(&mut self) -> io::Result<()> {
+        match self.peek()? {
+            Some(b'"') => self.string().map(drop),
+            Some(b'{') => {
+                self.reader.consume(1);
+                let mut first = true;
+                while self.next_key(&mut first)?.is_some() {
+                    self.skip_value()?;
+                }
+                Ok(())
+            }
+            Some(b'[') => {
+                self.reader.consume(1);
+                let mut first = true;
+                while self.next_element(&mut first)? {
+                    self.skip_value()?;
+                }
+                Ok(())
+            }
+            Some(b't') | Some(b'f') => {
+                while self.peek_raw()?.is_some_and(|b| b.is_ascii_alphabetic())
+                {
+                    self.reader.consume(1);
+                }
+                Ok(())
+            }
+            _ => {
+                if self.number()?.is_empty() {
+                    return Err(invalid("expected a value"));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn info(&mut self) -> io::Result<LogInfo> {
+        let mut info = LogInfo::default();
+        self.expect(b'{')?;
+        let mut first = true;
+        while let Some(key) = self.next_key(&mut first)? {
+            match key.as_str() {
+                "duration" => info.duration = self.u64()?,
+                "version" => info.version = self.string()?,
+                "lg_sample_rate" => info.lg_sample_rate = self.i64()?,
+                "prof_time_resolution" => {
+                    info.prof_time_resolution = self.string()?
+                }
+                "pid" => info.pid = self.i64()?,
+                _ => self.skip_value()?,
+            }
+        }
+        Ok(info)
+    }
+
+    fn threads(&mut self) -> io::Result<Vec<LogThread>> {
+        let mut threads = Vec::new();
+        self.expect(b'[')?;
+        let mut first = true;
+        while self.next_element(&mut first)? {
+            let mut thread = LogThread {
+                uid: 0,
+                name: String::new(),
+            };
+            self.expect(b'{')?;
+            let mut first = true;
+            while let Some(key) = self.next_key(&mut first)? {
+                match key.as_str() {
+                    "thr_uid" => thread.uid = self.u64()?,
+                    "thr_name" => thread.name = self.string()?,
+                    _ => self.skip_value()?,
+                }
+            }
+            threads.push(thread);
+        }
+        Ok(threads)
+    }
+
+    fn stacks(&mut self) -> io::Result<Vec<Vec<u64>>> {
+        let mut stacks = Vec::new();
+        self.expect(b'[')?;
+        let mut first = true;
+        while self.next_element(&mut first)? {
+            let mut stack = Vec::new();
+            self.expect(b'[')?;
+            let mut first = true;
+            while self.next_element(&mut first)? {
+                let address = self.string()?;
+                let address = address
+                    .strip_prefix("0x")
+                    .and_then(|a| u64::from_str_radix(a, 16).ok())
+                    .ok_or_else(|| invalid("expected an address"))?;
+                stack.push(address);
+            }
+            stacks.push(stack);
+        }
+        Ok(stacks)
+    }
+
+    fn allocation(&mut self) -> io::Result<LogAllocation> {
+        let mut allocation = LogAllocation {
+            alloc_thread: 0,
+            free_thread: 0,
+            alloc_stack: 0,
+            free_stack: 0,
+            alloc_time: 0,
+            free_time: 0,
+            usize: 0,
+        };
+        self.expect(b'{')?;
+        let mut first = true;
+        while let Some(key) = self.next_key(&mut first)? {
+            match key.as_str() {
+                "alloc_thread" => allocation.alloc_thread = self.usize()?,
+                "free_thread" => allocation.free_thread = self.usize()?,
+                "alloc_trace" => allocation.alloc_stack = self.usize()?,
+                "free_trace" => allocation.free_stack = self.usize()?,
+                "alloc_timestamp" => allocation.alloc_time = self.u64()?,
+                "free_timestamp" => allocation.free_time = self.u64()?,
+                "usize" => allocation.usize = self.u64()?,
+                _ => self.skip_value()?,
+            }
+        }
+        Ok(allocation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &[u8] = br#"{"info":{"duration":1000,"version":"5.3.0-0-g54eaed1d8b56b1aa528be3bdd1877e59c56fa90c","lg_sample_rate":19,"prof_time_resolution":"default","pid":42,"extra":[1,{"a":true}]},"threads":[{"thr_uid":0,"thr_name":""},{"thr_uid":3,"thr_name":"worker \"1\""}],"stack_traces":[["0x10","0x20"],["0x30"]],"allocations":[{"alloc_thread":0,"free_thread":1,"alloc_trace":0,"free_trace":1,"alloc_timestamp":100,"free_timestamp":350,"usize":1048576},
+{"alloc_thread":1,"free_thread":1,"alloc_trace":1,"free_trace":1,"alloc_timestamp":200,"free_timestamp":210,"usize":32}]}"#;
+
+    #[test]
+    fn read_log() {
+        let mut reader = LogReader::new(LOG).unwrap();
+        assert_eq!(
+            *reader.info(),
+            LogInfo {
+                duration: 1000,
+                version: "5.3.0-0-g54eaed1d8b56b1aa528be3bdd1877e59c56fa90c"
+                    .to_owned(),
+                lg_sample_rate: 19,
+                prof_time_resolution: "default".to_owned(),
+                pid: 42,
+            }
+        );
+        assert_eq!(
+            reader.threads(),
+            [
+                LogThread {
+                    uid: 0,
+                    name: String::new(),
+                },
+                LogThread {
+                    uid: 3,
+                    name: "worker \"1\"".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(reader.stacks(), [vec![0x10, 0x20], vec![0x30]]);
+
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(
+            first,
+            LogAllocation {
+                alloc_thread: 0,
+                free_thread: 1,
+                alloc_stack: 0,
+                free_stack: 1,
+                alloc_time: 100,
+                free_time: 350,
+                usize: 1048576,
+            }
+        );
+        assert_eq!(first.lifetime(), 250);
+        assert_eq!(reader.next().unwrap().unwrap().lifetime(), 10);
+        assert!(reader.next().is_none());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn read_errors() {
+        assert!(LogReader::new(&b"["[..]).is_err());
+        assert!(
+            LogReader::new(&br#"{"threads":[{"thr_uid":-1}]}"#[..]).is_err()
+        );
+
+        let mut reader =
+            LogReader::new(&br#"{"allocations":[{"usize":1},2]}"#[..])
+                .unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(reader.next().is_none());
+    }
+}
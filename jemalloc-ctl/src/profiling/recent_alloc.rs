@@ -0,0 +1,307 @@
+//! Log of the recently sampled allocations.
+
+use crate::raw;
+use crate::stats_print::with_write_cb;
+use libc::{c_char, c_void};
+use std::io::{self, Write};
+
+/// Argument of `experimental.prof_recent.alloc_dump`.
+#[repr(C)]
+struct WriteCbPacket {
+    write_cb: unsafe extern "C" fn(*mut c_void, *const c_char),
+    cbopaque: *mut c_void,
+}
+
+/// Writes the log of the recently sampled allocations as JSON
+/// (`experimental.prof_recent.alloc_dump`).
+///
+/// The log keeps the last [`recent_alloc_max`](super::recent_alloc_max)
+/// sampled allocations, whether they were freed since or not. The JSON
+/// output is an object with the `sample_interval`, `recent_alloc_max` and
+/// `recent_alloc` keys, see [`RecentAllocs`] for the meaning of the records.
+///
+/// Profiling must be enabled (`opt.prof`).
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling;
+/// if profiling::prof::read().unwrap() {
+///     let mut json = Vec::new();
+///     profiling::recent_alloc_dump(&mut json).unwrap();
+///     println!("{}", String::from_utf8_lossy(&json));
+/// }
+/// # }
+/// ```
+pub fn recent_alloc_dump<W: Write>(writer: W) -> io::Result<()> {
+    let mut result = Ok(());
+    with_write_cb(writer, |write_cb, cbopaque| {
+        let packet = WriteCbPacket { write_cb, cbopaque };
+        result = unsafe {
+            raw::write(b"experimental.prof_recent.alloc_dump\0", packet)
+        };
+    })?;
+    Ok(result?)
+}
+
+#[cfg(feature = "prof_recent")]
+pub use self::parse::*;
+
+#[cfg(feature = "prof_recent")]
+mod parse {
+    use super::recent_alloc_dump;
+    use serde_json::{Map, Value};
+    use std::convert::TryFrom;
+    use std::io;
+
+    /// Log of the recently sampled allocations, as dumped by
+    /// [`recent_alloc_dump`].
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub struct RecentAllocs {
+        /// Average number of bytes between samples.
+        pub sample_interval: u64,
+        /// Maximum number of records, or `-1` for no limit.
+        pub recent_alloc_max: i64,
+        /// Records, oldest first.
+        pub allocs: Vec<RecentAlloc>,
+    }
+
+    /// Sampled allocation.
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub struct RecentAlloc {
+        /// Requested size.
+        pub size: usize,
+        /// Usable size, i.e. the size class the allocation was rounded up
+        /// to.
+        pub usize: usize,
+        /// Whether the memory was freed.
+        pub released: bool,
+        /// Allocation.
+        pub alloc: AllocEvent,
+        /// Deallocation, if the memory was freed and sampling was active on
+        /// the freeing thread.
+        pub dalloc: Option<AllocEvent>,
+    }
+
+    /// Allocation or deallocation of a [`RecentAlloc`].
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub struct AllocEvent {
+        /// Unique id of the thread, as in heap profiles.
+        pub thread: u64,
+        /// Name of the thread, if set through `thread.prof.name`.
+        pub thread_name: Option<String>,
+        /// Time in nanoseconds, on the monotonic clock, or on the real-time
+        /// clock if `opt.prof_time_resolution` is `high`.
+        pub time: u64,
+        /// Return addresses, innermost frame first.
+        pub stack: Vec<u64>,
+    }
+
+    impl RecentAllocs {
+        /// Parses the JSON output of [`recent_alloc_dump`].
+        pub fn parse(json: &[u8]) -> io::Result<Self> {
+            let value: Value = serde_json::from_slice(json)?;
+            let log = object(&value, "log")?;
+            let allocs = array(log, "recent_alloc")?
+                .iter()
+                .map(RecentAlloc::from_json)
+                .collect::<io::Result<_>>()?;
+            Ok(RecentAllocs {
+                sample_interval: u64(log, "sample_interval")?,
+                recent_alloc_max: field(log, "recent_alloc_max")?
+                    .as_i64()
+                    .ok_or_else(|| invalid("recent_alloc_max"))?,
+                allocs,
+            })
+        }
+    }
+
+    impl RecentAlloc {
+        fn from_json(value: &Value) -> io::Result<Self> {
+            let record = object(value, "recent_alloc")?;
+            let released = field(record, "released")?
+                .as_bool()
+                .ok_or_else(|| invalid("released"))?;
+            let dalloc = if record.contains_key("dalloc_time") {
+                Some(AllocEvent::from_json(record, "dalloc")?)
+            } else {
+                None
+            };
+            Ok(RecentAlloc {
+                size: usize(record, "size")?,
+                usize: usize(record, "usize")?,
+                released,
+                alloc: AllocEvent::from_json(record, "alloc")?,
+                dalloc,
+            })
+        }
+    }
+
+    impl AllocEvent {
+        /// Reads the `<prefix>_*` fields of `record`.
+        fn from_json(
+            record: &Map<String, Value>,
+            prefix: &str,
+        ) -> io::Result<Self> {
+            let key = |name: &str| format!("{}_{}", prefix, name);
+            let thread_name = match record.get(&key("thread_name")) {
+                Some(name) => Some(
+                    name.as_str()
+                        .ok_or_else(|| invalid("thread_name"))?
+                        .to_owned(),
+                ),
+                None => None,
+            };
+            let stack = array(record, &key("trace"))?
+                .iter()
+                .map(|address| {
+                    address
+                        .as_str()
+                        .and_then(|a| a.strip_prefix("0x"))
+                        .and_then(|a| u64::from_str_radix(a, 16).ok())
+                        .ok_or_else(|| invalid("trace"))
+                })
+                .collect::<io::Result<_>>()?;
+            Ok(AllocEvent {
+                thread: u64(record, &key("thread_uid"))?,
+                thread_name,
+                time: u64(record, &key("time"))?,
+                stack,
+            })
+        }
+    }
+
+    /// Dumps and parses the log of the recently sampled allocations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// if profiling::prof::read().unwrap() {
+    ///     let log = profiling::recent_allocs().unwrap();
+    ///     if let Some(largest) = log.allocs.iter().max_by_key(|a| a.size) {
+    ///         println!("largest recent allocation: {:?}", largest);
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn recent_allocs() -> io::Result<RecentAllocs> {
+        let mut json = Vec::new();
+        recent_alloc_dump(&mut json)?;
+        RecentAllocs::parse(&json)
+    }
+
+    fn invalid(key: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid recent allocation log: bad `{}`", key),
+        )
+    }
+
+    fn field<'a>(
+        object: &'a Map<String, Value>,
+        key: &str,
+    ) -> io::Result<&'a Value> {
+        object.get(key).ok_or_else(|| invalid(key))
+    }
+
+    fn object<'a>(
+        value: &'a Value,
+        key: &str,
+    ) -> io::Result<&'a Map<String, Value>> {
+        value.as_object().ok_or_else(|| invalid(key))
+    }
+
+    fn array<'a>(
+        object: &'a Map<String, Value>,
+        key: &str,
+    ) -> io::Result<&'a Vec<Value>> {
+        field(object, key)?.as_array().ok_or_else(|| invalid(key))
+    }
+
+    fn u64(object: &Map<String, Value>, key: &str) -> io::Result<u64> {
+        field(object, key)?.as_u64().ok_or_else(|| invalid(key))
+    }
+
+    fn usize(object: &Map<String, Value>, key: &str) -> io::Result<usize> {
+        usize::try_from(u64(object, key)?).map_err(|_| invalid(key))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parse() {
+            let log = RecentAllocs::parse(
+                br#"{"sample_interval":524288,"recent_alloc_max":2,"recent_alloc":[
+{"size":1000000,"usize":1003520,"released":true,"alloc_thread_uid":1,
+"alloc_thread_name":"worker","alloc_time":10,"alloc_trace":["0x10","0x20"],
+"dalloc_thread_uid":2,"dalloc_time":30,"dalloc_trace":["0x30"]},
+{"size":24,"usize":32,"released":false,"alloc_thread_uid":0,
+"alloc_time":20,"alloc_trace":[]}]}"#,
+            )
+            .unwrap();
+            assert_eq!(
+                log,
+                RecentAllocs {
+                    sample_interval: 524288,
+                    recent_alloc_max: 2,
+                    allocs: vec![
+                        RecentAlloc {
+                            size: 1000000,
+                            usize: 1003520,
+                            released: true,
+                            alloc: AllocEvent {
+                                thread: 1,
+                                thread_name: Some("worker".to_owned()),
+                                time: 10,
+                                stack: vec![0x10, 0x20],
+                            },
+                            dalloc: Some(AllocEvent {
+                                thread: 2,
+                                thread_name: None,
+                                time: 30,
+                                stack: vec![0x30],
+                            }),
+                        },
+                        RecentAlloc {
+                            size: 24,
+                            usize: 32,
+                            released: false,
+                            alloc: AllocEvent {
+                                thread: 0,
+                                thread_name: None,
+                                time: 20,
+                                stack: vec![],
+                            },
+                            dalloc: None,
+                        },
+                    ],
+                }
+            );
+        }
+
+        #[test]
+        fn parse_errors() {
+            assert!(RecentAllocs::parse(b"{").is_err());
+            assert!(RecentAllocs::parse(b"[]").is_err());
+            let err = RecentAllocs::parse(
+                br#"{"sample_interval":1,"recent_alloc_max":-1,"recent_alloc":[
+{"size":1,"usize":8,"released":false,"alloc_thread_uid":0,
+"alloc_time":0,"alloc_trace":["main"]}]}"#,
+            )
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("trace"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Per-size-class statistics of the sampled allocations.
+
+use crate::arenas::{self, SizeClass, SizeClasses};
+use crate::error::Result;
+use libc::c_uint;
+
+/// Counts of sampled allocations (`prof_stats_t`).
+///
+/// Only the allocations picked by the sampler are counted, so the counts are
+/// exact for the sampled allocations, not estimates of all allocations.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ProfStats {
+    /// Sum of the requested sizes, in bytes.
+    pub requested_bytes: u64,
+    /// Number of allocations.
+    pub count: u64,
+}
+
+/// Implements `read(index)` for a key whose name contains the index of a bin
+/// or of a large size class as its fourth component.
+macro_rules! prof_stats_indexed {
+    ($id:ident) => {
+        paste::paste! {
+            impl $id {
+                /// Reads the statistics of the size class with index `index`.
+                pub fn read(index: c_uint) -> Result<ProfStats> {
+                    Self::mib()?.read(index)
+                }
+            }
+
+            impl [<$id _mib>] {
+                /// Reads the statistics of the size class with index `index`
+                /// using the MIB API.
+                pub fn read(self, index: c_uint) -> Result<ProfStats> {
+                    let mut mib = self.0;
+                    mib[3] = index as usize;
+                    unsafe { crate::raw::read_mib(mib.as_ref()) }
+                }
+            }
+        }
+    };
+}
+
+option! {
+    bins_live[ str: b"prof.stats.bins.0.live\0", non_str: 5 ] => ProfStats |
+    ops: |
+    docs:
+    /// Sampled allocations of the bin size class with index `index` that
+    /// are still live (`prof.stats.bins.<index>.live`).
+    ///
+    /// Both `opt.prof` and [`prof_stats`](super::prof_stats) must be enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// if profiling::prof::read().unwrap() && profiling::prof_stats::read().unwrap() {
+    ///     let live = profiling::bins_live::read(0).unwrap();
+    ///     println!("{} live sampled allocations in bin 0", live.count);
+    /// }
+    /// # }
+    /// ```
+    mib_docs: /// See [`bins_live`].
+}
+
+prof_stats_indexed!(bins_live);
+
+option! {
+    bins_accum[ str: b"prof.stats.bins.0.accum\0", non_str: 5 ] => ProfStats |
+    ops: |
+    docs:
+    /// Sampled allocations of the bin size class with index `index` since
+    /// the start of the process, freed or not
+    /// (`prof.stats.bins.<index>.accum`).
+    ///
+    /// Both `opt.prof` and [`prof_stats`](super::prof_stats) must be enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// if profiling::prof::read().unwrap() && profiling::prof_stats::read().unwrap() {
+    ///     let accum = profiling::bins_accum::read(0).unwrap();
+    ///     println!("{} sampled allocations in bin 0", accum.count);
+    /// }
+    /// # }
+    /// ```
+    mib_docs: /// See [`bins_accum`].
+}
+
+prof_stats_indexed!(bins_accum);
+
+option! {
+    lextents_live[ str: b"prof.stats.lextents.0.live\0", non_str: 5 ] => ProfStats |
+    ops: |
+    docs:
+    /// Sampled allocations of the large size class with index `index` that
+    /// are still live (`prof.stats.lextents.<index>.live`).
+    ///
+    /// Both `opt.prof` and [`prof_stats`](super::prof_stats) must be enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// if profiling::prof::read().unwrap() && profiling::prof_stats::read().unwrap() {
+    ///     let live = profiling::lextents_live::read(0).unwrap();
+    ///     println!("{} live sampled bytes in lextent 0", live.requested_bytes);
+    /// }
+    /// # }
+    /// ```
+    mib_docs: /// See [`lextents_live`].
+}
+
+prof_stats_indexed!(lextents_live);
+
+option! {
+    lextents_accum[ str: b"prof.stats.lextents.0.accum\0", non_str: 5 ] => ProfStats |
+    ops: |
+    docs:
+    /// Sampled allocations of the large size class with index `index` since
+    /// the start of the process, freed or not
+    /// (`prof.stats.lextents.<index>.accum`).
+    ///
+    /// Both `opt.prof` and [`prof_stats`](super::prof_stats) must be enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::profiling;
+    /// if profiling::prof::read().unwrap() && profiling::prof_stats::read().unwrap() {
+    ///     let accum = profiling::lextents_accum::read(0).unwrap();
+    ///     println!("{} sampled bytes in lextent 0", accum.requested_bytes);
+    /// }
+    /// # }
+    /// ```
+    mib_docs: /// See [`lextents_accum`].
+}
+
+prof_stats_indexed!(lextents_accum);
+
+/// Statistics of the sampled allocations of a size class.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SizeClassProfStats {
+    /// Size class.
+    pub size_class: SizeClass,
+    /// Sampled allocations that are still live.
+    pub live: ProfStats,
+    /// Sampled allocations since the start of the process.
+    pub accum: ProfStats,
+}
+
+/// Iterator over the statistics of the sampled allocations of all size
+/// classes, from the smallest to the largest.
+///
+/// Both `opt.prof` and [`prof_stats`](super::prof_stats) must be enabled, e.g.
+/// by starting the program with `_RJEM_MALLOC_CONF=prof:true,prof_stats:true`.
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling::{self, SizeClassesProfStats};
+/// if profiling::prof::read().unwrap() && profiling::prof_stats::read().unwrap() {
+///     for stats in SizeClassesProfStats::new().unwrap() {
+///         let stats = stats.unwrap();
+///         if stats.live.count > 0 {
+///             println!(
+///                 "{}: {} live sampled allocations",
+///                 stats.size_class.size, stats.live.count
+///             );
+///         }
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SizeClassesProfStats {
+    size_classes: SizeClasses,
+    nbins: c_uint,
+    next: c_uint,
+    bins_live: bins_live_mib,
+    bins_accum: bins_accum_mib,
+    lextents_live: lextents_live_mib,
+    lextents_accum: lextents_accum_mib,
+}
+
+impl SizeClassesProfStats {
+    /// Returns an iterator over the statistics of all size classes.
+    pub fn new() -> Result<Self> {
+        Ok(SizeClassesProfStats {
+            size_classes: SizeClasses::new()?,
+            nbins: arenas::nbins::read()?,
+            next: 0,
+            bins_live: bins_live::mib()?,
+            bins_accum: bins_accum::mib()?,
+            lextents_live: lextents_live::mib()?,
+            lextents_accum: lextents_accum::mib()?,
+        })
+    }
+
+    fn read(
+        &self,
+        index: c_uint,
+        size_class: SizeClass,
+    ) -> Result<SizeClassProfStats> {
+        let (live, accum) = if index < self.nbins {
+            (self.bins_live.read(index)?, self.bins_accum.read(index)?)
+        } else {
+            let index = index - self.nbins;
+            (
+                self.lextents_live.read(index)?,
+                self.lextents_accum.read(index)?,
+            )
+        };
+        Ok(SizeClassProfStats {
+            size_class,
+            live,
+            accum,
+        })
+    }
+}
+
+impl Iterator for SizeClassesProfStats {
+    type Item = Result<SizeClassProfStats>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size_class = self.size_classes.next()?;
+        let index = self.next;
+        self.next += 1;
+        Some(size_class.and_then(|size_class| self.read(index, size_class)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.size_classes.size_hint()
+    }
+}
+
+impl ExactSizeIterator for SizeClassesProfStats {}
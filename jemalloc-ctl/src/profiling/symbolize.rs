@@ -0,0 +1,49 @@
+//! In-process symbolization of profile addresses.
+
+/// Source-level frame of an address.
+///
+/// Flamegraphs only use the names.
+pub(super) struct Frame {
+    /// Demangled name, without the hash of Rust symbols.
+    pub name: String,
+    /// Symbol name as found in the binary.
+    #[cfg_attr(not(feature = "pprof"), allow(dead_code))]
+    pub system_name: String,
+    #[cfg_attr(
+        not(any(feature = "pprof", feature = "top_sites")),
+        allow(dead_code)
+    )]
+    pub filename: Option<String>,
+    #[cfg_attr(
+        not(any(feature = "pprof", feature = "top_sites")),
+        allow(dead_code)
+    )]
+    pub line: Option<u32>,
+}
+
+/// Resolves `pc`, an address within an instruction of the current process,
+/// to its frames, innermost inlined frame first. Frames without a name are
+/// left out.
+pub(super) fn resolve(pc: u64) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    // `resolve` expects an instruction pointer, which it moves back by one
+    // byte itself; `pc` already points into the instruction.
+    backtrace::resolve((pc + 1) as usize as *mut _, |symbol| {
+        let name = match symbol.name() {
+            Some(name) => name,
+            None => return,
+        };
+        let display = format!("{:#}", name);
+        frames.push(Frame {
+            system_name: name
+                .as_str()
+                .map_or_else(|| display.clone(), str::to_owned),
+            name: display,
+            filename: symbol
+                .filename()
+                .map(|f| f.to_string_lossy().into_owned()),
+            line: symbol.lineno(),
+        });
+    });
+    frames
+}
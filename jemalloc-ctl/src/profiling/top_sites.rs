@@ -0,0 +1,175 @@
+//! Symbolized allocation sites of the current process.
+
+use super::{dump_to_vec, symbolize, trim_allocator_frames, HeapProfile};
+use std::fmt;
+use std::io;
+
+/// Frame of an allocation site.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SiteFrame {
+    /// Address of the instruction, in the current process.
+    pub address: u64,
+    /// Demangled name of the function, without the hash of Rust symbols, or
+    /// the hexadecimal address if it could not be resolved.
+    pub function: String,
+    /// Source file, if there is debug information.
+    pub file: Option<String>,
+    /// Source line, if there is debug information.
+    pub line: Option<u32>,
+}
+
+/// Call stack that allocated live memory, as returned by [`top_sites`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct AllocationSite {
+    /// Estimated live bytes allocated by the stack.
+    pub bytes: f64,
+    /// Estimated live objects allocated by the stack.
+    pub objects: f64,
+    /// Frames of the stack, innermost first, with a frame per inlined
+    /// function. The frames of the allocator are left out.
+    pub frames: Vec<SiteFrame>,
+}
+
+impl fmt::Display for AllocationSite {
+    /// Formats the site on several lines, like a Rust backtrace.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.0} bytes in {:.0} objects", self.bytes, self.objects)?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            write!(f, "\n{:>4}: {}", i, frame.function)?;
+            if let Some(ref file) = frame.file {
+                write!(f, "\n             at {}", file)?;
+                if let Some(line) = frame.line {
+                    write!(f, ":{}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HeapProfile {
+    /// Returns the `n` stacks with the most estimated live bytes, resolved
+    /// with the debug information of the current process.
+    ///
+    /// This is only meaningful if the profile was dumped by the current
+    /// process.
+    pub fn top_sites(&self, n: usize) -> Vec<AllocationSite> {
+        let mut stacks: Vec<_> = self
+            .stacks
+            .iter()
+            .map(|stack| (stack, self.estimate(&stack.counts)))
+            .filter(|(_, estimate)| estimate.bytes > 0.0)
+            .collect();
+        stacks.sort_by(|a, b| b.1.bytes.total_cmp(&a.1.bytes));
+        stacks
+            .into_iter()
+            .take(n)
+            .map(|(stack, estimate)| AllocationSite {
+                bytes: estimate.bytes,
+                objects: estimate.objects,
+                frames: frames(stack.pcs()),
+            })
+            .collect()
+    }
+}
+
+/// Dumps a memory profile and returns the `n` call stacks with the most
+/// estimated live bytes, symbolized with the debug information of the
+/// current process.
+///
+/// The profile goes through a private temporary file, see [`dump_to_vec`];
+/// no external tool is needed.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::profiling;
+/// for site in profiling::top_sites(10).unwrap() {
+///     eprintln!("{}", site);
+/// }
+/// # }
+/// ```
+pub fn top_sites(n: usize) -> io::Result<Vec<AllocationSite>> {
+    let profile = dump_to_vec()?
+        .profile()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(profile.top_sites(n))
+}
+
+/// Resolves the frames of `pcs`, leaving out the frames of the allocator at
+/// the top of the stack.
+fn frames(pcs: impl Iterator<Item = u64>) -> Vec<SiteFrame> {
+    let mut frames = Vec::new();
+    for pc in pcs {
+        let resolved = symbolize::resolve(pc);
+        if resolved.is_empty() {
+            frames.push(SiteFrame {
+                address: pc,
+                function: format!("{:#x}", pc),
+                file: None,
+                line: None,
+            });
+        }
+        frames.extend(resolved.into_iter().map(|frame| SiteFrame {
+            address: pc,
+            function: frame.name,
+            file: frame.filename,
+            line: frame.line,
+        }));
+    }
+    trim_allocator_frames(&mut frames, |frame| &frame.function);
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_sites() {
+        let profile = HeapProfile::parse(
+            b"heap_v2/1
+  t*: 6: 6144 [0: 0]
+@ 0x10 0x21
+  t*: 1: 1024 [0: 0]
+@ 0x11 0x21
+  t*: 3: 3072 [0: 0]
+@ 0x12
+  t*: 0: 0 [0: 0]
+",
+        )
+        .unwrap();
+        let sites = profile.top_sites(5);
+        // Addresses outside of the process are not resolved.
+        let frame = |address: u64| SiteFrame {
+            address,
+            function: format!("{:#x}", address),
+            file: None,
+            line: None,
+        };
+        assert_eq!(
+            sites,
+            [
+                AllocationSite {
+                    bytes: 3072.0,
+                    objects: 3.0,
+                    frames: vec![frame(0x11), frame(0x20)],
+                },
+                AllocationSite {
+                    bytes: 1024.0,
+                    objects: 1.0,
+                    frames: vec![frame(0x10), frame(0x20)],
+                },
+            ]
+        );
+        assert_eq!(profile.top_sites(1).len(), 1);
+        assert_eq!(
+            sites[0].to_string(),
+            "3072 bytes in 3 objects\n   0: 0x11\n   1: 0x20"
+        );
+    }
+}
@@ -201,6 +201,103 @@ pub unsafe fn update<T>(name: &[u8], mut value: T) -> Result<T> {
     Ok(old_value.assume_init())
 }
 
+/// Uses the MIB `mib` as key to the _MALLCTL NAMESPACE_, writes its `value`
+/// and reads back a value of a possibly different type.
+///
+/// This is required by keys that take an argument as new value and return a
+/// result as old value, e.g. `arenas.lookup`.
+///
+/// The [`name_to_mib`] API translates a string of the key (e.g. `arenas.nbins`)
+/// to a `mib` (Management Information Base).
+///
+/// # Safety
+///
+/// This function is `unsafe` because it is possible to use it to construct an
+/// invalid `U`, or to pass an invalid `T` to the key.
+pub unsafe fn write_read_mib<T, U: Copy>(
+    mib: &[usize],
+    mut value: T,
+) -> Result<U> {
+    let mut old_len = mem::size_of::<U>();
+    let mut old_value = MaybeUninit::<U>::uninit();
+    cvt(tikv_jemalloc_sys::mallctlbymib(
+        mib.as_ptr(),
+        mib.len(),
+        old_value.as_mut_ptr() as *mut _,
+        &mut old_len,
+        &mut value as *mut _ as *mut _,
+        mem::size_of::<T>(),
+    ))?;
+    assert_eq!(old_len, mem::size_of::<U>());
+    Ok(old_value.assume_init())
+}
+
+/// Uses the null-terminated string `name` as key to the _MALLCTL NAMESPACE_,
+/// writes its `value` and reads back a value of a possibly different type.
+///
+/// This is required by keys that take an argument as new value and return a
+/// result as old value, e.g. `arenas.lookup`.
+///
+/// # Safety
+///
+/// This function is `unsafe` because it is possible to use it to construct an
+/// invalid `U`, or to pass an invalid `T` to the key.
+pub unsafe fn write_read<T, U: Copy>(name: &[u8], mut value: T) -> Result<U> {
+    validate_name(name);
+
+    let mut old_len = mem::size_of::<U>();
+    let mut old_value = MaybeUninit::<U>::uninit();
+    cvt(tikv_jemalloc_sys::mallctl(
+        name as *const _ as *const c_char,
+        old_value.as_mut_ptr() as *mut _,
+        &mut old_len,
+        &mut value as *mut _ as *mut _,
+        mem::size_of::<T>(),
+    ))?;
+    assert_eq!(old_len, mem::size_of::<U>());
+    Ok(old_value.assume_init())
+}
+
+/// Uses the MIB `mib` as key to the _MALLCTL NAMESPACE_ and triggers the
+/// action of a key without value (e.g. `arena.<i>.purge`).
+///
+/// The [`name_to_mib`] API translates a string of the key (e.g. `arenas.nbins`)
+/// to a `mib` (Management Information Base).
+///
+/// # Safety
+///
+/// This function is `unsafe` because some of these actions invalidate memory
+/// (e.g. `arena.<i>.reset` discards all the allocations of the arena).
+pub unsafe fn call_mib(mib: &[usize]) -> Result<()> {
+    cvt(tikv_jemalloc_sys::mallctlbymib(
+        mib.as_ptr(),
+        mib.len(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        0,
+    ))
+}
+
+/// Uses the null-terminated string `name` as key to the _MALLCTL NAMESPACE_ and
+/// triggers the action of a key without value (e.g. `arena.<i>.purge`).
+///
+/// # Safety
+///
+/// This function is `unsafe` because some of these actions invalidate memory
+/// (e.g. `arena.<i>.reset` discards all the allocations of the arena).
+pub unsafe fn call(name: &[u8]) -> Result<()> {
+    validate_name(name);
+
+    cvt(tikv_jemalloc_sys::mallctl(
+        name as *const _ as *const c_char,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        0,
+    ))
+}
+
 /// Uses the MIB `mib` as key to the _MALLCTL NAMESPACE_ and reads its value.
 ///
 /// The [`name_to_mib`] API translates a string of the key (e.g. `arenas.nbins`)
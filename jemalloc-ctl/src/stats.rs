@@ -4,6 +4,20 @@
 //! only refreshed when the `jemalloc` "epoch" is advanced. See the [`crate::epoch`] type
 //! for more information.
 
+mod arenas;
+pub use self::arenas::{AllocStats, ArenaStats, ArenaStatsMibs, PurgeStats};
+mod bins;
+pub use self::bins::{ArenaBins, BinStats, BinStatsMibs};
+mod extents;
+pub use self::extents::{
+    ArenaExtents, ArenaLextents, ExtentStats, LextentStats, LextentStatsMibs,
+};
+mod mutexes;
+pub use self::mutexes::{
+    mutexes_reset, mutexes_reset_mib, ArenaMutex, GlobalMutex, MutexStats,
+    MutexStatsMibs,
+};
+
 option! {
     allocated[ str: b"stats.allocated\0", non_str: 2 ] => libc::size_t |
     ops: r |
@@ -0,0 +1,372 @@
+//! Per-arena statistics (`stats.arenas.<i>.*`).
+
+use crate::error::Result;
+use crate::keys::{Access, AsName, Mib, MibStr};
+use crate::std::ops::IndexMut;
+use libc::c_uint;
+use tikv_jemalloc_sys::{MALLCTL_ARENAS_ALL, MALLCTL_ARENAS_DESTROYED};
+
+/// Reads the key of `mib` for the arena with index `index`, which is the
+/// third component of the `stats.arenas.<i>.*` keys.
+pub(crate) fn read_indexed<M, T>(mut mib: M, index: c_uint) -> Result<T>
+where
+    M: Access<T> + IndexMut<usize, Output = usize>,
+{
+    mib[2] = index as usize;
+    mib.read()
+}
+
+/// Allocation statistics of the small or the large size classes of an arena
+/// (`stats.arenas.<i>.small.*` or `stats.arenas.<i>.large.*`).
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct AllocStats {
+    /// Number of bytes currently allocated.
+    pub allocated: usize,
+    /// Cumulative number of times an allocation was served by the arena,
+    /// either directly or to fill a thread cache.
+    pub nmalloc: u64,
+    /// Cumulative number of times an allocation was returned to the arena,
+    /// either directly or by flushing a thread cache.
+    pub ndalloc: u64,
+    /// Cumulative number of allocation requests, including the ones served
+    /// by a thread cache.
+    pub nrequests: u64,
+    /// Cumulative number of thread cache fills.
+    pub nfills: u64,
+    /// Cumulative number of thread cache flushes.
+    pub nflushes: u64,
+}
+
+/// Purging statistics of the dirty or the muzzy pages of an arena
+/// (`stats.arenas.<i>.dirty_*` or `stats.arenas.<i>.muzzy_*`).
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct PurgeStats {
+    /// Number of purge sweeps.
+    pub npurge: u64,
+    /// Number of `madvise()` or similar calls made to purge pages.
+    pub nmadvise: u64,
+    /// Number of pages purged.
+    pub purged: u64,
+}
+
+/// Statistics of an arena (`stats.arenas.<i>.*`).
+///
+/// Like the global statistics, they are cached and only refreshed when the
+/// epoch is advanced, see [`crate::epoch`].
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::epoch;
+/// use tikv_jemalloc_ctl::stats::ArenaStats;
+///
+/// epoch::advance().unwrap();
+/// let merged = ArenaStats::read(ArenaStats::MERGED).unwrap();
+/// println!(
+///     "{} bytes resident, {} dirty pages",
+///     merged.resident, merged.pdirty
+/// );
+/// # }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ArenaStats {
+    /// Number of threads currently assigned to the arena.
+    pub nthreads: u32,
+    /// Time in nanoseconds since the creation of the arena, or since the
+    /// initialization of `jemalloc` for the merged statistics.
+    pub uptime: u64,
+    /// Precedence of `sbrk(2)` relative to `mmap(2)`, see
+    /// [`crate::opt::dss`].
+    pub dss: &'static str,
+    /// Time in milliseconds from the creation of a set of unused dirty pages
+    /// until they are purged or reused, or `-1`.
+    pub dirty_decay_ms: isize,
+    /// Time in milliseconds from the creation of a set of unused muzzy pages
+    /// until they are purged or reused, or `-1`.
+    pub muzzy_decay_ms: isize,
+    /// Number of pages in active extents.
+    pub pactive: usize,
+    /// Number of pages within unused extents that are potentially dirty.
+    pub pdirty: usize,
+    /// Number of pages within unused extents that are muzzy.
+    pub pmuzzy: usize,
+    /// Number of mapped bytes.
+    pub mapped: usize,
+    /// Number of bytes retained, i.e. unmapped but not returned to the
+    /// operating system.
+    pub retained: usize,
+    /// Number of bytes dedicated to bootstrap-sensitive allocator metadata.
+    pub base: usize,
+    /// Number of bytes dedicated to internal allocations.
+    pub internal: usize,
+    /// Number of transparent huge pages used for metadata.
+    pub metadata_thp: usize,
+    /// Number of bytes cached in the thread caches of the arena.
+    pub tcache_bytes: usize,
+    /// Number of bytes in physically resident data pages mapped by the
+    /// arena.
+    pub resident: usize,
+    /// Number of bytes in virtual memory mappings that were retained but
+    /// could not be reused because of a failing extent hook.
+    pub abandoned_vm: usize,
+    /// Allocations of the small size classes.
+    pub small: AllocStats,
+    /// Allocations of the large size classes.
+    pub large: AllocStats,
+    /// Purging of the dirty pages.
+    pub dirty: PurgeStats,
+    /// Purging of the muzzy pages.
+    pub muzzy: PurgeStats,
+}
+
+impl ArenaStats {
+    /// Index of the statistics merged from all arenas, including the
+    /// destroyed ones (`MALLCTL_ARENAS_ALL`).
+    pub const MERGED: c_uint = MALLCTL_ARENAS_ALL;
+
+    /// Index of the statistics merged from the destroyed arenas
+    /// (`MALLCTL_ARENAS_DESTROYED`).
+    ///
+    /// Reading them fails until an arena is destroyed.
+    pub const DESTROYED: c_uint = MALLCTL_ARENAS_DESTROYED;
+
+    /// Reads the statistics of the arena with index `index`, or of the
+    /// [`MERGED`](Self::MERGED) or [`DESTROYED`](Self::DESTROYED)
+    /// pseudo-arenas.
+    ///
+    /// This looks up the MIBs of all the keys first: use
+    /// [`ArenaStatsMibs`] to read the statistics repeatedly.
+    pub fn read(index: c_uint) -> Result<Self> {
+        ArenaStatsMibs::new()?.read(index)
+    }
+}
+
+/// MIBs of the [`AllocStats`] keys of an arena.
+#[derive(Copy, Clone)]
+struct AllocStatsMibs {
+    allocated: Mib<[usize; 5]>,
+    nmalloc: Mib<[usize; 5]>,
+    ndalloc: Mib<[usize; 5]>,
+    nrequests: Mib<[usize; 5]>,
+    nfills: Mib<[usize; 5]>,
+    nflushes: Mib<[usize; 5]>,
+}
+
+impl AllocStatsMibs {
+    fn small() -> Result<Self> {
+        Ok(AllocStatsMibs {
+            allocated: b"stats.arenas.0.small.allocated\0".name().mib()?,
+            nmalloc: b"stats.arenas.0.small.nmalloc\0".name().mib()?,
+            ndalloc: b"stats.arenas.0.small.ndalloc\0".name().mib()?,
+            nrequests: b"stats.arenas.0.small.nrequests\0".name().mib()?,
+            nfills: b"stats.arenas.0.small.nfills\0".name().mib()?,
+            nflushes: b"stats.arenas.0.small.nflushes\0".name().mib()?,
+        })
+    }
+
+    fn large() -> Result<Self> {
+        Ok(AllocStatsMibs {
+            allocated: b"stats.arenas.0.large.allocated\0".name().mib()?,
+            nmalloc: b"stats.arenas.0.large.nmalloc\0".name().mib()?,
+            ndalloc: b"stats.arenas.0.large.ndalloc\0".name().mib()?,
+            nrequests: b"stats.arenas.0.large.nrequests\0".name().mib()?,
+            nfills: b"stats.arenas.0.large.nfills\0".name().mib()?,
+            nflushes: b"stats.arenas.0.large.nflushes\0".name().mib()?,
+        })
+    }
+
+    fn read(&self, index: c_uint) -> Result<AllocStats> {
+        Ok(AllocStats {
+            allocated: read_indexed(self.allocated, index)?,
+            nmalloc: read_indexed(self.nmalloc, index)?,
+            ndalloc: read_indexed(self.ndalloc, index)?,
+            nrequests: read_indexed(self.nrequests, index)?,
+            nfills: read_indexed(self.nfills, index)?,
+            nflushes: read_indexed(self.nflushes, index)?,
+        })
+    }
+}
+
+/// MIBs of the [`PurgeStats`] keys of an arena.
+#[derive(Copy, Clone)]
+struct PurgeStatsMibs {
+    npurge: Mib<[usize; 4]>,
+    nmadvise: Mib<[usize; 4]>,
+    purged: Mib<[usize; 4]>,
+}
+
+impl PurgeStatsMibs {
+    fn dirty() -> Result<Self> {
+        Ok(PurgeStatsMibs {
+            npurge: b"stats.arenas.0.dirty_npurge\0".name().mib()?,
+            nmadvise: b"stats.arenas.0.dirty_nmadvise\0".name().mib()?,
+            purged: b"stats.arenas.0.dirty_purged\0".name().mib()?,
+        })
+    }
+
+    fn muzzy() -> Result<Self> {
+        Ok(PurgeStatsMibs {
+            npurge: b"stats.arenas.0.muzzy_npurge\0".name().mib()?,
+            nmadvise: b"stats.arenas.0.muzzy_nmadvise\0".name().mib()?,
+            purged: b"stats.arenas.0.muzzy_purged\0".name().mib()?,
+        })
+    }
+
+    fn read(&self, index: c_uint) -> Result<PurgeStats> {
+        Ok(PurgeStats {
+            npurge: read_indexed(self.npurge, index)?,
+            nmadvise: read_indexed(self.nmadvise, index)?,
+            purged: read_indexed(self.purged, index)?,
+        })
+    }
+}
+
+/// Cached MIBs of the keys of [`ArenaStats`].
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::{arenas, epoch, stats::ArenaStatsMibs};
+///
+/// let mibs = ArenaStatsMibs::new().unwrap();
+/// epoch::advance().unwrap();
+/// for i in 0..arenas::narenas::read().unwrap() {
+///     // Arenas that are not initialized have no statistics:
+///     if let Ok(stats) = mibs.read(i) {
+///         println!("arena {}: {} threads", i, stats.nthreads);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Copy, Clone)]
+pub struct ArenaStatsMibs {
+    nthreads: Mib<[usize; 4]>,
+    uptime: Mib<[usize; 4]>,
+    dss: MibStr<[usize; 4]>,
+    dirty_decay_ms: Mib<[usize; 4]>,
+    muzzy_decay_ms: Mib<[usize; 4]>,
+    pactive: Mib<[usize; 4]>,
+    pdirty: Mib<[usize; 4]>,
+    pmuzzy: Mib<[usize; 4]>,
+    mapped: Mib<[usize; 4]>,
+    retained: Mib<[usize; 4]>,
+    base: Mib<[usize; 4]>,
+    internal: Mib<[usize; 4]>,
+    metadata_thp: Mib<[usize; 4]>,
+    tcache_bytes: Mib<[usize; 4]>,
+    resident: Mib<[usize; 4]>,
+    abandoned_vm: Mib<[usize; 4]>,
+    small: AllocStatsMibs,
+    large: AllocStatsMibs,
+    dirty: PurgeStatsMibs,
+    muzzy: PurgeStatsMibs,
+}
+
+impl ArenaStatsMibs {
+    /// Looks up the MIBs of all the keys of [`ArenaStats`].
+    pub fn new() -> Result<Self> {
+        Ok(ArenaStatsMibs {
+            nthreads: b"stats.arenas.0.nthreads\0".name().mib()?,
+            uptime: b"stats.arenas.0.uptime\0".name().mib()?,
+            dss: b"stats.arenas.0.dss\0".name().mib_str()?,
+            dirty_decay_ms: b"stats.arenas.0.dirty_decay_ms\0".name().mib()?,
+            muzzy_decay_ms: b"stats.arenas.0.muzzy_decay_ms\0".name().mib()?,
+            pactive: b"stats.arenas.0.pactive\0".name().mib()?,
+            pdirty: b"stats.arenas.0.pdirty\0".name().mib()?,
+            pmuzzy: b"stats.arenas.0.pmuzzy\0".name().mib()?,
+            mapped: b"stats.arenas.0.mapped\0".name().mib()?,
+            retained: b"stats.arenas.0.retained\0".name().mib()?,
+            base: b"stats.arenas.0.base\0".name().mib()?,
+            internal: b"stats.arenas.0.internal\0".name().mib()?,
+            metadata_thp: b"stats.arenas.0.metadata_thp\0".name().mib()?,
+            tcache_bytes: b"stats.arenas.0.tcache_bytes\0".name().mib()?,
+            resident: b"stats.arenas.0.resident\0".name().mib()?,
+            abandoned_vm: b"stats.arenas.0.abandoned_vm\0".name().mib()?,
+            small: AllocStatsMibs::small()?,
+            large: AllocStatsMibs::large()?,
+            dirty: PurgeStatsMibs::dirty()?,
+            muzzy: PurgeStatsMibs::muzzy()?,
+        })
+    }
+
+    /// Reads the statistics of the arena with index `index`, see
+    /// [`ArenaStats::read`].
+    pub fn read(&self, index: c_uint) -> Result<ArenaStats> {
+        Ok(ArenaStats {
+            nthreads: read_indexed(self.nthreads, index)?,
+            uptime: read_indexed(self.uptime, index)?,
+            dss: read_indexed(self.dss, index)?,
+            dirty_decay_ms: read_indexed(self.dirty_decay_ms, index)?,
+            muzzy_decay_ms: read_indexed(self.muzzy_decay_ms, index)?,
+            pactive: read_indexed(self.pactive, index)?,
+            pdirty: read_indexed(self.pdirty, index)?,
+            pmuzzy: read_indexed(self.pmuzzy, index)?,
+            mapped: read_indexed(self.mapped, index)?,
+            retained: read_indexed(self.retained, index)?,
+            base: read_indexed(self.base, index)?,
+            internal: read_indexed(self.internal, index)?,
+            metadata_thp: read_indexed(self.metadata_thp, index)?,
+            tcache_bytes: read_indexed(self.tcache_bytes, index)?,
+            resident: read_indexed(self.resident, index)?,
+            abandoned_vm: read_indexed(self.abandoned_vm, index)?,
+            small: self.small.read(index)?,
+            large: self.large.read(index)?,
+            dirty: self.dirty.read(index)?,
+            muzzy: self.muzzy.read(index)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arena_stats() {
+        let arena = crate::arena::Arena::create().unwrap();
+        let flags = tikv_jemalloc_sys::MALLOCX_ARENA(arena.index() as usize)
+            | tikv_jemalloc_sys::MALLOCX_TCACHE_NONE;
+        let size = 1 << 20;
+        let ptr = unsafe { tikv_jemalloc_sys::mallocx(size, flags) };
+        assert!(!ptr.is_null());
+        crate::epoch::advance().unwrap();
+
+        let mibs = ArenaStatsMibs::new().unwrap();
+        let stats = mibs.read(arena.index()).unwrap();
+        assert_eq!(stats, ArenaStats::read(arena.index()).unwrap());
+        assert_eq!(stats.nthreads, 0);
+        assert_eq!(stats.dss, crate::opt::dss::read().unwrap());
+        assert_eq!(stats.large.allocated, size);
+        assert_eq!(stats.large.nmalloc, 1);
+        assert_eq!(stats.large.ndalloc, 0);
+        assert_eq!(stats.small, AllocStats::default());
+        assert!(stats.pactive * crate::arenas::page::read().unwrap() >= size);
+        assert!(stats.mapped >= size);
+
+        let merged = mibs.read(ArenaStats::MERGED).unwrap();
+        assert!(merged.nthreads > 0);
+        assert!(merged.large.allocated >= size);
+        assert!(merged.resident >= stats.resident);
+        assert!(merged.uptime >= stats.uptime);
+
+        unsafe { tikv_jemalloc_sys::sdallocx(ptr, size, flags) };
+        crate::epoch::advance().unwrap();
+        let stats = mibs.read(arena.index()).unwrap();
+        assert_eq!(stats.large.allocated, 0);
+        assert_eq!(stats.large.ndalloc, 1);
+
+        // The statistics of destroyed arenas are kept:
+        unsafe { arena.destroy().unwrap() };
+        crate::epoch::advance().unwrap();
+        let destroyed = mibs.read(ArenaStats::DESTROYED).unwrap();
+        assert!(destroyed.large.ndalloc >= 1);
+    }
+}
@@ -0,0 +1,256 @@
+//! Per-bin statistics (`stats.arenas.<i>.bins.<j>.*`).
+
+use super::arenas::read_indexed;
+use crate::arenas::{self, bin_nregs, bin_nregs_mib, bin_size, bin_size_mib};
+use crate::error::Result;
+use crate::keys::{Access, AsName, Mib};
+use libc::c_uint;
+
+/// Statistics of a bin, i.e. of a small size class, of an arena
+/// (`stats.arenas.<i>.bins.<j>.*`).
+///
+/// Like the global statistics, they are cached and only refreshed when the
+/// epoch is advanced, see [`crate::epoch`].
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::epoch;
+/// use tikv_jemalloc_ctl::stats::{ArenaBins, ArenaStats};
+///
+/// epoch::advance().unwrap();
+/// for bin in ArenaBins::new(ArenaStats::MERGED).unwrap() {
+///     let bin = bin.unwrap();
+///     if let Some(utilization) = bin.utilization() {
+///         println!("{} bytes: {:.0}% used", bin.size, utilization * 100.0);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BinStats {
+    /// Size of the regions of the bin (`arenas.bin.<j>.size`).
+    pub size: usize,
+    /// Number of regions per slab (`arenas.bin.<j>.nregs`).
+    pub nregs: u32,
+    /// Cumulative number of times a region was allocated from the bin,
+    /// either directly or to fill a thread cache.
+    pub nmalloc: u64,
+    /// Cumulative number of times a region was returned to the bin, either
+    /// directly or by flushing a thread cache.
+    pub ndalloc: u64,
+    /// Cumulative number of allocation requests, including the ones served
+    /// by a thread cache.
+    pub nrequests: u64,
+    /// Current number of regions of the bin in use.
+    pub curregs: usize,
+    /// Cumulative number of thread cache fills.
+    pub nfills: u64,
+    /// Cumulative number of thread cache flushes.
+    pub nflushes: u64,
+    /// Cumulative number of slabs created.
+    pub nslabs: u64,
+    /// Cumulative number of times the current slab was replaced by another
+    /// non-full slab.
+    pub nreslabs: u64,
+    /// Current number of slabs.
+    pub curslabs: usize,
+    /// Current number of slabs that are neither full nor empty.
+    pub nonfull_slabs: usize,
+}
+
+impl BinStats {
+    /// Reads the statistics of the bin with index `bin` of the arena with
+    /// index `arena`, which can be one of the pseudo-arenas of
+    /// [`ArenaStats`](super::ArenaStats).
+    ///
+    /// This looks up the MIBs of all the keys first: use [`BinStatsMibs`]
+    /// to read the statistics repeatedly.
+    pub fn read(arena: c_uint, bin: c_uint) -> Result<Self> {
+        BinStatsMibs::new()?.read(arena, bin)
+    }
+
+    /// Returns the fraction of the regions of the current slabs that are in
+    /// use, i.e. `curregs / (curslabs * nregs)`, or `None` if there are no
+    /// slabs.
+    ///
+    /// A low utilization means that the slabs of the bin are fragmented.
+    pub fn utilization(&self) -> Option<f64> {
+        let capacity = self.curslabs as f64 * f64::from(self.nregs);
+        if capacity > 0.0 {
+            Some(self.curregs as f64 / capacity)
+        } else {
+            None
+        }
+    }
+}
+
+/// Cached MIBs of the keys of [`BinStats`].
+#[derive(Copy, Clone)]
+pub struct BinStatsMibs {
+    size: bin_size_mib,
+    nregs: bin_nregs_mib,
+    nmalloc: Mib<[usize; 6]>,
+    ndalloc: Mib<[usize; 6]>,
+    nrequests: Mib<[usize; 6]>,
+    curregs: Mib<[usize; 6]>,
+    nfills: Mib<[usize; 6]>,
+    nflushes: Mib<[usize; 6]>,
+    nslabs: Mib<[usize; 6]>,
+    nreslabs: Mib<[usize; 6]>,
+    curslabs: Mib<[usize; 6]>,
+    nonfull_slabs: Mib<[usize; 6]>,
+}
+
+impl BinStatsMibs {
+    /// Looks up the MIBs of all the keys of [`BinStats`].
+    pub fn new() -> Result<Self> {
+        Ok(BinStatsMibs {
+            size: bin_size::mib()?,
+            nregs: bin_nregs::mib()?,
+            nmalloc: b"stats.arenas.0.bins.0.nmalloc\0".name().mib()?,
+            ndalloc: b"stats.arenas.0.bins.0.ndalloc\0".name().mib()?,
+            nrequests: b"stats.arenas.0.bins.0.nrequests\0".name().mib()?,
+            curregs: b"stats.arenas.0.bins.0.curregs\0".name().mib()?,
+            nfills: b"stats.arenas.0.bins.0.nfills\0".name().mib()?,
+            nflushes: b"stats.arenas.0.bins.0.nflushes\0".name().mib()?,
+            nslabs: b"stats.arenas.0.bins.0.nslabs\0".name().mib()?,
+            nreslabs: b"stats.arenas.0.bins.0.nreslabs\0".name().mib()?,
+            curslabs: b"stats.arenas.0.bins.0.curslabs\0".name().mib()?,
+            nonfull_slabs: b"stats.arenas.0.bins.0.nonfull_slabs\0"
+                .name()
+                .mib()?,
+        })
+    }
+
+    /// Reads the statistics of a bin of an arena, see [`BinStats::read`].
+    pub fn read(&self, arena: c_uint, bin: c_uint) -> Result<BinStats> {
+        arenas::check_bin(bin)?;
+        fn read<T>(
+            mut mib: Mib<[usize; 6]>,
+            arena: c_uint,
+            bin: c_uint,
+        ) -> Result<T>
+        where
+            Mib<[usize; 6]>: Access<T>,
+        {
+            mib[4] = bin as usize;
+            read_indexed(mib, arena)
+        }
+        Ok(BinStats {
+            size: self.size.read(bin)?,
+            nregs: self.nregs.read(bin)?,
+            nmalloc: read(self.nmalloc, arena, bin)?,
+            ndalloc: read(self.ndalloc, arena, bin)?,
+            nrequests: read(self.nrequests, arena, bin)?,
+            curregs: read(self.curregs, arena, bin)?,
+            nfills: read(self.nfills, arena, bin)?,
+            nflushes: read(self.nflushes, arena, bin)?,
+            nslabs: read(self.nslabs, arena, bin)?,
+            nreslabs: read(self.nreslabs, arena, bin)?,
+            curslabs: read(self.curslabs, arena, bin)?,
+            nonfull_slabs: read(self.nonfull_slabs, arena, bin)?,
+        })
+    }
+}
+
+/// Iterator over the statistics of all bins of an arena, from the smallest
+/// size class to the largest.
+#[derive(Clone)]
+pub struct ArenaBins {
+    mibs: BinStatsMibs,
+    arena: c_uint,
+    nbins: c_uint,
+    next: c_uint,
+}
+
+impl ArenaBins {
+    /// Returns an iterator over the statistics of the bins of the arena with
+    /// index `arena`, which can be one of the pseudo-arenas of
+    /// [`ArenaStats`](super::ArenaStats).
+    pub fn new(arena: c_uint) -> Result<Self> {
+        Ok(ArenaBins {
+            mibs: BinStatsMibs::new()?,
+            arena,
+            nbins: arenas::nbins::read()?,
+            next: 0,
+        })
+    }
+}
+
+impl Iterator for ArenaBins {
+    type Item = Result<BinStats>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.nbins {
+            return None;
+        }
+        let stats = self.mibs.read(self.arena, self.next);
+        self.next += 1;
+        Some(stats)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.nbins - self.next) as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for ArenaBins {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::ArenaStats;
+
+    #[test]
+    fn bin_stats() {
+        let arena = crate::arena::Arena::create().unwrap();
+        let flags = tikv_jemalloc_sys::MALLOCX_ARENA(arena.index() as usize)
+            | tikv_jemalloc_sys::MALLOCX_TCACHE_NONE;
+        let bin = 3;
+        let size = bin_size::read(bin).unwrap();
+        let ptrs: [_; 3] = core::array::from_fn(|_| unsafe {
+            tikv_jemalloc_sys::mallocx(size, flags)
+        });
+        crate::epoch::advance().unwrap();
+
+        let stats = BinStats::read(arena.index(), bin).unwrap();
+        assert_eq!(stats.size, size);
+        assert_eq!(stats.nregs, bin_nregs::read(bin).unwrap());
+        assert_eq!(stats.nmalloc, 3);
+        assert_eq!(stats.ndalloc, 0);
+        assert_eq!(stats.curregs, 3);
+        assert_eq!(stats.curslabs, 1);
+        assert_eq!(stats.nslabs, 1);
+        assert_eq!(stats.utilization(), Some(3.0 / f64::from(stats.nregs)));
+
+        let nbins = arenas::nbins::read().unwrap();
+        assert!(BinStats::read(ArenaStats::MERGED, nbins - 1).is_ok());
+        assert!(BinStats::read(ArenaStats::MERGED, nbins).is_err());
+
+        let bins = ArenaBins::new(arena.index()).unwrap();
+        assert_eq!(bins.len(), nbins as usize);
+        for (i, other) in bins.enumerate() {
+            let other = other.unwrap();
+            if i == bin as usize {
+                assert_eq!(other, stats);
+            } else {
+                assert_eq!(other.curregs, 0);
+                assert_eq!(other.utilization(), None);
+            }
+        }
+
+        for ptr in ptrs {
+            unsafe { tikv_jemalloc_sys::sdallocx(ptr, size, flags) };
+        }
+        crate::epoch::advance().unwrap();
+        let stats = BinStats::read(arena.index(), bin).unwrap();
+        assert_eq!(stats.ndalloc, 3);
+        assert_eq!(stats.curregs, 0);
+    }
+}
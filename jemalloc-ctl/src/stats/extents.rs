@@ -0,0 +1,444 @@
+//! Per-size statistics of the large allocations and of the unused extents
+//! (`stats.arenas.<i>.lextents.<j>.*` and `stats.arenas.<i>.extents.<j>.*`).
+
+use super::arenas::read_indexed;
+use crate::arenas::{
+    self, bin_size, bin_size_mib, lextent_size, lextent_size_mib,
+};
+use crate::error::Result;
+use crate::keys::{Access, AsName, Mib};
+use libc::c_uint;
+
+/// Reads the key of `mib` for the size class with index `class` of the arena
+/// with index `arena`.
+fn read_class<T>(
+    mut mib: Mib<[usize; 6]>,
+    arena: c_uint,
+    class: c_uint,
+) -> Result<T>
+where
+    Mib<[usize; 6]>: Access<T>,
+{
+    mib[4] = class as usize;
+    read_indexed(mib, arena)
+}
+
+/// Statistics of a large size class of an arena
+/// (`stats.arenas.<i>.lextents.<j>.*`).
+///
+/// Like the global statistics, they are cached and only refreshed when the
+/// epoch is advanced, see [`crate::epoch`].
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::epoch;
+/// use tikv_jemalloc_ctl::stats::{ArenaLextents, ArenaStats};
+///
+/// epoch::advance().unwrap();
+/// for lextent in ArenaLextents::new(ArenaStats::MERGED).unwrap() {
+///     let lextent = lextent.unwrap();
+///     if lextent.curlextents > 0 {
+///         println!("{} bytes: {} allocations", lextent.size, lextent.curlextents);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct LextentStats {
+    /// Size of the size class (`arenas.lextent.<j>.size`).
+    pub size: usize,
+    /// Cumulative number of times an allocation of this size class was
+    /// served by the arena.
+    pub nmalloc: u64,
+    /// Cumulative number of times an allocation of this size class was
+    /// returned to the arena.
+    pub ndalloc: u64,
+    /// Cumulative number of allocation requests, including the ones served
+    /// by a thread cache.
+    pub nrequests: u64,
+    /// Current number of allocations of this size class.
+    pub curlextents: usize,
+}
+
+impl LextentStats {
+    /// Reads the statistics of the large size class with index `lextent` of
+    /// the arena with index `arena`, which can be one of the pseudo-arenas of
+    /// [`ArenaStats`](super::ArenaStats).
+    ///
+    /// This looks up the MIBs of all the keys first: use
+    /// [`LextentStatsMibs`] to read the statistics repeatedly.
+    pub fn read(arena: c_uint, lextent: c_uint) -> Result<Self> {
+        LextentStatsMibs::new()?.read(arena, lextent)
+    }
+}
+
+/// Cached MIBs of the keys of [`LextentStats`].
+#[derive(Copy, Clone)]
+pub struct LextentStatsMibs {
+    size: lextent_size_mib,
+    nmalloc: Mib<[usize; 6]>,
+    ndalloc: Mib<[usize; 6]>,
+    nrequests: Mib<[usize; 6]>,
+    curlextents: Mib<[usize; 6]>,
+}
+
+impl LextentStatsMibs {
+    /// Looks up the MIBs of all the keys of [`LextentStats`].
+    pub fn new() -> Result<Self> {
+        Ok(LextentStatsMibs {
+            size: lextent_size::mib()?,
+            nmalloc: b"stats.arenas.0.lextents.0.nmalloc\0".name().mib()?,
+            ndalloc: b"stats.arenas.0.lextents.0.ndalloc\0".name().mib()?,
+            nrequests: b"stats.arenas.0.lextents.0.nrequests\0"
+                .name()
+                .mib()?,
+            curlextents: b"stats.arenas.0.lextents.0.curlextents\0"
+                .name()
+                .mib()?,
+        })
+    }
+
+    /// Reads the statistics of a large size class of an arena, see
+    /// [`LextentStats::read`].
+    pub fn read(
+        &self,
+        arena: c_uint,
+        lextent: c_uint,
+    ) -> Result<LextentStats> {
+        arenas::check_lextent(lextent)?;
+        Ok(LextentStats {
+            size: self.size.read(lextent)?,
+            nmalloc: read_class(self.nmalloc, arena, lextent)?,
+            ndalloc: read_class(self.ndalloc, arena, lextent)?,
+            nrequests: read_class(self.nrequests, arena, lextent)?,
+            curlextents: read_class(self.curlextents, arena, lextent)?,
+        })
+    }
+}
+
+/// Iterator over the statistics of all large size classes of an arena, from
+/// the smallest to the largest.
+#[derive(Clone)]
+pub struct ArenaLextents {
+    mibs: LextentStatsMibs,
+    arena: c_uint,
+    nlextents: c_uint,
+    next: c_uint,
+}
+
+impl ArenaLextents {
+    /// Returns an iterator over the statistics of the large size classes of
+    /// the arena with index `arena`, which can be one of the pseudo-arenas of
+    /// [`ArenaStats`](super::ArenaStats).
+    pub fn new(arena: c_uint) -> Result<Self> {
+        Ok(ArenaLextents {
+            mibs: LextentStatsMibs::new()?,
+            arena,
+            nlextents: arenas::nlextents::read()?,
+            next: 0,
+        })
+    }
+}
+
+impl Iterator for ArenaLextents {
+    type Item = Result<LextentStats>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.nlextents {
+            return None;
+        }
+        let stats = self.mibs.read(self.arena, self.next);
+        self.next += 1;
+        Some(stats)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.nlextents - self.next) as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for ArenaLextents {}
+
+/// Statistics of the unused extents of a page size class of an arena
+/// (`stats.arenas.<i>.extents.<j>.*`).
+///
+/// The unused extents are kept in pools indexed by page size class, i.e. by
+/// the size classes that are multiples of the page size, from the smallest to
+/// the largest. Extent sizes are quantized to these size classes, so the
+/// extents of a pool can be somewhat smaller or larger than its size, which
+/// is the one reported by [`crate::stats_print`].
+///
+/// Like the global statistics, they are cached and only refreshed when the
+/// epoch is advanced, see [`crate::epoch`].
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::epoch;
+/// use tikv_jemalloc_ctl::stats::{ArenaExtents, ArenaStats};
+///
+/// epoch::advance().unwrap();
+/// for extents in ArenaExtents::new(ArenaStats::MERGED).unwrap() {
+///     let extents = extents.unwrap();
+///     if extents.dirty_bytes > 0 {
+///         println!("{} bytes: {} dirty bytes", extents.size, extents.dirty_bytes);
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ExtentStats {
+    /// Size of the page size class.
+    pub size: usize,
+    /// Number of dirty extents, i.e. unused but not purged.
+    pub ndirty: usize,
+    /// Number of muzzy extents, i.e. lazily purged.
+    pub nmuzzy: usize,
+    /// Number of retained extents, i.e. purged but still mapped.
+    pub nretained: usize,
+    /// Number of bytes in dirty extents.
+    pub dirty_bytes: usize,
+    /// Number of bytes in muzzy extents.
+    pub muzzy_bytes: usize,
+    /// Number of bytes in retained extents.
+    pub retained_bytes: usize,
+}
+
+impl ExtentStats {
+    /// Reads the statistics of the unused extents of the page size class with
+    /// index `pszind` of the arena with index `arena`, which can be one of the
+    /// pseudo-arenas of [`ArenaStats`](super::ArenaStats).
+    ///
+    /// This looks up the MIBs of all the keys and the size classes first: use
+    /// [`ArenaExtents`] to read the statistics of all page size classes.
+    pub fn read(arena: c_uint, pszind: c_uint) -> Result<Self> {
+        // This fails unless `pszind` is the index of a page size class:
+        let mut stats = ExtentStatsMibs::new()?.read(arena, pszind, 0)?;
+        if let Some(size) = PageSizeClasses::new()?.nth(pszind as usize) {
+            stats.size = size?;
+        }
+        Ok(stats)
+    }
+}
+
+/// Cached MIBs of the keys of [`ExtentStats`].
+#[derive(Copy, Clone)]
+struct ExtentStatsMibs {
+    ndirty: Mib<[usize; 6]>,
+    nmuzzy: Mib<[usize; 6]>,
+    nretained: Mib<[usize; 6]>,
+    dirty_bytes: Mib<[usize; 6]>,
+    muzzy_bytes: Mib<[usize; 6]>,
+    retained_bytes: Mib<[usize; 6]>,
+}
+
+impl ExtentStatsMibs {
+    fn new() -> Result<Self> {
+        Ok(ExtentStatsMibs {
+            ndirty: b"stats.arenas.0.extents.0.ndirty\0".name().mib()?,
+            nmuzzy: b"stats.arenas.0.extents.0.nmuzzy\0".name().mib()?,
+            nretained: b"stats.arenas.0.extents.0.nretained\0".name().mib()?,
+            dirty_bytes: b"stats.arenas.0.extents.0.dirty_bytes\0"
+                .name()
+                .mib()?,
+            muzzy_bytes: b"stats.arenas.0.extents.0.muzzy_bytes\0"
+                .name()
+                .mib()?,
+            retained_bytes: b"stats.arenas.0.extents.0.retained_bytes\0"
+                .name()
+                .mib()?,
+        })
+    }
+
+    fn read(
+        &self,
+        arena: c_uint,
+        pszind: c_uint,
+        size: usize,
+    ) -> Result<ExtentStats> {
+        Ok(ExtentStats {
+            size,
+            ndirty: read_class(self.ndirty, arena, pszind)?,
+            nmuzzy: read_class(self.nmuzzy, arena, pszind)?,
+            nretained: read_class(self.nretained, arena, pszind)?,
+            dirty_bytes: read_class(self.dirty_bytes, arena, pszind)?,
+            muzzy_bytes: read_class(self.muzzy_bytes, arena, pszind)?,
+            retained_bytes: read_class(self.retained_bytes, arena, pszind)?,
+        })
+    }
+}
+
+/// Iterator over the sizes of the page size classes, i.e. of the size
+/// classes that are multiples of the page size.
+#[derive(Clone)]
+struct PageSizeClasses {
+    page: usize,
+    nbins: c_uint,
+    nclasses: c_uint,
+    next: c_uint,
+    bin_size: bin_size_mib,
+    lextent_size: lextent_size_mib,
+}
+
+impl PageSizeClasses {
+    fn new() -> Result<Self> {
+        let nbins = arenas::nbins::read()?;
+        Ok(PageSizeClasses {
+            page: arenas::page::read()?,
+            nbins,
+            nclasses: nbins + arenas::nlextents::read()?,
+            next: 0,
+            bin_size: bin_size::mib()?,
+            lextent_size: lextent_size::mib()?,
+        })
+    }
+}
+
+impl Iterator for PageSizeClasses {
+    type Item = Result<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.nclasses {
+            let index = self.next;
+            self.next += 1;
+            let size = if index < self.nbins {
+                self.bin_size.read(index)
+            } else {
+                self.lextent_size.read(index - self.nbins)
+            };
+            match size {
+                Ok(size) if size % self.page != 0 => continue,
+                size => return Some(size),
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over the statistics of the unused extents of all page size
+/// classes of an arena, from the smallest to the largest.
+#[derive(Clone)]
+pub struct ArenaExtents {
+    mibs: ExtentStatsMibs,
+    sizes: PageSizeClasses,
+    arena: c_uint,
+    next: c_uint,
+}
+
+impl ArenaExtents {
+    /// Returns an iterator over the statistics of the unused extents of the
+    /// arena with index `arena`, which can be one of the pseudo-arenas of
+    /// [`ArenaStats`](super::ArenaStats).
+    pub fn new(arena: c_uint) -> Result<Self> {
+        Ok(ArenaExtents {
+            mibs: ExtentStatsMibs::new()?,
+            sizes: PageSizeClasses::new()?,
+            arena,
+            next: 0,
+        })
+    }
+}
+
+impl Iterator for ArenaExtents {
+    type Item = Result<ExtentStats>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = self.sizes.next()?;
+        let pszind = self.next;
+        self.next += 1;
+        Some(size.and_then(|size| self.mibs.read(self.arena, pszind, size)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::ArenaStats;
+
+    #[test]
+    fn lextent_stats() {
+        let arena = crate::arena::Arena::create().unwrap();
+        let flags = tikv_jemalloc_sys::MALLOCX_ARENA(arena.index() as usize)
+            | tikv_jemalloc_sys::MALLOCX_TCACHE_NONE;
+        let lextent = 2;
+        let size = lextent_size::read(lextent).unwrap();
+        let ptr = unsafe { tikv_jemalloc_sys::mallocx(size, flags) };
+        assert!(!ptr.is_null());
+        crate::epoch::advance().unwrap();
+
+        let stats = LextentStats::read(arena.index(), lextent).unwrap();
+        assert_eq!(
+            stats,
+            LextentStats {
+                size,
+                nmalloc: 1,
+                ndalloc: 0,
+                nrequests: 1,
+                curlextents: 1,
+            }
+        );
+        let nlextents = arenas::nlextents::read().unwrap();
+        assert!(LextentStats::read(ArenaStats::MERGED, nlextents - 1).is_ok());
+        assert!(LextentStats::read(ArenaStats::MERGED, nlextents).is_err());
+
+        let lextents = ArenaLextents::new(arena.index()).unwrap();
+        assert_eq!(lextents.len(), nlextents as usize);
+        let live: usize = lextents.map(|l| l.unwrap().curlextents).sum();
+        assert_eq!(live, 1);
+
+        unsafe { tikv_jemalloc_sys::sdallocx(ptr, size, flags) };
+        crate::epoch::advance().unwrap();
+        let stats = LextentStats::read(arena.index(), lextent).unwrap();
+        assert_eq!(stats.ndalloc, 1);
+        assert_eq!(stats.curlextents, 0);
+    }
+
+    #[test]
+    fn extent_stats() {
+        let page = arenas::page::read().unwrap();
+        let arena = crate::arena::Arena::create().unwrap();
+        arena.set_dirty_decay_ms(-1).unwrap();
+        let flags = tikv_jemalloc_sys::MALLOCX_ARENA(arena.index() as usize)
+            | tikv_jemalloc_sys::MALLOCX_TCACHE_NONE;
+        let size = 1 << 20;
+        unsafe {
+            let ptr = tikv_jemalloc_sys::mallocx(size, flags);
+            assert!(!ptr.is_null());
+            tikv_jemalloc_sys::sdallocx(ptr, size, flags);
+        }
+        crate::epoch::advance().unwrap();
+
+        let mut prev = 0;
+        let mut dirty_bytes = 0;
+        let mut npsizes = 0;
+        for extents in ArenaExtents::new(arena.index()).unwrap() {
+            npsizes += 1;
+            let extents = extents.unwrap();
+            assert!(extents.size > prev);
+            assert_eq!(extents.size % page, 0);
+            prev = extents.size;
+            assert_eq!(extents.ndirty == 0, extents.dirty_bytes == 0);
+            assert_eq!(extents.dirty_bytes % page, 0);
+            dirty_bytes += extents.dirty_bytes;
+        }
+        assert!(dirty_bytes >= size);
+        let stats = crate::stats::ArenaStats::read(arena.index()).unwrap();
+        assert_eq!(dirty_bytes, stats.pdirty * page);
+
+        let first = ExtentStats::read(arena.index(), 0).unwrap();
+        assert_eq!(first.size, page);
+        // Every page size class of `jemalloc` is visited:
+        assert!(ExtentStats::read(arena.index(), npsizes - 1).is_ok());
+        assert!(ExtentStats::read(arena.index(), npsizes).is_err());
+    }
+}
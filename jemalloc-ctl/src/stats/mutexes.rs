@@ -0,0 +1,433 @@
+//! Lock profiling statistics of the jemalloc mutexes (`stats.mutexes.*`,
+//! `stats.arenas.<i>.mutexes.*` and `stats.arenas.<i>.bins.<j>.mutex.*`).
+
+use crate::error::Result;
+use crate::keys::{Access, AsName, Mib};
+use crate::ops::IndexMut;
+use libc::c_uint;
+
+/// A global jemalloc mutex (`stats.mutexes.<name>`).
+///
+/// The mutexes of disabled features, e.g. the `prof*` ones when profiling is
+/// disabled, have no statistics and read as zero.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GlobalMutex {
+    /// Protects the state of the background threads.
+    BackgroundThread,
+    /// Maximum of the mutexes of the background threads.
+    MaxPerBgThd,
+    /// Serializes the `mallctl` calls.
+    Ctl,
+    /// Protects the backtrace table of the profiler.
+    Prof,
+    /// Protects the per-thread data of the profiler.
+    ProfThdsData,
+    /// Serializes the heap profile dumps.
+    ProfDump,
+    /// Protects the recent allocation records.
+    ProfRecentAlloc,
+    /// Serializes the dumps of the recent allocation records.
+    ProfRecentDump,
+    /// Protects the per-size-class profiling statistics.
+    ProfStats,
+}
+
+impl GlobalMutex {
+    /// All global mutexes, in the order of `stats.mutexes`.
+    pub const ALL: [GlobalMutex; 9] = [
+        GlobalMutex::BackgroundThread,
+        GlobalMutex::MaxPerBgThd,
+        GlobalMutex::Ctl,
+        GlobalMutex::Prof,
+        GlobalMutex::ProfThdsData,
+        GlobalMutex::ProfDump,
+        GlobalMutex::ProfRecentAlloc,
+        GlobalMutex::ProfRecentDump,
+        GlobalMutex::ProfStats,
+    ];
+
+    /// Returns the name of the mutex in the _MALLCTL NAMESPACE_.
+    pub fn name(self) -> &'static str {
+        match self {
+            GlobalMutex::BackgroundThread => "background_thread",
+            GlobalMutex::MaxPerBgThd => "max_per_bg_thd",
+            GlobalMutex::Ctl => "ctl",
+            GlobalMutex::Prof => "prof",
+            GlobalMutex::ProfThdsData => "prof_thds_data",
+            GlobalMutex::ProfDump => "prof_dump",
+            GlobalMutex::ProfRecentAlloc => "prof_recent_alloc",
+            GlobalMutex::ProfRecentDump => "prof_recent_dump",
+            GlobalMutex::ProfStats => "prof_stats",
+        }
+    }
+}
+
+/// A mutex of each arena (`stats.arenas.<i>.mutexes.<name>`).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ArenaMutex {
+    /// Protects the list of the large allocations.
+    Large,
+    /// Protects the cache of unused extent metadata.
+    ExtentAvail,
+    /// Protects the dirty extents.
+    ExtentsDirty,
+    /// Protects the muzzy extents.
+    ExtentsMuzzy,
+    /// Protects the retained extents.
+    ExtentsRetained,
+    /// Protects the decay state of the dirty pages.
+    DecayDirty,
+    /// Protects the decay state of the muzzy pages.
+    DecayMuzzy,
+    /// Protects the metadata allocator.
+    Base,
+    /// Protects the list of the thread caches of the arena.
+    TcacheList,
+    /// Protects the huge page allocator.
+    HpaShard,
+    /// Serializes the growth of the huge page allocator.
+    HpaShardGrow,
+    /// Protects the small extent cache of the huge page allocator.
+    HpaSec,
+}
+
+impl ArenaMutex {
+    /// All arena mutexes, in the order of `stats.arenas.<i>.mutexes`.
+    pub const ALL: [ArenaMutex; 12] = [
+        ArenaMutex::Large,
+        ArenaMutex::ExtentAvail,
+        ArenaMutex::ExtentsDirty,
+        ArenaMutex::ExtentsMuzzy,
+        ArenaMutex::ExtentsRetained,
+        ArenaMutex::DecayDirty,
+        ArenaMutex::DecayMuzzy,
+        ArenaMutex::Base,
+        ArenaMutex::TcacheList,
+        ArenaMutex::HpaShard,
+        ArenaMutex::HpaShardGrow,
+        ArenaMutex::HpaSec,
+    ];
+
+    /// Returns the name of the mutex in the _MALLCTL NAMESPACE_.
+    pub fn name(self) -> &'static str {
+        match self {
+            ArenaMutex::Large => "large",
+            ArenaMutex::ExtentAvail => "extent_avail",
+            ArenaMutex::ExtentsDirty => "extents_dirty",
+            ArenaMutex::ExtentsMuzzy => "extents_muzzy",
+            ArenaMutex::ExtentsRetained => "extents_retained",
+            ArenaMutex::DecayDirty => "decay_dirty",
+            ArenaMutex::DecayMuzzy => "decay_muzzy",
+            ArenaMutex::Base => "base",
+            ArenaMutex::TcacheList => "tcache_list",
+            ArenaMutex::HpaShard => "hpa_shard",
+            ArenaMutex::HpaShardGrow => "hpa_shard_grow",
+            ArenaMutex::HpaSec => "hpa_sec",
+        }
+    }
+}
+
+/// Lock profiling statistics of a mutex.
+///
+/// Like the other statistics, they are cached and only refreshed when the
+/// epoch is advanced, see [`crate::epoch`]. They are cleared by
+/// [`mutexes_reset`].
+///
+/// # Examples
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::epoch;
+/// use tikv_jemalloc_ctl::stats::{ArenaMutex, ArenaStats, MutexStats};
+///
+/// epoch::advance().unwrap();
+/// for mutex in ArenaMutex::ALL {
+///     let stats = MutexStats::read_arena(ArenaStats::MERGED, mutex).unwrap();
+///     println!(
+///         "{}: {} of {} lock operations waited",
+///         mutex.name(),
+///         stats.num_wait,
+///         stats.num_ops
+///     );
+/// }
+/// # }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct MutexStats {
+    /// Number of lock operations.
+    pub num_ops: u64,
+    /// Number of lock operations that had to wait, i.e. that did not
+    /// acquire the mutex by spinning.
+    pub num_wait: u64,
+    /// Number of lock operations that acquired the mutex by spinning.
+    pub num_spin_acq: u64,
+    /// Number of times the mutex was acquired by another thread than its
+    /// previous owner.
+    pub num_owner_switch: u64,
+    /// Total time spent waiting for the mutex, in nanoseconds.
+    pub total_wait_time: u64,
+    /// Longest time spent waiting for the mutex by a lock operation, in
+    /// nanoseconds.
+    pub max_wait_time: u64,
+    /// Maximum number of threads waiting for the mutex at the same time.
+    pub max_num_thds: u32,
+}
+
+impl MutexStats {
+    /// Reads the statistics of a global mutex.
+    ///
+    /// This looks up the MIBs of all the keys first: use [`MutexStatsMibs`]
+    /// to read the statistics repeatedly.
+    pub fn read_global(mutex: GlobalMutex) -> Result<Self> {
+        MutexStatsMibs::new()?.read_global(mutex)
+    }
+
+    /// Reads the statistics of a mutex of the arena with index `arena`,
+    /// which can be one of the pseudo-arenas of
+    /// [`ArenaStats`](super::ArenaStats).
+    pub fn read_arena(arena: c_uint, mutex: ArenaMutex) -> Result<Self> {
+        MutexStatsMibs::new()?.read_arena(arena, mutex)
+    }
+
+    /// Reads the statistics of the mutex of the bin with index `bin` of the
+    /// arena with index `arena`, merged over the shards of the bin.
+    pub fn read_bin(arena: c_uint, bin: c_uint) -> Result<Self> {
+        MutexStatsMibs::new()?.read_bin(arena, bin)
+    }
+}
+
+/// MIBs of the counters of a mutex.
+#[derive(Copy, Clone)]
+struct CounterMibs<M> {
+    num_ops: M,
+    num_wait: M,
+    num_spin_acq: M,
+    num_owner_switch: M,
+    total_wait_time: M,
+    max_wait_time: M,
+    max_num_thds: M,
+}
+
+/// Looks up the MIBs of the counters of the mutex with key prefix `$prefix`.
+macro_rules! counter_mibs {
+    ($prefix:literal) => {
+        CounterMibs {
+            num_ops: concat!($prefix, "num_ops\0").as_bytes().name().mib()?,
+            num_wait: concat!($prefix, "num_wait\0")
+                .as_bytes()
+                .name()
+                .mib()?,
+            num_spin_acq: concat!($prefix, "num_spin_acq\0")
+                .as_bytes()
+                .name()
+                .mib()?,
+            num_owner_switch: concat!($prefix, "num_owner_switch\0")
+                .as_bytes()
+                .name()
+                .mib()?,
+            total_wait_time: concat!($prefix, "total_wait_time\0")
+                .as_bytes()
+                .name()
+                .mib()?,
+            max_wait_time: concat!($prefix, "max_wait_time\0")
+                .as_bytes()
+                .name()
+                .mib()?,
+            max_num_thds: concat!($prefix, "max_num_thds\0")
+                .as_bytes()
+                .name()
+                .mib()?,
+        }
+    };
+}
+
+impl<M> CounterMibs<M>
+where
+    M: Copy + IndexMut<usize, Output = usize> + Access<u64> + Access<u32>,
+{
+    /// Reads the counters after setting the MIB components of `indices`,
+    /// given as `(position, index)` pairs.
+    fn read(mut self, indices: &[(usize, usize)]) -> Result<MutexStats> {
+        for mib in [
+            &mut self.num_ops,
+            &mut self.num_wait,
+            &mut self.num_spin_acq,
+            &mut self.num_owner_switch,
+            &mut self.total_wait_time,
+            &mut self.max_wait_time,
+            &mut self.max_num_thds,
+        ] {
+            for &(position, index) in indices {
+                mib[position] = index;
+            }
+        }
+        Ok(MutexStats {
+            num_ops: self.num_ops.read()?,
+            num_wait: self.num_wait.read()?,
+            num_spin_acq: self.num_spin_acq.read()?,
+            num_owner_switch: self.num_owner_switch.read()?,
+            total_wait_time: self.total_wait_time.read()?,
+            max_wait_time: self.max_wait_time.read()?,
+            max_num_thds: self.max_num_thds.read()?,
+        })
+    }
+}
+
+/// Cached MIBs of the keys of [`MutexStats`].
+#[derive(Copy, Clone)]
+pub struct MutexStatsMibs {
+    global: CounterMibs<Mib<[usize; 4]>>,
+    arena: CounterMibs<Mib<[usize; 6]>>,
+    bin: CounterMibs<Mib<[usize; 7]>>,
+}
+
+impl MutexStatsMibs {
+    /// Looks up the MIBs of all the keys of [`MutexStats`].
+    pub fn new() -> Result<Self> {
+        Ok(MutexStatsMibs {
+            global: counter_mibs!("stats.mutexes.ctl."),
+            arena: counter_mibs!("stats.arenas.0.mutexes.large."),
+            bin: counter_mibs!("stats.arenas.0.bins.0.mutex."),
+        })
+    }
+
+    /// Reads the statistics of a global mutex, see
+    /// [`MutexStats::read_global`].
+    pub fn read_global(&self, mutex: GlobalMutex) -> Result<MutexStats> {
+        // The mutexes are the children of `stats.mutexes`, in order:
+        self.global.read(&[(2, mutex as usize)])
+    }
+
+    /// Reads the statistics of a mutex of an arena, see
+    /// [`MutexStats::read_arena`].
+    pub fn read_arena(
+        &self,
+        arena: c_uint,
+        mutex: ArenaMutex,
+    ) -> Result<MutexStats> {
+        self.arena.read(&[(2, arena as usize), (4, mutex as usize)])
+    }
+
+    /// Reads the statistics of the mutex of a bin of an arena, see
+    /// [`MutexStats::read_bin`].
+    pub fn read_bin(&self, arena: c_uint, bin: c_uint) -> Result<MutexStats> {
+        crate::arenas::check_bin(bin)?;
+        self.bin.read(&[(2, arena as usize), (4, bin as usize)])
+    }
+}
+
+option! {
+    mutexes_reset[ str: b"stats.mutexes.reset\0", non_str: 3 ] => () |
+    ops: c |
+    docs:
+    /// Clears the statistics of all mutexes: the global ones, the ones of
+    /// the arenas and the ones of the bins.
+    ///
+    /// This is useful to measure the lock contention of a workload.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::epoch;
+    /// use tikv_jemalloc_ctl::stats::{mutexes_reset, GlobalMutex, MutexStats};
+    ///
+    /// mutexes_reset::call().unwrap();
+    /// // Run the workload...
+    /// epoch::advance().unwrap();
+    /// let ctl = MutexStats::read_global(GlobalMutex::Ctl).unwrap();
+    /// println!("{} waits on the ctl mutex", ctl.num_wait);
+    /// # }
+    /// ```
+    mib_docs: /// See [`mutexes_reset`].
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw;
+
+    /// Checks that the mutex at component `position` of `mib` is the one
+    /// named `name`, by looking up `<prefix><name>.num_ops`.
+    fn assert_named(mib: &[usize], prefix: &str, name: &str, position: usize) {
+        let mut key = [0_u8; 64];
+        let suffix = ".num_ops\0";
+        let len = prefix.len() + name.len() + suffix.len();
+        key[..prefix.len()].copy_from_slice(prefix.as_bytes());
+        key[prefix.len()..prefix.len() + name.len()]
+            .copy_from_slice(name.as_bytes());
+        key[prefix.len() + name.len()..len].copy_from_slice(suffix.as_bytes());
+        let mut expected = [0; 6];
+        let expected = &mut expected[..mib.len()];
+        raw::name_to_mib(&key[..len], expected).unwrap();
+        assert_eq!(expected[position], mib[position], "{}", name);
+    }
+
+    #[test]
+    fn mutex_names() {
+        let mibs = MutexStatsMibs::new().unwrap();
+        for mutex in GlobalMutex::ALL {
+            let mut mib = mibs.global.num_ops;
+            mib[2] = mutex as usize;
+            assert_named(mib.as_ref(), "stats.mutexes.", mutex.name(), 2);
+        }
+        for mutex in ArenaMutex::ALL {
+            let mut mib = mibs.arena.num_ops;
+            mib[4] = mutex as usize;
+            assert_named(
+                mib.as_ref(),
+                "stats.arenas.0.mutexes.",
+                mutex.name(),
+                4,
+            );
+        }
+    }
+
+    #[test]
+    fn mutex_stats() {
+        let arena = crate::arena::Arena::create().unwrap();
+        let flags = tikv_jemalloc_sys::MALLOCX_ARENA(arena.index() as usize)
+            | tikv_jemalloc_sys::MALLOCX_TCACHE_NONE;
+        let (small, large) = (8, 1 << 20);
+        let small_ptr = unsafe { tikv_jemalloc_sys::mallocx(small, flags) };
+        let large_ptr = unsafe { tikv_jemalloc_sys::mallocx(large, flags) };
+        crate::epoch::advance().unwrap();
+
+        let mibs = MutexStatsMibs::new().unwrap();
+        let ctl = mibs.read_global(GlobalMutex::Ctl).unwrap();
+        assert!(ctl.num_ops > 0);
+        assert!(ctl.num_wait <= ctl.num_ops);
+        assert!(ctl.max_wait_time <= ctl.total_wait_time);
+        let again = MutexStats::read_global(GlobalMutex::Ctl).unwrap();
+        assert_eq!(again.num_ops, ctl.num_ops);
+
+        let large_mutex =
+            mibs.read_arena(arena.index(), ArenaMutex::Large).unwrap();
+        assert!(large_mutex.num_ops > 0);
+        let bin = mibs.read_bin(arena.index(), 0).unwrap();
+        assert!(bin.num_ops > 0);
+        assert_eq!(MutexStats::read_bin(arena.index(), 0).unwrap(), bin);
+        let nbins = crate::arenas::nbins::read().unwrap();
+        assert!(mibs.read_bin(arena.index(), nbins - 1).is_ok());
+        assert!(mibs.read_bin(arena.index(), nbins).is_err());
+
+        unsafe {
+            tikv_jemalloc_sys::sdallocx(small_ptr, small, flags);
+            tikv_jemalloc_sys::sdallocx(large_ptr, large, flags);
+        }
+        mutexes_reset::call().unwrap();
+        crate::epoch::advance().unwrap();
+        // Refreshing the statistics locks the mutexes of the arena, but the
+        // allocations are no longer counted:
+        let reset = mibs.read_arena(arena.index(), ArenaMutex::Large).unwrap();
+        assert!(reset.num_ops < large_mutex.num_ops);
+        let reset = mibs.read_bin(arena.index(), 0).unwrap();
+        assert!(reset.num_ops < bin.num_ops);
+    }
+}
@@ -10,6 +10,7 @@ use std::panic::{self, AssertUnwindSafe};
 ///
 /// All options default to `false`.
 #[derive(Copy, Clone, Default)]
+#[non_exhaustive]
 pub struct Options {
     /// If set, the output will be JSON-formatted.
     ///
@@ -45,8 +46,6 @@ pub struct Options {
     ///
     /// This corresponds to the `x` character.
     pub skip_mutex_statistics: bool,
-
-    _p: (),
 }
 
 struct State<W> {
@@ -76,63 +75,76 @@ where
     }
 }
 
+/// Calls `f` with a `write_cb_t` callback and its opaque argument, which
+/// forward the output of `jemalloc` to `writer`.
+///
+/// Writing stops at the first error of `writer`, which is returned; panics of
+/// `writer` are resumed once `f` returns.
+pub(crate) fn with_write_cb<W, F>(writer: W, f: F) -> io::Result<()>
+where
+    W: Write,
+    F: FnOnce(unsafe extern "C" fn(*mut c_void, *const c_char), *mut c_void),
+{
+    let mut state = State {
+        writer,
+        error: Ok(()),
+        panic: Ok(()),
+    };
+    f(callback::<W>, &mut state as *mut _ as *mut c_void);
+    if let Err(e) = state.panic {
+        panic::resume_unwind(e);
+    }
+    state.error
+}
+
 /// Writes allocator statistics.
 ///
 /// The information is the same that can be retrieved by the individual lookup methods in this
 /// crate, but all done at once.
-#[cfg_attr(feature = "cargo-clippy", allow(clippy::cast_possible_wrap))]
+#[allow(clippy::cast_possible_wrap)]
 pub fn stats_print<W>(writer: W, options: Options) -> io::Result<()>
 where
     W: Write,
 {
-    unsafe {
-        let mut state = State {
-            writer,
-            error: Ok(()),
-            panic: Ok(()),
-        };
-        let mut opts = [0; 8];
-        let mut i = 0;
-        if options.json_format {
-            opts[i] = b'J' as c_char;
-            i += 1;
-        }
-        if options.skip_constants {
-            opts[i] = b'g' as c_char;
-            i += 1;
-        }
-        if options.skip_merged_arenas {
-            opts[i] = b'm' as c_char;
-            i += 1;
-        }
-        if options.skip_per_arena {
-            opts[i] = b'a' as c_char;
-            i += 1;
-        }
-        if options.skip_bin_size_classes {
-            opts[i] = b'b' as c_char;
-            i += 1;
-        }
-        if options.skip_large_size_classes {
-            opts[i] = b'l' as c_char;
-            i += 1;
-        }
-        if options.skip_mutex_statistics {
-            opts[i] = b'x' as c_char;
-            i += 1;
-        }
-        opts[i] = 0;
+    let mut opts = [0; 8];
+    let mut i = 0;
+    if options.json_format {
+        opts[i] = b'J' as c_char;
+        i += 1;
+    }
+    if options.skip_constants {
+        opts[i] = b'g' as c_char;
+        i += 1;
+    }
+    if options.skip_merged_arenas {
+        opts[i] = b'm' as c_char;
+        i += 1;
+    }
+    if options.skip_per_arena {
+        opts[i] = b'a' as c_char;
+        i += 1;
+    }
+    if options.skip_bin_size_classes {
+        opts[i] = b'b' as c_char;
+        i += 1;
+    }
+    if options.skip_large_size_classes {
+        opts[i] = b'l' as c_char;
+        i += 1;
+    }
+    if options.skip_mutex_statistics {
+        opts[i] = b'x' as c_char;
+        i += 1;
+    }
+    opts[i] = 0;
 
+    with_write_cb(writer, |write_cb, cbopaque| unsafe {
         tikv_jemalloc_sys::malloc_stats_print(
-            Some(callback::<W>),
-            &mut state as *mut _ as *mut c_void,
+            Some(write_cb),
+            cbopaque,
             opts.as_ptr(),
         );
-        if let Err(e) = state.panic {
-            panic::resume_unwind(e);
-        }
-        state.error
-    }
+    })
 }
 
 #[cfg(test)]
@@ -157,7 +169,6 @@ mod test {
             skip_bin_size_classes: true,
             skip_large_size_classes: true,
             skip_mutex_statistics: true,
-            _p: (),
         };
         stats_print(&mut buf, options).unwrap();
         println!("{}", String::from_utf8(buf).unwrap());
@@ -0,0 +1,91 @@
+//! Explicit thread caches.
+//!
+//! Besides the implicit cache of every thread, jemalloc can create thread
+//! caches that are managed explicitly and that are passed to the allocation
+//! functions with the `MALLOCX_TCACHE(tc)` flag. Such caches are not tied to an
+//! OS thread, which makes them suitable for e.g. tasks that migrate between
+//! the threads of an executor.
+
+use crate::error::Result;
+use crate::raw::{read, write};
+use crate::std::{cell::Cell, marker::PhantomData, mem};
+
+/// Explicit thread cache created with `tcache.create`.
+///
+/// The cache is flushed and destroyed through `tcache.destroy` when the handle
+/// is dropped.
+///
+/// A thread cache must not be used by several threads concurrently, so the
+/// handle is `Send` but not `Sync`.
+///
+/// # Example
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::tcache::Tcache;
+///
+/// let tcache = Tcache::new().unwrap();
+/// println!("created tcache {}", tcache.index());
+/// tcache.flush().unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Tcache {
+    index: u32,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl Tcache {
+    /// Creates a new explicit thread cache.
+    pub fn new() -> Result<Self> {
+        let index = unsafe { read(b"tcache.create\0")? };
+        Ok(Tcache {
+            index,
+            _not_sync: PhantomData,
+        })
+    }
+
+    /// Returns the index of the cache, to be passed to `MALLOCX_TCACHE`.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Flushes the cache through `tcache.flush`, returning the cached objects
+    /// to their arenas.
+    pub fn flush(&self) -> Result<()> {
+        unsafe { write(b"tcache.flush\0", self.index) }
+    }
+
+    /// Destroys the cache, returning the error reported by jemalloc if any.
+    ///
+    /// Dropping the handle does the same, but ignores errors.
+    pub fn destroy(self) -> Result<()> {
+        let index = self.index;
+        mem::forget(self);
+        unsafe { write(b"tcache.destroy\0", index) }
+    }
+}
+
+impl Drop for Tcache {
+    fn drop(&mut self) {
+        let _ = unsafe { write(b"tcache.destroy\0", self.index) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tcache;
+
+    #[test]
+    fn create_flush_destroy() {
+        let a = Tcache::new().unwrap();
+        let b = Tcache::new().unwrap();
+        assert_ne!(a.index(), b.index());
+        a.flush().unwrap();
+        a.destroy().unwrap();
+        drop(b);
+    }
+}
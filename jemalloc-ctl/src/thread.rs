@@ -2,6 +2,12 @@
 
 use crate::error::Result;
 use crate::raw::{read, read_mib};
+#[cfg(feature = "stats")]
+use crate::{
+    keys::Mib,
+    std::marker::PhantomData,
+    std::sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 option! {
     allocatedp[ str: b"thread.allocatedp\0", non_str: 2 ] => *mut u64 |
@@ -125,3 +131,474 @@ where
         unsafe { *self.0 }
     }
 }
+
+option! {
+    allocated[ str: b"thread.allocated\0", non_str: 2 ] => u64 |
+    ops: r |
+    docs:
+    /// Total number of bytes ever allocated by the calling thread.
+    ///
+    /// See [`allocatedp`] for a faster way to read it repeatedly.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// let a = thread::allocated::read().unwrap();
+    /// let buf = vec![0; 1024 * 1024];
+    /// let b = thread::allocated::read().unwrap();
+    /// drop(buf);
+    /// assert!(a < b);
+    /// # }
+    /// ```
+    mib_docs: /// See [`allocated`].
+}
+
+option! {
+    deallocated[ str: b"thread.deallocated\0", non_str: 2 ] => u64 |
+    ops: r |
+    docs:
+    /// Total number of bytes ever deallocated by the calling thread.
+    ///
+    /// See [`deallocatedp`] for a faster way to read it repeatedly.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// let buf = vec![0; 1024 * 1024];
+    /// let a = thread::deallocated::read().unwrap();
+    /// drop(buf);
+    /// let b = thread::deallocated::read().unwrap();
+    /// assert!(a < b);
+    /// # }
+    /// ```
+    mib_docs: /// See [`deallocated`].
+}
+
+option! {
+    arena[ str: b"thread.arena\0", non_str: 2 ] => libc::c_uint |
+    ops: r,w,u |
+    docs:
+    /// Index of the arena associated with the calling thread.
+    ///
+    /// Writing it binds the thread to another arena, e.g. one created with
+    /// [`crate::arena::Arena::create`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::{arena::Arena, thread};
+    /// let arena = Arena::create().unwrap();
+    /// let previous = thread::arena::update(arena.index()).unwrap();
+    /// assert_eq!(thread::arena::read().unwrap(), arena.index());
+    /// thread::arena::write(previous).unwrap();
+    /// # }
+    /// ```
+    mib_docs: /// See [`arena`].
+}
+
+option! {
+    tcache_enabled[ str: b"thread.tcache.enabled\0", non_str: 3 ] => bool |
+    ops: r,w,u |
+    docs:
+    /// Whether the thread cache of the calling thread is enabled.
+    ///
+    /// Disabling it flushes the cache.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// let enabled = thread::tcache_enabled::update(false).unwrap();
+    /// assert!(!thread::tcache_enabled::read().unwrap());
+    /// thread::tcache_enabled::write(enabled).unwrap();
+    /// # }
+    /// ```
+    mib_docs: /// See [`tcache_enabled`].
+}
+
+option! {
+    tcache_flush[ str: b"thread.tcache.flush\0", non_str: 3 ] => () |
+    ops: c |
+    docs:
+    /// Flushes the thread cache of the calling thread, returning the cached
+    /// objects to their arenas.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// thread::tcache_flush::call().unwrap();
+    /// # }
+    /// ```
+    mib_docs: /// See [`tcache_flush`].
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    prof_name[ str: b"thread.prof.name\0", str: 3 ] => &'static str |
+    ops: w |
+    docs:
+    /// Name associated with the calling thread in heap profiles.
+    ///
+    /// The key fails with `ENOENT` unless profiling is enabled (`opt.prof`).
+    /// The name must be null-terminated, and jemalloc copies it.
+    ///
+    /// jemalloc frees the name when it is replaced or when the thread exits,
+    /// so the key cannot be read as a `&'static str`. With the `use_std`
+    /// feature, `read` returns a copy of it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// // Fails unless profiling is enabled:
+    /// let _ = thread::prof_name::write("worker-0\0");
+    /// # }
+    /// ```
+    mib_docs: /// See [`prof_name`].
+}
+
+#[cfg(all(feature = "profiling", feature = "use_std"))]
+impl prof_name {
+    /// Reads a copy of the name using string API.
+    pub fn read() -> Result<String> {
+        unsafe { copy_prof_name(read(Self::name().as_bytes())?) }
+    }
+}
+
+#[cfg(all(feature = "profiling", feature = "use_std"))]
+impl prof_name_mib {
+    /// Reads a copy of the name using MIB API.
+    pub fn read(self) -> Result<String> {
+        unsafe { copy_prof_name(read_mib(self.0.as_ref())?) }
+    }
+}
+
+/// Copies the name of the calling thread returned by `thread.prof.name`.
+///
+/// # Safety
+///
+/// `name` must have just been read: it is only valid until the name of the
+/// thread changes.
+#[cfg(all(feature = "profiling", feature = "use_std"))]
+unsafe fn copy_prof_name(name: *const libc::c_char) -> Result<String> {
+    // jemalloc only accepts printable ASCII names:
+    Ok(std::ffi::CStr::from_ptr(name)
+        .to_string_lossy()
+        .into_owned())
+}
+
+#[cfg(feature = "profiling")]
+option! {
+    prof_active[ str: b"thread.prof.active\0", non_str: 3 ] => bool |
+    ops: r,w,u |
+    docs:
+    /// Whether sampling is active for the calling thread.
+    ///
+    /// Writing it fails with `ENOENT` unless profiling is enabled (`opt.prof`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// println!("thread sampling active: {}", thread::prof_active::read().unwrap());
+    /// # }
+    /// ```
+    mib_docs: /// See [`prof_active`].
+}
+
+option! {
+    idle[ str: b"thread.idle\0", non_str: 2 ] => () |
+    ops: c |
+    docs:
+    /// Hints jemalloc that the calling thread is going to be idle.
+    ///
+    /// jemalloc flushes the thread cache of the thread, and might purge the
+    /// unused pages of its arena.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// thread::idle::call().unwrap();
+    /// # }
+    /// ```
+    mib_docs: /// See [`idle`].
+}
+
+#[cfg(feature = "stats")]
+option! {
+    peak_read[ str: b"thread.peak.read\0", non_str: 3 ] => u64 |
+    ops: r |
+    docs:
+    /// High-water mark of the number of bytes allocated minus the number of
+    /// bytes deallocated by the calling thread since the last
+    /// [`peak_reset`].
+    ///
+    /// The mark is only updated approximately, every 64 KiB of allocation
+    /// activity.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// thread::peak_reset::call().unwrap();
+    /// let buf = vec![0_u8; 1024 * 1024];
+    /// drop(buf);
+    /// assert!(thread::peak_read::read().unwrap() >= 1024 * 1024);
+    /// # }
+    /// ```
+    mib_docs: /// See [`peak_read`].
+}
+
+#[cfg(feature = "stats")]
+option! {
+    peak_reset[ str: b"thread.peak.reset\0", non_str: 3 ] => () |
+    ops: c |
+    docs:
+    /// Resets the high-water mark of [`peak_read`] for the calling thread.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[global_allocator]
+    /// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+    /// #
+    /// # fn main() {
+    /// use tikv_jemalloc_ctl::thread;
+    /// thread::peak_reset::call().unwrap();
+    /// # }
+    /// ```
+    mib_docs: /// See [`peak_reset`].
+}
+
+/// MIB components of `thread.peak.read` followed by the last component of
+/// `thread.peak.reset`, looked up by the first [`PeakTracker`].
+#[cfg(feature = "stats")]
+static PEAK_MIBS: [AtomicUsize; 4] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+#[cfg(feature = "stats")]
+static PEAK_MIBS_INIT: AtomicBool = AtomicBool::new(false);
+
+/// Returns the MIBs of `thread.peak.read` and `thread.peak.reset`.
+#[cfg(feature = "stats")]
+fn peak_mibs() -> Result<(peak_read_mib, peak_reset_mib)> {
+    if PEAK_MIBS_INIT.load(Ordering::Acquire) {
+        let mut read = Mib::<[usize; 3]>::default();
+        for i in 0..3 {
+            read[i] = PEAK_MIBS[i].load(Ordering::Relaxed);
+        }
+        let mut reset = read;
+        reset[2] = PEAK_MIBS[3].load(Ordering::Relaxed);
+        return Ok((peak_read_mib(read), peak_reset_mib(reset)));
+    }
+
+    let read = peak_read::mib()?;
+    let reset = peak_reset::mib()?;
+    debug_assert_eq!(read.0[0], reset.0[0]);
+    debug_assert_eq!(read.0[1], reset.0[1]);
+    for (cached, &component) in PEAK_MIBS.iter().zip(read.0.as_ref()) {
+        cached.store(component, Ordering::Relaxed);
+    }
+    PEAK_MIBS[3].store(reset.0[2], Ordering::Relaxed);
+    // Concurrent initializations store the same values:
+    PEAK_MIBS_INIT.store(true, Ordering::Release);
+    Ok((read, reset))
+}
+
+/// Scope guard that tracks the peak memory usage of the calling thread.
+///
+/// Creating the tracker resets the high-water mark of the thread
+/// ([`peak_reset`]), and [`peak`](Self::peak) returns the high-water mark of
+/// the bytes allocated minus the bytes deallocated by the thread since then
+/// ([`peak_read`]). The callback passed to
+/// [`with_report`](Self::with_report) receives the mark when the tracker is
+/// dropped.
+///
+/// The MIBs of the keys are looked up once per process, so creating a tracker
+/// is cheap. The mark is only updated every 64 KiB of allocation activity, so
+/// it is approximate. Since the mark is per thread, the tracker is neither
+/// `Send` nor `Sync`, and nested trackers reset the mark of enclosing ones.
+///
+/// # Example
+///
+/// ```
+/// # #[global_allocator]
+/// # static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+/// #
+/// # fn main() {
+/// use tikv_jemalloc_ctl::thread::PeakTracker;
+///
+/// let tracker = PeakTracker::new().unwrap();
+/// let buf = vec![0_u8; 1024 * 1024];
+/// drop(buf);
+/// assert!(tracker.finish().unwrap() >= 1024 * 1024);
+///
+/// {
+///     let _tracker = PeakTracker::with_report(|peak| {
+///         println!("request peak: {} bytes", peak);
+///     })
+///     .unwrap();
+///     // handle the request...
+/// }
+/// # }
+/// ```
+#[cfg(feature = "stats")]
+pub struct PeakTracker<F: FnOnce(u64) = fn(u64)> {
+    read: peak_read_mib,
+    reset: peak_reset_mib,
+    report: Option<F>,
+    // the high-water mark is thread-local:
+    _not_send: PhantomData<*const ()>,
+}
+
+#[cfg(feature = "stats")]
+impl PeakTracker {
+    /// Resets the high-water mark of the calling thread and starts tracking
+    /// it.
+    pub fn new() -> Result<Self> {
+        Self::start(None)
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<F: FnOnce(u64)> PeakTracker<F> {
+    /// Like [`new`](PeakTracker::new), but passes the high-water mark to
+    /// `report` when the tracker is dropped.
+    ///
+    /// `report` is not called if reading the mark fails.
+    pub fn with_report(report: F) -> Result<Self> {
+        Self::start(Some(report))
+    }
+
+    fn start(report: Option<F>) -> Result<Self> {
+        let (read, reset) = peak_mibs()?;
+        reset.call()?;
+        Ok(PeakTracker {
+            read,
+            reset,
+            report,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Returns the high-water mark since the tracker was created or last
+    /// reset.
+    pub fn peak(&self) -> Result<u64> {
+        self.read.read()
+    }
+
+    /// Resets the high-water mark of the calling thread.
+    pub fn reset(&self) -> Result<()> {
+        self.reset.call()
+    }
+
+    /// Stops tracking and returns the high-water mark, without calling the
+    /// report callback.
+    pub fn finish(mut self) -> Result<u64> {
+        self.report = None;
+        self.peak()
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<F: FnOnce(u64)> Drop for PeakTracker<F> {
+    fn drop(&mut self) {
+        if let Some(report) = self.report.take() {
+            if let Ok(peak) = self.peak() {
+                report(peak);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod tests {
+    use super::PeakTracker;
+    use crate::std::cell::Cell;
+
+    fn allocate(size: usize) {
+        unsafe {
+            let ptr = tikv_jemalloc_sys::mallocx(size, 0);
+            assert!(!ptr.is_null());
+            // touch the memory so that the allocation is not elided:
+            crate::ptr::write_volatile(ptr as *mut u8, 1);
+            tikv_jemalloc_sys::sdallocx(ptr, size, 0);
+        }
+    }
+
+    #[test]
+    fn peak_tracker() {
+        let tracker = PeakTracker::new().unwrap();
+        allocate(4 << 20);
+        let peak = tracker.peak().unwrap();
+        assert!(peak >= 4 << 20);
+
+        tracker.reset().unwrap();
+        allocate(1 << 20);
+        let peak = tracker.peak().unwrap();
+        assert!((1 << 20..4 << 20).contains(&peak), "{}", peak);
+        assert!(tracker.finish().unwrap() >= 1 << 20);
+    }
+
+    #[test]
+    fn peak_tracker_report() {
+        let reported = Cell::new(0);
+        {
+            let _tracker =
+                PeakTracker::with_report(|peak| reported.set(peak)).unwrap();
+            allocate(2 << 20);
+        }
+        assert!(reported.get() >= 2 << 20);
+
+        reported.set(0);
+        let tracker =
+            PeakTracker::with_report(|peak| reported.set(peak)).unwrap();
+        allocate(2 << 20);
+        assert!(tracker.finish().unwrap() >= 2 << 20);
+        assert_eq!(reported.get(), 0);
+    }
+}
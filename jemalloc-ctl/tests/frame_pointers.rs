@@ -0,0 +1,62 @@
+//! Profiles with the frame pointer walker. Run it with
+//! `RUSTFLAGS="-C force-frame-pointers=yes"`, which the `frame_pointers`
+//! feature cannot enable by itself.
+#![cfg(all(
+    feature = "frame_pointers",
+    feature = "top_sites",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+
+use tikv_jemalloc_ctl::profiling;
+
+#[global_allocator]
+static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+union U {
+    x: &'static u8,
+    y: &'static libc::c_char,
+}
+
+#[allow(non_upper_case_globals)]
+#[export_name = "_rjem_malloc_conf"]
+pub static malloc_conf: Option<&'static libc::c_char> = Some(unsafe {
+    U {
+        x: &b"prof:true,prof_active:false\0"[0],
+    }
+    .y
+});
+
+#[inline(never)]
+fn allocate_with_frame_pointers() -> Vec<Vec<u8>> {
+    (0..16).map(|_| vec![1; 1 << 20]).collect()
+}
+
+#[test]
+fn frame_pointer_backtrace_hook() {
+    unsafe { profiling::set_frame_pointer_backtrace_hook().unwrap() };
+    let live = {
+        let _guard = profiling::ProfActiveGuard::new().unwrap();
+        allocate_with_frame_pointers()
+    };
+    let sites = profiling::top_sites(32).unwrap();
+    drop(live);
+    profiling::reset_backtrace_hook().unwrap();
+
+    // The loop may be unrolled, which splits the allocations among several
+    // call sites.
+    let sites: Vec<_> = sites
+        .iter()
+        .filter(|s| {
+            s.frames
+                .iter()
+                .any(|f| f.function.ends_with("allocate_with_frame_pointers"))
+        })
+        .collect();
+    let bytes: f64 = sites.iter().map(|s| s.bytes).sum();
+    assert!(bytes > (8 << 20) as f64, "{:#?}", sites);
+    for site in sites {
+        let top = &site.frames[0].function;
+        assert!(!top.starts_with("_rjem_"), "{}", site);
+        assert!(!top.starts_with("tikv_jemalloc_ctl::"), "{}", site);
+    }
+}
@@ -0,0 +1,534 @@
+//! Tests of the `prof.*` keys, which require profiling to be enabled.
+#![cfg(feature = "profiling")]
+
+#[cfg(feature = "pprof")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "pprof")]
+use std::collections::HashMap;
+#[cfg(feature = "pprof")]
+use std::io::Read;
+use std::sync::{Mutex, MutexGuard};
+use tikv_jemalloc_ctl::{profiling, thread};
+
+#[global_allocator]
+static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+union U {
+    x: &'static u8,
+    y: &'static libc::c_char,
+}
+
+#[allow(non_upper_case_globals)]
+#[export_name = "_rjem_malloc_conf"]
+pub static malloc_conf: Option<&'static libc::c_char> = Some(unsafe {
+    U {
+        x: &b"prof:true,prof_active:false,prof_stats:true\0"[0],
+    }
+    .y
+});
+
+/// Serializes the tests that change `prof.active` or `prof.reset`, and the
+/// ones that dump heap profiles, which the global dump hook observes.
+fn lock() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Allocates `n` objects of `size` bytes with sampling active, and returns
+/// what `f` returns while they are still alive.
+///
+/// Sampling is random, but with the default sample interval of 512 KiB most
+/// objects of 1 MiB or more are sampled: the tests expect at least half of
+/// them in the profiles. The objects are allocated by this function, which
+/// is never inlined so that it can be found in the symbolized stacks.
+#[cfg(feature = "use_std")]
+#[inline(never)]
+fn with_sampled_allocations<T>(
+    n: usize,
+    size: usize,
+    f: impl FnOnce() -> T,
+) -> T {
+    let _lock = lock();
+    let _guard = profiling::ProfActiveGuard::new().unwrap();
+    let live: Vec<Vec<u8>> = (0..n).map(|_| vec![1; size]).collect();
+    let result = f();
+    drop(live);
+    result
+}
+
+#[test]
+fn prof_enabled() {
+    assert!(profiling::prof::read().unwrap());
+    assert!(profiling::interval::read().is_ok());
+}
+
+#[test]
+fn active_guard() {
+    let _lock = lock();
+    assert!(!profiling::active::read().unwrap());
+    {
+        let _guard = profiling::ProfActiveGuard::new().unwrap();
+        assert!(profiling::active::read().unwrap());
+    }
+    assert!(!profiling::active::read().unwrap());
+
+    let init = profiling::thread_active_init::update(false).unwrap();
+    assert!(!profiling::thread_active_init::read().unwrap());
+    profiling::thread_active_init::write(init).unwrap();
+
+    let gdump = profiling::gdump::update(true).unwrap();
+    assert!(profiling::gdump::read().unwrap());
+    profiling::gdump::write(gdump).unwrap();
+}
+
+#[test]
+fn reset_sample_rate() {
+    let _lock = lock();
+    let lg_sample = profiling::lg_sample::read().unwrap();
+    profiling::reset::write(10).unwrap();
+    assert_eq!(profiling::lg_sample::read().unwrap(), 10);
+    profiling::reset::call().unwrap();
+    assert_eq!(profiling::lg_sample::read().unwrap(), 10);
+    profiling::reset::mib().unwrap().write(lg_sample).unwrap();
+    assert_eq!(profiling::lg_sample::read().unwrap(), lg_sample);
+}
+
+#[test]
+fn dump() {
+    let _lock = lock();
+    let path = std::env::temp_dir()
+        .join(format!("tikv-jemalloc-ctl-{}.heap", std::process::id()));
+    let name: &'static str =
+        Box::leak(format!("{}\0", path.to_str().unwrap()).into_boxed_str());
+    profiling::dump::write(name).unwrap();
+    let profile = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(profile.starts_with("heap_v2/"), "{}", profile);
+}
+
+#[test]
+fn thread_keys() {
+    thread::prof_name::write("profiled-thread\0").unwrap();
+    #[cfg(feature = "use_std")]
+    {
+        assert_eq!(thread::prof_name::read().unwrap(), "profiled-thread");
+        let mib = thread::prof_name::mib().unwrap();
+        mib.write("renamed-thread\0").unwrap();
+        assert_eq!(mib.read().unwrap(), "renamed-thread");
+    }
+
+    let active = thread::prof_active::update(false).unwrap();
+    assert!(!thread::prof_active::read().unwrap());
+    thread::prof_active::write(active).unwrap();
+}
+
+#[test]
+fn size_class_stats() {
+    let _lock = lock();
+    assert!(profiling::prof_stats::read().unwrap());
+    let size = 5 << 20;
+    let stats = || {
+        profiling::SizeClassesProfStats::new()
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|stats| stats.size_class.size == size)
+            .unwrap()
+    };
+
+    let before = stats();
+    let live = {
+        let _guard = profiling::ProfActiveGuard::new().unwrap();
+        (0..4).map(|_| vec![1u8; size]).collect::<Vec<_>>()
+    };
+    let during = stats();
+    drop(live);
+    let after = stats();
+
+    // With the default sample interval of 512 KiB, such large allocations
+    // are almost always sampled.
+    assert!(during.live.count > before.live.count, "{:?}", during);
+    assert_eq!(
+        during.live.requested_bytes - before.live.requested_bytes,
+        (during.live.count - before.live.count) * size as u64
+    );
+    assert!(during.accum.count >= during.live.count);
+    assert_eq!(after.live, before.live);
+    assert_eq!(after.accum, during.accum);
+
+    assert_eq!(
+        profiling::SizeClassesProfStats::new().unwrap().len(),
+        tikv_jemalloc_ctl::arenas::SizeClasses::new().unwrap().len()
+    );
+    let bin = profiling::bins_accum::mib().unwrap().read(0).unwrap();
+    assert!(bin.requested_bytes <= bin.count * 8);
+}
+
+#[test]
+fn backtrace_hook() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static UNINIT: AtomicUsize = AtomicUsize::new(0);
+    fn fake_stack(frames: &mut [*mut libc::c_void]) -> usize {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        // A panic would abort, so check that `frames` is zeroed afterwards:
+        let uninit = frames.iter().filter(|f| !f.is_null()).count();
+        UNINIT.fetch_add(uninit, Ordering::Relaxed);
+        frames[0] = 0x1234 as *mut libc::c_void;
+        1
+    }
+
+    let _lock = lock();
+    let default = profiling::raw_backtrace_hook().unwrap();
+    profiling::set_backtrace_hook(fake_stack).unwrap();
+    {
+        let _guard = profiling::ProfActiveGuard::new().unwrap();
+        let live: Vec<Vec<u8>> = (0..4).map(|_| vec![1; 1 << 20]).collect();
+        drop(live);
+    }
+    profiling::reset_backtrace_hook().unwrap();
+    assert!(CALLS.load(Ordering::Relaxed) > 0);
+    assert_eq!(UNINIT.load(Ordering::Relaxed), 0);
+    assert_eq!(
+        profiling::raw_backtrace_hook()
+            .unwrap()
+            .map(|h| h as *const ()),
+        default.map(|h| h as *const ())
+    );
+}
+
+#[test]
+fn dump_hook() {
+    static DUMPED: Mutex<Vec<std::ffi::CString>> = Mutex::new(Vec::new());
+    fn on_dump(filename: &std::ffi::CStr) {
+        DUMPED.lock().unwrap().push(filename.to_owned());
+    }
+
+    let _lock = lock();
+
+    let path = std::env::temp_dir().join(format!(
+        "tikv-jemalloc-ctl-{}-hook.heap",
+        std::process::id()
+    ));
+    let name: &'static str =
+        Box::leak(format!("{}\0", path.to_str().unwrap()).into_boxed_str());
+    assert!(profiling::raw_dump_hook().unwrap().is_none());
+    profiling::set_dump_hook(Some(on_dump)).unwrap();
+    assert!(profiling::raw_dump_hook().unwrap().is_some());
+    profiling::dump::write(name).unwrap();
+    profiling::set_dump_hook(None).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(profiling::raw_dump_hook().unwrap().is_none());
+    let dumped = DUMPED.lock().unwrap();
+    assert!(
+        dumped.iter().any(|f| f.to_str().ok() == path.to_str()),
+        "{:?}",
+        dumped
+    );
+}
+
+#[cfg(feature = "use_std")]
+#[test]
+fn dump_to_path() {
+    let _lock = lock();
+    let path = std::env::temp_dir().join(format!(
+        "tikv-jemalloc-ctl-{}-path.heap",
+        std::process::id()
+    ));
+    profiling::dump_to_path(&path).unwrap();
+    let profile = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(profiling::ProfileHeader::parse(&profile).is_some());
+
+    let err = profiling::dump_to_path("a\0b".as_ref()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "use_std")]
+#[test]
+fn dump_to_vec() {
+    let _lock = lock();
+    let dump = profiling::dump_to_vec().unwrap();
+    assert!(dump.data.starts_with(b"heap_v2/"));
+    assert!(dump.header.sample_period.is_power_of_two());
+}
+
+#[cfg(feature = "use_std")]
+#[test]
+fn prof_log() {
+    use std::fs::{self, File};
+    use std::io::BufReader;
+
+    let path = std::env::temp_dir().join(format!(
+        "tikv-jemalloc-ctl-test-{}.json",
+        std::process::id()
+    ));
+    let log = profiling::ProfLog::start(&path).unwrap();
+    // Only one log can be written at a time.
+    assert!(profiling::ProfLog::start(&path).is_err());
+    with_sampled_allocations(16, 1 << 20, || ());
+    log.stop().unwrap();
+
+    let reader =
+        profiling::LogReader::new(BufReader::new(File::open(&path).unwrap()))
+            .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(reader.info().pid, i64::from(std::process::id()));
+    let threads = reader.threads().len();
+    let stacks = reader.stacks().len();
+    let allocations: Vec<_> = reader.map(Result::unwrap).collect();
+    let large: Vec<_> =
+        allocations.iter().filter(|a| a.usize >= 1 << 20).collect();
+    assert!(large.len() >= 8, "{:?}", allocations);
+    for allocation in large {
+        assert!(allocation.alloc_thread < threads);
+        assert!(allocation.free_stack < stacks);
+        assert!(allocation.free_time >= allocation.alloc_time);
+    }
+}
+
+#[cfg(feature = "heap_profile")]
+#[test]
+fn parse_heap_profile() {
+    let dump = with_sampled_allocations(64, 1 << 20, || {
+        profiling::dump_to_vec().unwrap()
+    });
+
+    let profile = dump.profile().unwrap();
+    assert_eq!(profile.header, dump.header);
+    assert!(!profile.stacks.is_empty());
+    assert!(profile.stacks.iter().all(|s| !s.addresses.is_empty()));
+    // The header only sums the threads that are still alive, while the
+    // stacks also count the objects of the threads that have exited.
+    let bytes: u64 = profile.stacks.iter().map(|s| s.counts.bytes).sum();
+    assert!(bytes >= profile.header.bytes);
+    let estimate = profile.estimated_totals();
+    assert!(estimate.bytes >= profile.header.bytes as f64);
+    assert!(estimate.bytes > (32 << 20) as f64);
+    #[cfg(target_os = "linux")]
+    assert!(profile.mapping(profile.stacks[0].addresses[0]).is_some());
+}
+
+#[cfg(feature = "pprof")]
+#[test]
+fn dump_pprof() {
+    let mut options = profiling::PprofOptions::default();
+    options.symbolize = true;
+    let pprof = with_sampled_allocations(16, 1 << 20, || {
+        profiling::dump_pprof(&options).unwrap()
+    });
+
+    let mut proto = Vec::new();
+    GzDecoder::new(&pprof[..]).read_to_end(&mut proto).unwrap();
+    let fields = decode(&proto);
+    let strings: Vec<&str> = field(&fields, 6)
+        .map(|s| std::str::from_utf8(s.bytes()).unwrap())
+        .collect();
+    let string = |v: &Value| strings[v.varint() as usize];
+
+    let types: Vec<_> = field(&fields, 1)
+        .map(|t| string(&decode(t.bytes())[0].1))
+        .collect();
+    assert_eq!(types, ["inuse_objects", "inuse_space"]);
+    assert!(field(&fields, 2).next().is_some());
+    #[cfg(target_os = "linux")]
+    assert!(field(&fields, 3).next().is_some());
+    let functions: HashMap<u64, &str> = field(&fields, 5)
+        .map(|f| {
+            let f = decode(f.bytes());
+            (f[0].1.varint(), string(&f[1].1))
+        })
+        .collect();
+    assert!(
+        functions
+            .values()
+            .any(|f| f.ends_with("profiling::with_sampled_allocations")),
+        "{:?}",
+        functions
+    );
+
+    // The frames of the allocator are left out.
+    let locations: HashMap<u64, Vec<&str>> = field(&fields, 4)
+        .map(|l| {
+            let l = decode(l.bytes());
+            let lines = field(&l, 4)
+                .map(|line| functions[&decode(line.bytes())[0].1.varint()])
+                .collect();
+            (l[0].1.varint(), lines)
+        })
+        .collect();
+    for sample in field(&fields, 2) {
+        let sample = decode(sample.bytes());
+        let mut ids = sample[0].1.bytes();
+        let top = &locations[&varint(&mut ids)];
+        assert!(
+            top.iter().all(|f| !f.starts_with("_rjem_")
+                && !f.starts_with("prof_")
+                && !f.contains("__rust_alloc")),
+            "{:?}",
+            top
+        );
+    }
+}
+
+/// Field of a `profile.proto` message.
+#[cfg(feature = "pprof")]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+#[cfg(feature = "pprof")]
+impl Value<'_> {
+    fn varint(&self) -> u64 {
+        match *self {
+            Value::Varint(v) => v,
+            Value::Bytes(_) => panic!("expected a varint"),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match *self {
+            Value::Bytes(v) => v,
+            Value::Varint(_) => panic!("expected bytes"),
+        }
+    }
+}
+
+#[cfg(feature = "pprof")]
+fn varint(buf: &mut &[u8]) -> u64 {
+    let mut v = 0;
+    for shift in (0..64).step_by(7) {
+        let b = buf[0];
+        *buf = &buf[1..];
+        v |= u64::from(b & 0x7f) << shift;
+        if b < 0x80 {
+            break;
+        }
+    }
+    v
+}
+
+/// Decodes the fields of a message, which only has varints and
+/// length-delimited fields.
+#[cfg(feature = "pprof")]
+fn decode(mut buf: &[u8]) -> Vec<(u64, Value<'_>)> {
+    let mut fields = Vec::new();
+    while !buf.is_empty() {
+        let key = varint(&mut buf);
+        let value = match key & 7 {
+            0 => Value::Varint(varint(&mut buf)),
+            2 => {
+                let len = varint(&mut buf) as usize;
+                let (value, rest) = buf.split_at(len);
+                buf = rest;
+                Value::Bytes(value)
+            }
+            t => panic!("unexpected wire type {}", t),
+        };
+        fields.push((key >> 3, value));
+    }
+    fields
+}
+
+#[cfg(feature = "pprof")]
+fn field<'a, 'b>(
+    fields: &'b [(u64, Value<'a>)],
+    n: u64,
+) -> impl Iterator<Item = &'b Value<'a>> {
+    fields.iter().filter(move |f| f.0 == n).map(|f| &f.1)
+}
+
+#[cfg(feature = "flamegraph")]
+#[test]
+fn flamegraph() {
+    let profile = with_sampled_allocations(16, 1 << 20, || {
+        profiling::dump_to_vec().unwrap().profile().unwrap()
+    });
+
+    let mut options = profiling::FlamegraphOptions::default();
+    options.symbolize = true;
+    let mut collapsed = Vec::new();
+    profile.write_collapsed(&mut collapsed, &options).unwrap();
+    let collapsed = String::from_utf8(collapsed).unwrap();
+    assert!(
+        collapsed.contains("profiling::with_sampled_allocations"),
+        "{}",
+        collapsed
+    );
+    // The frames of the allocator are left out.
+    for line in collapsed.lines() {
+        let (stack, _) = line.rsplit_once(' ').unwrap();
+        let leaf = stack.rsplit(';').next().unwrap();
+        assert!(
+            !leaf.starts_with("_rjem_")
+                && !leaf.starts_with("prof_")
+                && !leaf.contains("__rust_alloc"),
+            "{}",
+            line
+        );
+    }
+
+    let mut svg = Vec::new();
+    profile.write_flamegraph(&mut svg, &options).unwrap();
+    assert!(svg.starts_with(b"<?xml"));
+}
+
+#[cfg(feature = "top_sites")]
+#[test]
+fn top_sites() {
+    let sites = with_sampled_allocations(16, 1 << 20, || {
+        profiling::top_sites(3).unwrap()
+    });
+
+    assert!(!sites.is_empty() && sites.len() <= 3);
+    let site = sites
+        .iter()
+        .find(|s| {
+            s.frames.iter().any(|f| {
+                f.function.ends_with("profiling::with_sampled_allocations")
+            })
+        })
+        .unwrap_or_else(|| panic!("{:#?}", sites));
+    assert!(site.bytes > (8 << 20) as f64);
+    assert!(!site.frames[0].function.starts_with("_rjem_"), "{}", site);
+}
+
+#[cfg(feature = "prof_recent")]
+#[test]
+fn recent_allocs() {
+    let previous = profiling::recent_alloc_max::update(16).unwrap();
+    let log = with_sampled_allocations(4, 3 << 20, || {
+        profiling::recent_allocs().unwrap()
+    });
+    profiling::recent_alloc_max::write(previous).unwrap();
+
+    assert_eq!(log.recent_alloc_max, 16);
+    let alloc = log
+        .allocs
+        .iter()
+        .find(|a| a.size == 3 << 20)
+        .unwrap_or_else(|| panic!("{:#?}", log));
+    assert!(alloc.usize >= alloc.size);
+    assert!(!alloc.released);
+    assert!(alloc.dalloc.is_none());
+    assert!(!alloc.alloc.stack.is_empty());
+}
+
+#[cfg(feature = "heap_profile")]
+#[test]
+fn diff() {
+    let current = with_sampled_allocations(16, 1 << 20, || {
+        profiling::dump_to_vec().unwrap().profile().unwrap()
+    });
+    // The objects have been freed since.
+    let base = {
+        let _lock = lock();
+        profiling::dump_to_vec().unwrap().profile().unwrap()
+    };
+
+    let diff = current.diff(&base);
+    assert!(diff.stacks[0].bytes() > (8 << 20) as f64);
+    let mut report = Vec::new();
+    diff.write_report(&mut report, 5).unwrap();
+    assert!(report.starts_with(b"Total: +"));
+}
@@ -154,12 +154,16 @@ fn main() {
     // Disable -Wextra warnings - jemalloc doesn't compile free of warnings with
     // it enabled: https://github.com/jemalloc/jemalloc/issues/1196
     let compiler = cc::Build::new().extra_warnings(false).get_compiler();
-    let cflags = compiler
+    let mut cflags = compiler
         .args()
         .iter()
         .map(|s| s.to_str().unwrap())
         .collect::<Vec<_>>()
         .join(" ");
+    if env::var("CARGO_FEATURE_FRAME_POINTERS").is_ok() {
+        info!("CARGO_FEATURE_FRAME_POINTERS set");
+        cflags.push_str(" -fno-omit-frame-pointer");
+    }
     let ldflags = read_and_watch_env("LDFLAGS").unwrap_or_else(|_| cflags.clone());
     info!("CC={:?}", compiler.path());
     info!("CFLAGS={:?}", cflags);
@@ -123,6 +123,15 @@ pub const fn MALLOCX_ARENA(a: usize) -> c_int {
     (a as c_int).wrapping_add(1).wrapping_shl(20)
 }
 
+/// Arena index that addresses all arenas at once in the `arena.<i>.*`
+/// `mallctl`s (e.g. `arena.<i>.purge`), and that refers to the merged
+/// statistics of all arenas in `stats.arenas.<i>.*`.
+pub const MALLCTL_ARENAS_ALL: c_uint = 4096;
+
+/// Arena index that refers to the merged statistics of all destroyed arenas
+/// in `stats.arenas.<i>.*`.
+pub const MALLCTL_ARENAS_DESTROYED: c_uint = 4097;
+
 extern "C" {
     /// Allocates `size` bytes of uninitialized memory.
     ///
@@ -1,13 +1,19 @@
 #[cfg(prefixed)]
 #[test]
 fn malloc_is_prefixed() {
-    assert_ne!(tikv_jemalloc_sys::malloc as usize, libc::malloc as usize)
+    assert_ne!(
+        tikv_jemalloc_sys::malloc as *const () as usize,
+        libc::malloc as *const () as usize
+    )
 }
 
 #[cfg(not(prefixed))]
 #[test]
 fn malloc_is_overridden() {
-    assert_eq!(tikv_jemalloc_sys::malloc as usize, libc::malloc as usize)
+    assert_eq!(
+        tikv_jemalloc_sys::malloc as *const () as usize,
+        libc::malloc as *const () as usize
+    )
 }
 
 #[cfg(any(
@@ -1,26 +1,26 @@
 //! Benchmarks the cost of the different allocation functions by doing a
 //! roundtrip (allocate, deallocate).
-#![feature(test, allocator_api)]
-#![cfg(feature = "alloc_trait")]
+#![cfg_attr(feature = "allocator_api", feature(test, allocator_api))]
+#![cfg(feature = "allocator_api")]
 
 extern crate test;
 
-use jemallocator::Jemalloc;
 use libc::c_int;
 use std::{
-    alloc::{Alloc, Excess, Layout},
+    alloc::{Allocator, Layout},
     ptr,
 };
 use test::Bencher;
 use tikv_jemalloc_sys::MALLOCX_ALIGN;
+use tikv_jemallocator::Jemalloc;
 
 #[global_allocator]
 static A: Jemalloc = Jemalloc;
 
 // FIXME: replace with jemallocator::layout_to_flags
-#[cfg(all(any(target_arch = "arm", target_arch = "mips", target_arch = "powerpc")))]
+#[cfg(any(target_arch = "arm", target_arch = "mips", target_arch = "powerpc"))]
 const MIN_ALIGN: usize = 8;
-#[cfg(all(any(
+#[cfg(any(
     target_arch = "x86",
     target_arch = "x86_64",
     target_arch = "aarch64",
@@ -30,7 +30,7 @@ const MIN_ALIGN: usize = 8;
     target_arch = "riscv64",
     target_arch = "s390x",
     target_arch = "sparc64"
-)))]
+))]
 const MIN_ALIGN: usize = 16;
 
 fn layout_to_flags(layout: &Layout) -> c_int {
@@ -47,7 +47,7 @@ macro_rules! rt {
             #[bench]
             fn [<rt_mallocx_size_ $size _align_ $align>](b: &mut Bencher) {
                 b.iter(|| unsafe {
-                    use jemalloc_sys as jemalloc;
+                    use tikv_jemalloc_sys as jemalloc;
                     let flags = layout_to_flags(&Layout::from_size_align($size, $align).unwrap());
                     let ptr = jemalloc::mallocx($size, flags);
                     test::black_box(ptr);
@@ -58,7 +58,7 @@ macro_rules! rt {
             #[bench]
             fn [<rt_mallocx_nallocx_size_ $size _align_ $align>](b: &mut Bencher) {
                 b.iter(|| unsafe {
-                    use jemalloc_sys as jemalloc;
+                    use tikv_jemalloc_sys as jemalloc;
                     let flags = layout_to_flags(&Layout::from_size_align($size, $align).unwrap());
                     let ptr = jemalloc::mallocx($size, flags);
                     test::black_box(ptr);
@@ -72,9 +72,9 @@ macro_rules! rt {
             fn [<rt_alloc_layout_checked_size_ $size _align_ $align>](b: &mut Bencher) {
                 b.iter(|| unsafe {
                     let layout = Layout::from_size_align($size, $align).unwrap();
-                    let ptr = Jemalloc.alloc(layout.clone()).unwrap();
+                    let ptr = Jemalloc.allocate(layout).unwrap().cast::<u8>();
                     test::black_box(ptr);
-                    Jemalloc.dealloc(ptr, layout);
+                    Jemalloc.deallocate(ptr, layout);
                 });
             }
 
@@ -82,37 +82,16 @@ macro_rules! rt {
             fn [<rt_alloc_layout_unchecked_size_ $size _align_ $align>](b: &mut Bencher) {
                 b.iter(|| unsafe {
                     let layout = Layout::from_size_align_unchecked($size, $align);
-                    let ptr = Jemalloc.alloc(layout.clone()).unwrap();
+                    let ptr = Jemalloc.allocate(layout).unwrap().cast::<u8>();
                     test::black_box(ptr);
-                    Jemalloc.dealloc(ptr, layout);
-                });
-            }
-
-            #[bench]
-            fn [<rt_alloc_excess_unused_size_ $size _align_ $align>](b: &mut Bencher) {
-                b.iter(|| unsafe {
-                    let layout = Layout::from_size_align($size, $align).unwrap();
-                    let Excess(ptr, _) = Jemalloc.alloc_excess(layout.clone()).unwrap();
-                    test::black_box(ptr);
-                    Jemalloc.dealloc(ptr, layout);
-                });
-            }
-
-            #[bench]
-            fn [<rt_alloc_excess_used_size_ $size _align_ $align>](b: &mut Bencher) {
-                b.iter(|| unsafe {
-                    let layout = Layout::from_size_align($size, $align).unwrap();
-                    let Excess(ptr, excess) = Jemalloc.alloc_excess(layout.clone()).unwrap();
-                    test::black_box(ptr);
-                    test::black_box(excess);
-                    Jemalloc.dealloc(ptr, layout);
+                    Jemalloc.deallocate(ptr, layout);
                 });
             }
 
             #[bench]
             fn [<rt_mallocx_zeroed_size_ $size _align_ $align>](b: &mut Bencher) {
                 b.iter(|| unsafe {
-                    use jemalloc_sys as jemalloc;
+                    use tikv_jemalloc_sys as jemalloc;
                     let flags = layout_to_flags(&Layout::from_size_align($size, $align).unwrap());
                     let ptr = jemalloc::mallocx($size, flags | jemalloc::MALLOCX_ZERO);
                     test::black_box(ptr);
@@ -123,7 +102,7 @@ macro_rules! rt {
             #[bench]
             fn [<rt_calloc_size_ $size _align_ $align>](b: &mut Bencher) {
                 b.iter(|| unsafe {
-                    use jemalloc_sys as jemalloc;
+                    use tikv_jemalloc_sys as jemalloc;
                     let flags = layout_to_flags(&Layout::from_size_align($size, $align).unwrap());
                     test::black_box(flags);
                     let ptr = jemalloc::calloc(1, $size);
@@ -136,20 +115,20 @@ macro_rules! rt {
             fn [<rt_realloc_naive_size_ $size _align_ $align>](b: &mut Bencher) {
                 b.iter(|| unsafe {
                     let layout = Layout::from_size_align($size, $align).unwrap();
-                    let ptr = Jemalloc.alloc(layout.clone()).unwrap();
+                    let ptr = Jemalloc.allocate(layout).unwrap().cast::<u8>();
                     test::black_box(ptr);
 
-                    // navie realloc:
+                    // naive realloc:
                     let new_layout = Layout::from_size_align(2 * $size, $align).unwrap();
                     let ptr = {
-                        let new_ptr = Jemalloc.alloc(new_layout.clone()).unwrap();
+                        let new_ptr = Jemalloc.allocate(new_layout).unwrap().cast::<u8>();
                         ptr::copy_nonoverlapping(ptr.as_ptr() as *const u8, new_ptr.as_ptr(), layout.size());
-                        Jemalloc.dealloc(ptr, layout);
+                        Jemalloc.deallocate(ptr, layout);
                         new_ptr
                     };
                     test::black_box(ptr);
 
-                    Jemalloc.dealloc(ptr, new_layout);
+                    Jemalloc.deallocate(ptr, new_layout);
                 });
             }
 
@@ -157,52 +136,16 @@ macro_rules! rt {
             fn [<rt_realloc_size_ $size _align_ $align>](b: &mut Bencher) {
                 b.iter(|| unsafe {
                     let layout = Layout::from_size_align($size, $align).unwrap();
-                    let ptr = Jemalloc.alloc(layout.clone()).unwrap();
+                    let ptr = Jemalloc.allocate(layout).unwrap().cast::<u8>();
                     test::black_box(ptr);
 
                     let new_layout = Layout::from_size_align(2 * $size, $align).unwrap();
-                    let ptr = Jemalloc.realloc(ptr, layout, new_layout.size()).unwrap();
+                    let ptr = Jemalloc.grow(ptr, layout, new_layout).unwrap().cast::<u8>();
                     test::black_box(ptr);
 
-                    Jemalloc.dealloc(ptr, new_layout);
+                    Jemalloc.deallocate(ptr, new_layout);
                 });
             }
-
-            #[bench]
-            fn [<rt_realloc_excess_unused_size_ $size _align_ $align>](b: &mut Bencher) {
-                b.iter(|| unsafe {
-                    let layout = Layout::from_size_align($size, $align).unwrap();
-                    let ptr = Jemalloc.alloc(layout.clone()).unwrap();
-                    test::black_box(ptr);
-
-                    let new_layout = Layout::from_size_align(2 * $size, $align).unwrap();
-                    let Excess(ptr, _) = Jemalloc
-                        .realloc_excess(ptr, layout, new_layout.size())
-                        .unwrap();
-                    test::black_box(ptr);
-
-                    Jemalloc.dealloc(ptr, new_layout);
-                });
-            }
-
-            #[bench]
-            fn [<rt_realloc_excess_used_size_ $size _align_ $align>](b: &mut Bencher) {
-                b.iter(|| unsafe {
-                    let layout = Layout::from_size_align($size, $align).unwrap();
-                    let ptr = Jemalloc.alloc(layout.clone()).unwrap();
-                    test::black_box(ptr);
-
-                    let new_layout = Layout::from_size_align(2 * $size, $align).unwrap();
-                    let Excess(ptr, excess) = Jemalloc
-                        .realloc_excess(ptr, layout, new_layout.size())
-                        .unwrap();
-                    test::black_box(ptr);
-                    test::black_box(excess);
-
-                    Jemalloc.dealloc(ptr, new_layout);
-                });
-            }
-
         }
     };
     ([$($size:expr),*]) => {
@@ -0,0 +1,164 @@
+//! Allocator bound to a single jemalloc arena.
+
+#[cfg(feature = "allocator-api2")]
+use allocator_api2::alloc::AllocError as AllocError2;
+#[cfg(feature = "allocator_api")]
+use core::alloc::AllocError;
+
+use crate::error::{cvt, Error};
+use crate::{ffi, layout_to_flags};
+use core::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr};
+use libc::{c_int, c_void};
+
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
+use crate::sized;
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
+use core::ptr::NonNull;
+
+/// Handle to an allocator that serves all allocations from one jemalloc arena.
+///
+/// Every allocation, reallocation and deallocation passes `MALLOCX_ARENA(i)`
+/// to jemalloc, so the memory used through this handle can be measured,
+/// purged, reset or destroyed separately from the rest of the process (e.g.
+/// through the `arena.<i>.*` and `stats.arenas.<i>.*` keys of
+/// `tikv-jemalloc-ctl`).
+///
+/// The handle bypasses the thread cache (`MALLOCX_TCACHE_NONE`): the thread
+/// caches are shared by all arenas, so cached objects freed by other
+/// allocators could otherwise be returned by this one, and objects freed by
+/// this one could be reused elsewhere.
+///
+/// Like [`Jemalloc`](crate::Jemalloc), it implements `GlobalAlloc`, and the
+/// `Allocator` traits when the `allocator_api` or `allocator-api2` features
+/// are enabled.
+///
+/// # Example
+///
+/// ```rust
+/// use core::alloc::{GlobalAlloc, Layout};
+/// use tikv_jemallocator::JemallocArena;
+///
+/// let arena = JemallocArena::new().unwrap();
+/// let layout = Layout::from_size_align(64, 8).unwrap();
+/// unsafe {
+///     let ptr = arena.alloc(layout);
+///     assert!(!ptr.is_null());
+///     arena.dealloc(ptr, layout);
+/// }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct JemallocArena {
+    index: u32,
+}
+
+impl JemallocArena {
+    /// Creates a new arena through `arenas.create` and returns a handle to it.
+    ///
+    /// The arena uses the default extent hooks. Arenas are never freed
+    /// implicitly: see [`destroy`](Self::destroy).
+    pub fn new() -> Result<Self, Error> {
+        let mut index: u32 = 0;
+        let mut len = mem::size_of::<u32>();
+        unsafe {
+            cvt(ffi::mallctl(
+                b"arenas.create\0".as_ptr() as *const _,
+                &mut index as *mut u32 as *mut c_void,
+                &mut len,
+                ptr::null_mut(),
+                0,
+            ))?;
+        }
+        debug_assert_eq!(len, mem::size_of::<u32>());
+        Ok(JemallocArena { index })
+    }
+
+    /// Returns a handle to the existing arena with index `index`.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be the index of an arena that has been initialized and
+    /// that has not been destroyed.
+    #[inline]
+    pub const unsafe fn from_index(index: u32) -> Self {
+        JemallocArena { index }
+    }
+
+    /// Returns the index of the arena.
+    #[inline]
+    pub const fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Discards all the allocations of the arena through `arena.<i>.reset`.
+    ///
+    /// The arena remains usable afterwards.
+    ///
+    /// # Safety
+    ///
+    /// All the memory allocated from the arena is released: none of the
+    /// allocations made through any handle to the arena may be used or
+    /// deallocated afterwards.
+    pub unsafe fn reset(&self) -> Result<(), Error> {
+        self.call(b"arena.0.reset\0")
+    }
+
+    /// Destroys the arena through `arena.<i>.destroy`, discarding all of its
+    /// allocations.
+    ///
+    /// The index may be reused by arenas created afterwards.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`reset`](Self::reset). Additionally, no other handle to the
+    /// arena may be used afterwards, and the arena must not be associated
+    /// with any thread (e.g. through `thread.arena`).
+    pub unsafe fn destroy(self) -> Result<(), Error> {
+        self.call(b"arena.0.destroy\0")
+    }
+
+    /// Calls the void `arena.<i>.*` key `name`, given with index `0`.
+    unsafe fn call(&self, name: &[u8]) -> Result<(), Error> {
+        let mut mib = [0; 3];
+        let mut len = mib.len();
+        cvt(ffi::mallctlnametomib(
+            name.as_ptr() as *const _,
+            mib.as_mut_ptr(),
+            &mut len,
+        ))?;
+        debug_assert_eq!(len, mib.len());
+        mib[1] = self.index as usize;
+        cvt(ffi::mallctlbymib(
+            mib.as_ptr(),
+            len,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            0,
+        ))
+    }
+
+    /// Flags passed to jemalloc in addition to the alignment flags.
+    #[inline]
+    fn flags(&self) -> c_int {
+        ffi::MALLOCX_ARENA(self.index as usize) | ffi::MALLOCX_TCACHE_NONE
+    }
+}
+
+impl_global_alloc!(JemallocArena);
+
+#[cfg(feature = "allocator_api")]
+impl_allocator!(
+    core::alloc::Allocator,
+    AllocError,
+    JemallocArena,
+    JemallocArena::flags
+);
+
+#[cfg(feature = "allocator-api2")]
+impl_allocator!(
+    allocator_api2::alloc::Allocator,
+    AllocError2,
+    JemallocArena,
+    JemallocArena::flags
+);
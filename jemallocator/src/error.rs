@@ -0,0 +1,48 @@
+//! Error type
+
+use core::fmt;
+use libc::c_int;
+
+/// Error returned when jemalloc rejects a control request, e.g. when it
+/// fails to create a new arena.
+///
+/// It wraps the error code returned by `mallctl`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Error(c_int);
+
+impl Error {
+    #[inline]
+    pub(crate) fn from_code(code: c_int) -> Self {
+        debug_assert!(code != 0);
+        Error(code)
+    }
+
+    /// Returns the error code returned by jemalloc.
+    #[inline]
+    pub fn code(self) -> c_int {
+        self.0
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            libc::EINVAL => f.write_str("invalid argument passed to jemalloc"),
+            libc::ENOENT => f.write_str("unknown jemalloc control key"),
+            libc::EPERM => f.write_str("jemalloc control key is not accessible"),
+            libc::EAGAIN => f.write_str("jemalloc failed to allocate memory"),
+            libc::EFAULT => f.write_str("jemalloc returned an interface error"),
+            code => write!(f, "jemalloc error code {}", code),
+        }
+    }
+}
+
+/// Converts the return code of a `mallctl` call into a `Result`.
+#[inline]
+pub(crate) fn cvt(code: c_int) -> Result<(), Error> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(Error::from_code(code))
+    }
+}
@@ -12,19 +12,26 @@
 //!
 //! This crate provides bindings to jemalloc as a memory allocator for Rust.
 //! This crate mainly exports, one type, `Jemalloc`, which implements the
-//! `GlobalAlloc` trait and optionally the `Alloc` trait,
+//! `GlobalAlloc` trait and optionally the `Allocator` trait (either the
+//! unstable one of the standard library or the one of `allocator-api2`),
 //! and is suitable both as a memory allocator and as a global allocator.
+//!
+//! The `JemallocArena` type serves all of its allocations from a single
+//! jemalloc arena, and the `JemallocTcache` type routes them through an
+//! explicit thread cache.
 
-#![cfg_attr(feature = "alloc_trait", feature(allocator_api))]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 // TODO: rename the following lint on next minor bump
 #![allow(renamed_and_removed_lints)]
 #![deny(missing_docs, broken_intra_doc_links)]
 #![no_std]
 
-#[cfg(feature = "alloc_trait")]
-use core::alloc::{Alloc, AllocErr, CannotReallocInPlace, Excess};
+#[cfg(feature = "allocator-api2")]
+use allocator_api2::alloc::AllocError as AllocError2;
+#[cfg(feature = "allocator_api")]
+use core::alloc::AllocError;
 use core::alloc::{GlobalAlloc, Layout};
-#[cfg(feature = "alloc_trait")]
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
 use core::ptr::NonNull;
 
 use libc::{c_int, c_void};
@@ -86,8 +93,12 @@ macro_rules! assume {
 ///
 /// This type implements the `GlobalAllocAlloc` trait, allowing usage a global allocator.
 ///
-/// When the `alloc_trait` feature of this crate is enabled, it also implements the `Alloc` trait,
-/// allowing usage in collections.
+/// When the `allocator_api` feature of this crate is enabled, it also implements the `Allocator`
+/// trait, allowing usage in collections (e.g. `Vec<T, Jemalloc>` or `Box<T, Jemalloc>`).
+///
+/// When the `allocator-api2` feature of this crate is enabled, it implements the `Allocator` trait
+/// of the [`allocator-api2`](https://docs.rs/allocator-api2) crate, allowing usage in collections
+/// that support it on stable Rust (e.g. `hashbrown`).
 #[derive(Copy, Clone, Default, Debug)]
 pub struct Jemalloc;
 
@@ -138,133 +149,240 @@ unsafe impl GlobalAlloc for Jemalloc {
     }
 }
 
-#[cfg(feature = "alloc_trait")]
-unsafe impl Alloc for Jemalloc {
-    #[inline]
-    unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
-        NonNull::new(GlobalAlloc::alloc(self, layout)).ok_or(AllocErr)
-    }
+/// Allocation routines shared by the implementations of the `Allocator`
+/// traits.
+///
+/// Unlike the `GlobalAlloc` methods, these accept zero-sized layouts and
+/// report the usable size of the allocations they return. The `flags` are
+/// passed to jemalloc in addition to the alignment flags of the layouts (e.g.
+/// `MALLOCX_ZERO` or `MALLOCX_ARENA`).
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
+mod sized {
+    use super::{ffi, layout_to_flags};
+    use core::alloc::Layout;
+    use core::ptr::{self, NonNull};
+    use libc::{c_int, c_void};
 
+    /// Returns a dangling pointer suitably aligned for `layout`, which is
+    /// what zero-sized allocations are backed by.
     #[inline]
-    unsafe fn alloc_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
-        NonNull::new(GlobalAlloc::alloc_zeroed(self, layout)).ok_or(AllocErr)
+    fn dangling(layout: Layout) -> NonNull<[u8]> {
+        // `Layout` guarantees that the alignment is a non-zero power of two:
+        let ptr = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+        NonNull::slice_from_raw_parts(ptr, 0)
     }
 
+    /// Returns whether `ptr` satisfies the alignment of `layout`, in which
+    /// case jemalloc may resize the allocation in place.
     #[inline]
-    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
-        GlobalAlloc::dealloc(self, ptr.as_ptr(), layout)
+    fn is_aligned(ptr: NonNull<u8>, layout: Layout) -> bool {
+        (ptr.as_ptr() as usize) & (layout.align() - 1) == 0
     }
 
     #[inline]
-    unsafe fn realloc(
-        &mut self,
-        ptr: NonNull<u8>,
-        layout: Layout,
-        new_size: usize,
-    ) -> Result<NonNull<u8>, AllocErr> {
-        NonNull::new(GlobalAlloc::realloc(self, ptr.as_ptr(), layout, new_size)).ok_or(AllocErr)
+    pub unsafe fn allocate(layout: Layout, flags: c_int) -> Option<NonNull<[u8]>> {
+        if layout.size() == 0 {
+            return Some(dangling(layout));
+        }
+        let flags = layout_to_flags(layout.align(), layout.size()) | flags;
+        let ptr = NonNull::new(ffi::mallocx(layout.size(), flags) as *mut u8)?;
+        let usable_size = ffi::nallocx(layout.size(), flags);
+        Some(NonNull::slice_from_raw_parts(ptr, usable_size))
     }
 
     #[inline]
-    unsafe fn alloc_excess(&mut self, layout: Layout) -> Result<Excess, AllocErr> {
-        let flags = layout_to_flags(layout.align(), layout.size());
-        let ptr = ffi::mallocx(layout.size(), flags);
-        if let Some(nonnull) = NonNull::new(ptr as *mut u8) {
-            let excess = ffi::nallocx(layout.size(), flags);
-            Ok(Excess(nonnull, excess))
-        } else {
-            Err(AllocErr)
+    pub unsafe fn deallocate(ptr: NonNull<u8>, layout: Layout, flags: c_int) {
+        if layout.size() != 0 {
+            let flags = layout_to_flags(layout.align(), layout.size()) | flags;
+            ffi::sdallocx(ptr.as_ptr() as *mut c_void, layout.size(), flags)
         }
     }
 
     #[inline]
-    unsafe fn realloc_excess(
-        &mut self,
+    pub unsafe fn grow(
         ptr: NonNull<u8>,
-        layout: Layout,
-        new_size: usize,
-    ) -> Result<Excess, AllocErr> {
-        let flags = layout_to_flags(layout.align(), new_size);
-        let ptr = ffi::rallocx(ptr.cast().as_ptr(), new_size, flags);
-        if let Some(nonnull) = NonNull::new(ptr as *mut u8) {
-            let excess = ffi::nallocx(new_size, flags);
-            Ok(Excess(nonnull, excess))
-        } else {
-            Err(AllocErr)
+        old_layout: Layout,
+        new_layout: Layout,
+        flags: c_int,
+    ) -> Option<NonNull<[u8]>> {
+        if old_layout.size() == 0 {
+            return allocate(new_layout, flags);
         }
-    }
-
-    #[inline]
-    fn usable_size(&self, layout: &Layout) -> (usize, usize) {
-        let flags = layout_to_flags(layout.align(), layout.size());
-        unsafe {
-            let max = ffi::nallocx(layout.size(), flags);
-            (layout.size(), max)
+        if flags & ffi::MALLOCX_ZERO != 0 {
+            // jemalloc only zeroes the memory past the usable size of the
+            // original allocation, but the caller might have written to the
+            // bytes between the size of `old_layout` and the usable size:
+            let old_flags = layout_to_flags(old_layout.align(), old_layout.size());
+            let old_usable_size = ffi::nallocx(old_layout.size(), old_flags);
+            ptr::write_bytes(
+                ptr.as_ptr().add(old_layout.size()),
+                0,
+                old_usable_size - old_layout.size(),
+            );
         }
-    }
-
-    #[inline]
-    unsafe fn grow_in_place(
-        &mut self,
-        ptr: NonNull<u8>,
-        layout: Layout,
-        new_size: usize,
-    ) -> Result<(), CannotReallocInPlace> {
-        let flags = layout_to_flags(layout.align(), new_size);
-        let usable_size = ffi::xallocx(ptr.cast().as_ptr(), new_size, 0, flags);
-        if usable_size >= new_size {
-            Ok(())
-        } else {
+        let flags = layout_to_flags(new_layout.align(), new_layout.size()) | flags;
+        if is_aligned(ptr, new_layout) {
+            let usable_size =
+                ffi::xallocx(ptr.as_ptr() as *mut c_void, new_layout.size(), 0, flags);
+            if usable_size >= new_layout.size() {
+                return Some(NonNull::slice_from_raw_parts(ptr, usable_size));
+            }
             // `xallocx` returns a size smaller than the requested one to
             // indicate that the allocation could not be grown in place
             //
             // the old allocation remains unaltered
-            Err(CannotReallocInPlace)
         }
+        let ptr = ffi::rallocx(ptr.as_ptr() as *mut c_void, new_layout.size(), flags);
+        let ptr = NonNull::new(ptr as *mut u8)?;
+        let usable_size = ffi::nallocx(new_layout.size(), flags);
+        Some(NonNull::slice_from_raw_parts(ptr, usable_size))
     }
 
     #[inline]
-    unsafe fn shrink_in_place(
-        &mut self,
+    pub unsafe fn shrink(
         ptr: NonNull<u8>,
-        layout: Layout,
-        new_size: usize,
-    ) -> Result<(), CannotReallocInPlace> {
-        if new_size == layout.size() {
-            return Ok(());
+        old_layout: Layout,
+        new_layout: Layout,
+        flags: c_int,
+    ) -> Option<NonNull<[u8]>> {
+        if new_layout.size() == 0 {
+            deallocate(ptr, old_layout, flags);
+            return Some(dangling(new_layout));
         }
-        let flags = layout_to_flags(layout.align(), new_size);
-        let usable_size = ffi::xallocx(ptr.cast().as_ptr(), new_size, 0, flags);
-
-        if usable_size < layout.size() {
-            // If `usable_size` is smaller than the original size, the
-            // size-class of the allocation was shrunk to the size-class of
-            // `new_size`, and it is safe to deallocate the allocation with
-            // `new_size`:
-            Ok(())
-        } else if usable_size == ffi::nallocx(new_size, flags) {
-            // If the allocation was not shrunk and the size class of `new_size`
-            // is the same as the size-class of `layout.size()`, then the
-            // allocation can be properly deallocated using `new_size` (and also
-            // using `layout.size()` because the allocation did not change)
-
-            // note: when the allocation is not shrunk, `xallocx` returns the
-            // usable size of the original allocation, which in this case matches
-            // that of the requested allocation:
-            debug_assert_eq!(
-                ffi::nallocx(new_size, flags),
-                ffi::nallocx(layout.size(), flags)
-            );
-            Ok(())
-        } else {
-            // If the allocation was not shrunk, but the size-class of
-            // `new_size` is not the same as that of the original allocation,
-            // then shrinking the allocation failed:
-            Err(CannotReallocInPlace)
+        let flags = layout_to_flags(new_layout.align(), new_layout.size()) | flags;
+        let new_usable_size = ffi::nallocx(new_layout.size(), flags);
+        if is_aligned(ptr, new_layout) {
+            let usable_size =
+                ffi::xallocx(ptr.as_ptr() as *mut c_void, new_layout.size(), 0, flags);
+            // The allocation can only be deallocated with `new_layout` if it
+            // ended up in the size-class of `new_layout.size()`. This is the
+            // case if `xallocx` shrunk it, or if both sizes were already in the
+            // same size-class:
+            if usable_size == new_usable_size {
+                return Some(NonNull::slice_from_raw_parts(ptr, usable_size));
+            }
         }
+        let ptr = ffi::rallocx(ptr.as_ptr() as *mut c_void, new_layout.size(), flags);
+        let ptr = NonNull::new(ptr as *mut u8)?;
+        Some(NonNull::slice_from_raw_parts(ptr, new_usable_size))
     }
 }
 
+/// Implements an allocator trait, which has the same shape for
+/// `core::alloc::Allocator` and `allocator_api2::alloc::Allocator`, on top of
+/// the [`sized`] routines. `$flags` returns the flags passed to jemalloc in
+/// addition to the alignment flags, given the allocator.
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
+macro_rules! impl_allocator {
+    ($allocator:path, $alloc_error:ident, $ty:ty, $flags:expr) => {
+        unsafe impl $allocator for $ty {
+            #[inline]
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, $alloc_error> {
+                unsafe { sized::allocate(layout, ($flags)(self)).ok_or($alloc_error) }
+            }
+
+            #[inline]
+            fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, $alloc_error> {
+                let flags = ($flags)(self) | ffi::MALLOCX_ZERO;
+                unsafe { sized::allocate(layout, flags).ok_or($alloc_error) }
+            }
+
+            #[inline]
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                sized::deallocate(ptr, layout, ($flags)(self))
+            }
+
+            #[inline]
+            unsafe fn grow(
+                &self,
+                ptr: NonNull<u8>,
+                old_layout: Layout,
+                new_layout: Layout,
+            ) -> Result<NonNull<[u8]>, $alloc_error> {
+                sized::grow(ptr, old_layout, new_layout, ($flags)(self)).ok_or($alloc_error)
+            }
+
+            #[inline]
+            unsafe fn grow_zeroed(
+                &self,
+                ptr: NonNull<u8>,
+                old_layout: Layout,
+                new_layout: Layout,
+            ) -> Result<NonNull<[u8]>, $alloc_error> {
+                let flags = ($flags)(self) | ffi::MALLOCX_ZERO;
+                sized::grow(ptr, old_layout, new_layout, flags).ok_or($alloc_error)
+            }
+
+            #[inline]
+            unsafe fn shrink(
+                &self,
+                ptr: NonNull<u8>,
+                old_layout: Layout,
+                new_layout: Layout,
+            ) -> Result<NonNull<[u8]>, $alloc_error> {
+                sized::shrink(ptr, old_layout, new_layout, ($flags)(self)).ok_or($alloc_error)
+            }
+        }
+    };
+}
+
+/// Implements `GlobalAlloc` for an allocator type that passes the flags
+/// returned by its `flags` method to every jemalloc call.
+macro_rules! impl_global_alloc {
+    ($ty:ty) => {
+        unsafe impl GlobalAlloc for $ty {
+            #[inline]
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                assume!(layout.size() != 0);
+                let flags = layout_to_flags(layout.align(), layout.size()) | self.flags();
+                ffi::mallocx(layout.size(), flags) as *mut u8
+            }
+
+            #[inline]
+            unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+                assume!(layout.size() != 0);
+                let flags = layout_to_flags(layout.align(), layout.size()) | self.flags();
+                ffi::mallocx(layout.size(), flags | ffi::MALLOCX_ZERO) as *mut u8
+            }
+
+            #[inline]
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                assume!(!ptr.is_null());
+                assume!(layout.size() != 0);
+                let flags = layout_to_flags(layout.align(), layout.size()) | self.flags();
+                ffi::sdallocx(ptr as *mut c_void, layout.size(), flags)
+            }
+
+            #[inline]
+            unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+                assume!(layout.size() != 0);
+                assume!(new_size != 0);
+                let flags = layout_to_flags(layout.align(), new_size) | self.flags();
+                ffi::rallocx(ptr as *mut c_void, new_size, flags) as *mut u8
+            }
+        }
+    };
+}
+
+#[cfg(feature = "allocator_api")]
+impl_allocator!(core::alloc::Allocator, AllocError, Jemalloc, |_| 0);
+
+#[cfg(feature = "allocator-api2")]
+impl_allocator!(
+    allocator_api2::alloc::Allocator,
+    AllocError2,
+    Jemalloc,
+    |_| 0
+);
+
+mod arena;
+mod error;
+mod tcache;
+
+pub use arena::JemallocArena;
+pub use error::Error;
+pub use tcache::JemallocTcache;
+
 /// Return the usable size of the allocation pointed to by ptr.
 ///
 /// The return value may be larger than the size that was requested during allocation.
@@ -0,0 +1,100 @@
+//! Allocator bound to an explicit thread cache.
+
+#[cfg(feature = "allocator-api2")]
+use allocator_api2::alloc::AllocError as AllocError2;
+#[cfg(feature = "allocator_api")]
+use core::alloc::AllocError;
+
+use crate::{ffi, layout_to_flags};
+use core::alloc::{GlobalAlloc, Layout};
+use libc::{c_int, c_void};
+
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
+use crate::sized;
+#[cfg(any(feature = "allocator_api", feature = "allocator-api2"))]
+use core::ptr::NonNull;
+
+/// Handle to an allocator that routes allocations through an explicit thread
+/// cache, or through no thread cache at all.
+///
+/// Every allocation, reallocation and deallocation passes `MALLOCX_TCACHE(tc)`
+/// (or `MALLOCX_TCACHE_NONE`) to jemalloc instead of using the implicit cache
+/// of the calling thread. Explicit caches are created with `tcache.create`,
+/// e.g. through `tikv_jemalloc_ctl::tcache::Tcache`, and are not tied to an OS
+/// thread.
+///
+/// Like [`Jemalloc`](crate::Jemalloc), it implements `GlobalAlloc`, and the
+/// `Allocator` traits when the `allocator_api` or `allocator-api2` features
+/// are enabled.
+///
+/// # Example
+///
+/// ```rust
+/// use core::alloc::{GlobalAlloc, Layout};
+/// use tikv_jemalloc_ctl::tcache::Tcache;
+/// use tikv_jemallocator::JemallocTcache;
+///
+/// let tcache = Tcache::new().unwrap();
+/// // Safe: the cache outlives the allocator and is only used by this thread.
+/// let alloc = unsafe { JemallocTcache::new(tcache.index()) };
+/// let layout = Layout::from_size_align(64, 8).unwrap();
+/// unsafe {
+///     let ptr = alloc.alloc(layout);
+///     assert!(!ptr.is_null());
+///     alloc.dealloc(ptr, layout);
+/// }
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct JemallocTcache {
+    flags: c_int,
+}
+
+impl JemallocTcache {
+    /// Returns an allocator that uses the explicit thread cache with index
+    /// `index`.
+    ///
+    /// # Safety
+    ///
+    /// The thread cache must have been created with `tcache.create` and must
+    /// not be destroyed while the allocator is in use. A thread cache is not
+    /// thread-safe: it must not be used by several threads concurrently,
+    /// through this allocator or otherwise.
+    #[inline]
+    pub const unsafe fn new(index: u32) -> Self {
+        JemallocTcache {
+            flags: ffi::MALLOCX_TCACHE(index as usize),
+        }
+    }
+
+    /// Returns an allocator that bypasses thread caches altogether.
+    #[inline]
+    pub const fn none() -> Self {
+        JemallocTcache {
+            flags: ffi::MALLOCX_TCACHE_NONE,
+        }
+    }
+
+    /// Flags passed to jemalloc in addition to the alignment flags.
+    #[inline]
+    fn flags(&self) -> c_int {
+        self.flags
+    }
+}
+
+impl_global_alloc!(JemallocTcache);
+
+#[cfg(feature = "allocator_api")]
+impl_allocator!(
+    core::alloc::Allocator,
+    AllocError,
+    JemallocTcache,
+    JemallocTcache::flags
+);
+
+#[cfg(feature = "allocator-api2")]
+impl_allocator!(
+    allocator_api2::alloc::Allocator,
+    AllocError2,
+    JemallocTcache,
+    JemallocTcache::flags
+);
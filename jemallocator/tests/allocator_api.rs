@@ -0,0 +1,59 @@
+#![cfg(feature = "allocator_api")]
+#![feature(allocator_api)]
+
+use std::alloc::{Allocator, Layout};
+use tikv_jemallocator::Jemalloc;
+
+#[global_allocator]
+static A: Jemalloc = Jemalloc;
+
+#[test]
+fn collections() {
+    let mut v: Vec<u64, Jemalloc> = Vec::new_in(Jemalloc);
+    v.extend(0..1000);
+    v.truncate(10);
+    v.shrink_to_fit();
+    assert_eq!(v.iter().sum::<u64>(), 45);
+
+    let b = Box::new_in([1u8; 100], Jemalloc);
+    assert_eq!(b.len(), 100);
+}
+
+#[test]
+fn usable_size() {
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let ptr = Jemalloc.allocate(layout).unwrap();
+    unsafe {
+        assert_eq!(
+            ptr.len(),
+            tikv_jemallocator::usable_size(ptr.cast::<u8>().as_ptr())
+        );
+        Jemalloc.deallocate(ptr.cast(), layout);
+    }
+}
+
+#[test]
+fn allocate_zeroed() {
+    let layout = Layout::from_size_align(1000, 64).unwrap();
+    let ptr = Jemalloc.allocate_zeroed(layout).unwrap();
+    unsafe {
+        let bytes = &*ptr.as_ptr();
+        assert_eq!(ptr.cast::<u8>().as_ptr() as usize % 64, 0);
+        assert!(bytes.iter().all(|&b| b == 0));
+        Jemalloc.deallocate(ptr.cast(), layout);
+    }
+}
+
+#[test]
+fn zero_sized() {
+    let layout = Layout::from_size_align(0, 32).unwrap();
+    let ptr = Jemalloc.allocate(layout).unwrap();
+    assert_eq!(ptr.len(), 0);
+    assert_eq!(ptr.cast::<u8>().as_ptr() as usize % 32, 0);
+    unsafe {
+        let new_layout = Layout::from_size_align(48, 32).unwrap();
+        let grown = Jemalloc.grow(ptr.cast(), layout, new_layout).unwrap();
+        assert!(grown.len() >= 48);
+        Jemalloc.deallocate(grown.cast(), new_layout);
+    }
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "allocator-api2")]
+
+use allocator_api2::alloc::{Allocator, Layout};
+use allocator_api2::boxed::Box;
+use allocator_api2::vec::Vec;
+use tikv_jemallocator::Jemalloc;
+
+#[global_allocator]
+static A: Jemalloc = Jemalloc;
+
+#[test]
+fn collections() {
+    let mut v: Vec<u64, Jemalloc> = Vec::new_in(Jemalloc);
+    v.extend(0..1000);
+    v.truncate(10);
+    v.shrink_to_fit();
+    assert_eq!(v.iter().sum::<u64>(), 45);
+
+    let b = Box::new_in([1u8; 100], Jemalloc);
+    assert_eq!(b.len(), 100);
+}
+
+#[test]
+fn usable_size() {
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let ptr = Jemalloc.allocate(layout).unwrap();
+    unsafe {
+        assert_eq!(
+            ptr.len(),
+            tikv_jemallocator::usable_size(ptr.cast::<u8>().as_ptr())
+        );
+        Jemalloc.deallocate(ptr.cast(), layout);
+    }
+}
+
+#[test]
+fn grow_and_shrink_in_place() {
+    unsafe {
+        // 7 bytes end up in the 8 byte size-class, so growing to 8 bytes
+        // happens in place:
+        let orig_l = Layout::from_size_align(7, 1).unwrap();
+        let ptr = Jemalloc.allocate(orig_l).unwrap();
+        assert_eq!(ptr.len(), 8);
+        let new_l = Layout::from_size_align(8, 1).unwrap();
+        let grown = Jemalloc.grow(ptr.cast(), orig_l, new_l).unwrap();
+        assert_eq!(grown.cast::<u8>(), ptr.cast::<u8>());
+
+        // and shrinking back to 7 bytes too:
+        let shrunk = Jemalloc.shrink(grown.cast(), new_l, orig_l).unwrap();
+        assert_eq!(shrunk.cast::<u8>(), ptr.cast::<u8>());
+        assert_eq!(shrunk.len(), 8);
+        Jemalloc.deallocate(shrunk.cast(), orig_l);
+    }
+}
+
+#[test]
+fn arena_collections() {
+    use tikv_jemallocator::JemallocArena;
+
+    let arena = JemallocArena::new().unwrap();
+    let mut v: Vec<u64, JemallocArena> = Vec::new_in(arena);
+    v.extend(0..1000);
+    v.truncate(10);
+    v.shrink_to_fit();
+    assert_eq!(v.iter().sum::<u64>(), 45);
+}
@@ -0,0 +1,105 @@
+extern crate tikv_jemalloc_sys as ffi;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::mem;
+use std::ptr;
+
+use libc::c_void;
+use tikv_jemallocator::{Jemalloc, JemallocArena};
+
+#[global_allocator]
+static A: Jemalloc = Jemalloc;
+
+/// Returns the index of the arena that owns `ptr`.
+fn arena_of(ptr: *mut u8) -> u32 {
+    let mut index: u32 = 0;
+    let mut len = mem::size_of::<u32>();
+    let mut ptr = ptr as *const c_void;
+    unsafe {
+        assert_eq!(
+            ffi::mallctl(
+                b"arenas.lookup\0".as_ptr() as *const _,
+                &mut index as *mut u32 as *mut c_void,
+                &mut len,
+                &mut ptr as *mut *const c_void as *mut c_void,
+                mem::size_of::<*const c_void>(),
+            ),
+            0
+        );
+    }
+    index
+}
+
+#[test]
+fn smoke() {
+    let arena = JemallocArena::new().unwrap();
+    assert_ne!(arena.index(), 0);
+    assert_eq!(unsafe { JemallocArena::from_index(arena.index()) }, arena);
+
+    // small, large, and over-aligned allocations:
+    for &(size, align) in &[(8, 8), (100, 8), (1 << 20, 8), (64, 4096)] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        unsafe {
+            let ptr = arena.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0);
+            assert_eq!(arena_of(ptr), arena.index());
+
+            let ptr = arena.realloc(ptr, layout, size * 2);
+            assert!(!ptr.is_null());
+            assert_eq!(arena_of(ptr), arena.index());
+            let layout = Layout::from_size_align(size * 2, align).unwrap();
+            arena.dealloc(ptr, layout);
+
+            let ptr = arena.alloc_zeroed(layout);
+            assert!(!ptr.is_null());
+            assert!(std::slice::from_raw_parts(ptr, layout.size())
+                .iter()
+                .all(|&b| b == 0));
+            arena.dealloc(ptr, layout);
+        }
+    }
+}
+
+#[test]
+fn arenas_are_distinct() {
+    let a = JemallocArena::new().unwrap();
+    let b = JemallocArena::new().unwrap();
+    assert_ne!(a.index(), b.index());
+
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    unsafe {
+        let pa = a.alloc(layout);
+        let pb = b.alloc(layout);
+        assert_eq!(arena_of(pa), a.index());
+        assert_eq!(arena_of(pb), b.index());
+        a.dealloc(pa, layout);
+        b.dealloc(pb, layout);
+    }
+}
+
+#[test]
+fn reset_and_destroy() {
+    let arena = JemallocArena::new().unwrap();
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    unsafe {
+        for _ in 0..16 {
+            assert!(!arena.alloc(layout).is_null());
+        }
+        arena.reset().unwrap();
+
+        let ptr = arena.alloc(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(arena_of(ptr), arena.index());
+        ptr::write_bytes(ptr, 1, layout.size());
+
+        arena.destroy().unwrap();
+    }
+}
+
+#[test]
+fn invalid_index() {
+    let arena = unsafe { JemallocArena::from_index(u32::MAX >> 4) };
+    let err = unsafe { arena.reset() }.unwrap_err();
+    assert_eq!(err.code(), libc::ENOENT);
+}
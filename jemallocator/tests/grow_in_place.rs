@@ -1,4 +1,4 @@
-#![cfg_attr(feature = "alloc_trait", feature(allocator_api))]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
 use tikv_jemallocator::Jemalloc;
 
@@ -6,28 +6,56 @@ use tikv_jemallocator::Jemalloc;
 static A: Jemalloc = Jemalloc;
 
 #[test]
-#[cfg(feature = "alloc_trait")]
-fn shrink_in_place() {
+#[cfg(feature = "allocator_api")]
+fn grow_in_place() {
     unsafe {
-        use std::alloc::{Alloc, Layout};
+        use std::alloc::{Allocator, Layout};
 
         // allocate 7 bytes which end up in the 8 byte size-class as long as
         // jemalloc's default size classes are used:
         let orig_sz = 7;
         let orig_l = Layout::from_size_align(orig_sz, 1).unwrap();
-        let ptr = Jemalloc.alloc(orig_l).unwrap();
+        let ptr = Jemalloc.allocate(orig_l).unwrap();
+        assert_eq!(ptr.len(), 8);
 
-        // try to grow it in place by 1 byte - it should grow without problems:
+        // try to grow it by 1 byte - it should grow in place without problems:
         let new_sz = orig_sz + 1;
-        assert!(Jemalloc.grow_in_place(ptr, orig_l, new_sz).is_ok());
-        let new_l = Layout::from_size_align(orig_sz + 1, 1).unwrap();
+        let new_l = Layout::from_size_align(new_sz, 1).unwrap();
+        let grown = Jemalloc.grow(ptr.cast(), orig_l, new_l).unwrap();
+        assert_eq!(grown.cast::<u8>(), ptr.cast::<u8>());
+        assert_eq!(grown.len(), 8);
 
-        // trying to do it again fails because it would require moving the
-        // allocation to a different size class which jemalloc's xallocx does not
-        // do:
-        let new_sz = new_sz + 1;
-        assert!(Jemalloc.grow_in_place(ptr, new_l, new_sz).is_err());
+        // growing it again requires moving the allocation to a different
+        // size class, which jemalloc's xallocx does not do, so the allocation
+        // is reallocated instead:
+        let newer_l = Layout::from_size_align(new_sz + 1, 1).unwrap();
+        let moved = Jemalloc.grow(grown.cast(), new_l, newer_l).unwrap();
+        assert!(moved.len() >= newer_l.size());
 
-        Jemalloc.dealloc(ptr, new_l)
+        Jemalloc.deallocate(moved.cast(), newer_l)
+    }
+}
+
+#[test]
+#[cfg(feature = "allocator_api")]
+fn grow_zeroed() {
+    unsafe {
+        use std::alloc::{Allocator, Layout};
+
+        let orig_l = Layout::from_size_align(7, 1).unwrap();
+        for new_sz in [8, 4096, 10 * 4096] {
+            let ptr = Jemalloc.allocate(orig_l).unwrap();
+            // write to the whole usable size of the allocation, including the
+            // bytes past the size of the layout:
+            ptr.cast::<u8>().as_ptr().write_bytes(0xff, ptr.len());
+
+            let new_l = Layout::from_size_align(new_sz, 1).unwrap();
+            let grown = Jemalloc.grow_zeroed(ptr.cast(), orig_l, new_l).unwrap();
+            let bytes = std::slice::from_raw_parts(grown.cast::<u8>().as_ptr(), new_sz);
+            assert!(bytes[..orig_l.size()].iter().all(|&b| b == 0xff));
+            assert!(bytes[orig_l.size()..].iter().all(|&b| b == 0));
+
+            Jemalloc.deallocate(grown.cast(), new_l);
+        }
     }
 }
@@ -1,4 +1,4 @@
-#![cfg_attr(feature = "alloc_trait", feature(allocator_api))]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
 use tikv_jemallocator::Jemalloc;
 
@@ -6,27 +6,39 @@ use tikv_jemallocator::Jemalloc;
 static A: Jemalloc = Jemalloc;
 
 #[test]
-#[cfg(feature = "alloc_trait")]
+#[cfg(feature = "allocator_api")]
 fn shrink_in_place() {
     unsafe {
-        use std::alloc::{Alloc, Layout};
+        use std::alloc::{Allocator, Layout};
 
         // allocate a "large" block of memory:
         let orig_sz = 10 * 4096;
         let orig_l = Layout::from_size_align(orig_sz, 1).unwrap();
-        let ptr = Jemalloc.alloc(orig_l).unwrap();
-
-        // try to shrink it in place to 1 byte - if this succeeds,
-        // the size-class of the new allocation should be different
-        // than that of the original allocation:
-        let new_sz = 1;
-        if let Ok(()) = Jemalloc.shrink_in_place(ptr, orig_l, new_sz) {
-            // test that deallocating with the new layout succeeds:
-            let new_l = Layout::from_size_align(new_sz, 1).unwrap();
-            Jemalloc.dealloc(ptr, new_l);
-        } else {
-            // if shrink in place failed - deallocate with the old layout
-            Jemalloc.dealloc(ptr, orig_l);
-        }
+        let ptr = Jemalloc.allocate(orig_l).unwrap();
+
+        // shrink it to 1 byte - jemalloc cannot shrink a large allocation into
+        // a small size-class in place, so the allocation is moved, and it must
+        // be deallocated with the new layout:
+        let new_l = Layout::from_size_align(1, 1).unwrap();
+        let shrunk = Jemalloc.shrink(ptr.cast(), orig_l, new_l).unwrap();
+        assert!(shrunk.len() >= new_l.size());
+        Jemalloc.deallocate(shrunk.cast(), new_l);
+    }
+}
+
+#[test]
+#[cfg(feature = "allocator_api")]
+fn shrink_to_zero() {
+    unsafe {
+        use std::alloc::{Allocator, Layout};
+
+        let orig_l = Layout::from_size_align(64, 16).unwrap();
+        let ptr = Jemalloc.allocate(orig_l).unwrap();
+
+        let new_l = Layout::from_size_align(0, 16).unwrap();
+        let shrunk = Jemalloc.shrink(ptr.cast(), orig_l, new_l).unwrap();
+        assert_eq!(shrunk.len(), 0);
+        assert_eq!(shrunk.cast::<u8>().as_ptr() as usize % 16, 0);
+        Jemalloc.deallocate(shrunk.cast(), new_l);
     }
 }
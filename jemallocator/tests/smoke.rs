@@ -5,6 +5,7 @@ use tikv_jemallocator::Jemalloc;
 static A: Jemalloc = Jemalloc;
 
 #[test]
+#[allow(clippy::reserve_after_initialization)]
 fn smoke() {
     let mut a = Vec::new();
     a.reserve(1);
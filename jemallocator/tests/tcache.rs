@@ -0,0 +1,61 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::thread;
+
+use tikv_jemalloc_ctl::tcache::Tcache;
+use tikv_jemallocator::{Jemalloc, JemallocTcache};
+
+#[global_allocator]
+static A: Jemalloc = Jemalloc;
+
+fn roundtrip(alloc: &JemallocTcache) {
+    for &(size, align) in &[(8, 8), (100, 8), (1 << 20, 8), (64, 4096)] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        unsafe {
+            let ptr = alloc.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0);
+            let ptr = alloc.realloc(ptr, layout, size * 2);
+            assert!(!ptr.is_null());
+            let layout = Layout::from_size_align(size * 2, align).unwrap();
+            alloc.dealloc(ptr, layout);
+        }
+    }
+}
+
+#[test]
+fn explicit_tcache() {
+    let tcache = Tcache::new().unwrap();
+    let alloc = unsafe { JemallocTcache::new(tcache.index()) };
+    roundtrip(&alloc);
+    tcache.flush().unwrap();
+    tcache.destroy().unwrap();
+}
+
+#[test]
+fn no_tcache() {
+    roundtrip(&JemallocTcache::none());
+}
+
+#[test]
+fn tcache_moves_between_threads() {
+    let tcache = Tcache::new().unwrap();
+    let layout = Layout::from_size_align(48, 8).unwrap();
+
+    // Allocate on one thread and deallocate on another one through the same
+    // cache, as a task migrating between executor threads would:
+    let (tcache, ptr) = thread::spawn(move || {
+        let alloc = unsafe { JemallocTcache::new(tcache.index()) };
+        let ptr = unsafe { alloc.alloc(layout) };
+        assert!(!ptr.is_null());
+        (tcache, ptr as usize)
+    })
+    .join()
+    .unwrap();
+    thread::spawn(move || {
+        let alloc = unsafe { JemallocTcache::new(tcache.index()) };
+        unsafe { alloc.dealloc(ptr as *mut u8, layout) };
+        drop(tcache);
+    })
+    .join()
+    .unwrap();
+}
@@ -0,0 +1,220 @@
+//! Reads the heap profiles written by jemalloc's `prof.dump`.
+//!
+//! This covers the parts of jemalloc's Perl `jeprof` script that are used
+//! the most, for hosts without Perl:
+//!
+//! ```text
+//! jeprof-rs [--text|--collapsed|--svg] [--inuse_space|--inuse_objects]
+//!           [--nodecount=<n>] [--base=<profile>] <binary> <profile>
+//! ```
+
+mod report;
+mod symbolize;
+
+use crate::report::{Sample, Unit};
+use crate::symbolize::Symbolizer;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+use tikv_jemalloc_ctl::profiling::HeapProfile;
+
+const USAGE: &str = "\
+Usage: jeprof-rs [OPTIONS] <binary> <profile>
+
+Reads a heap profile written by jemalloc's `prof.dump` and symbolizes it
+through the debug information of <binary> and of the shared objects listed in
+the profile.
+
+Output format:
+  --text              Top functions by flat and cumulative usage (default)
+  --collapsed         Collapsed stacks, as used by flamegraph.pl
+  --svg               Flamegraph
+
+Options:
+  --inuse_space       Weight stacks by live bytes (default)
+  --inuse_objects     Weight stacks by live objects
+  --nodecount=<n>     Show at most <n> functions in --text output
+  --base=<profile>    Subtract <profile> first, to show what changed since
+  -h, --help          Show this help
+";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Format {
+    Text,
+    Collapsed,
+    Svg,
+}
+
+#[derive(PartialEq, Debug)]
+struct Args {
+    format: Format,
+    unit: Unit,
+    nodecount: Option<usize>,
+    base: Option<PathBuf>,
+    binary: PathBuf,
+    profile: PathBuf,
+}
+
+impl Args {
+    /// Parses the arguments, without the program name. Returns `Ok(None)` if
+    /// help was requested.
+    fn parse<I>(args: I) -> Result<Option<Args>, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut format = Format::Text;
+        let mut unit = Unit::Bytes;
+        let mut nodecount = None;
+        let mut base = None;
+        let mut positional = Vec::new();
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            match (name, value) {
+                ("-h", None) | ("--help", None) => return Ok(None),
+                ("--text", None) => format = Format::Text,
+                ("--collapsed", None) => format = Format::Collapsed,
+                ("--svg", None) => format = Format::Svg,
+                ("--inuse_space", None) => unit = Unit::Bytes,
+                ("--inuse_objects", None) => unit = Unit::Objects,
+                ("--nodecount", Some(n)) => {
+                    nodecount = Some(
+                        n.parse()
+                            .map_err(|_| format!("invalid --nodecount value `{}`", n))?,
+                    )
+                }
+                ("--base", Some(path)) => base = Some(PathBuf::from(path)),
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ => positional.push(PathBuf::from(arg)),
+            }
+        }
+        match <[PathBuf; 2]>::try_from(positional) {
+            Ok([binary, profile]) => Ok(Some(Args {
+                format,
+                unit,
+                nodecount,
+                base,
+                binary,
+                profile,
+            })),
+            Err(_) => Err("expected <binary> and <profile>".to_owned()),
+        }
+    }
+}
+
+fn read_profile(path: &PathBuf) -> Result<HeapProfile, Box<dyn Error>> {
+    let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let profile = HeapProfile::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(profile)
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let profile = read_profile(&args.profile)?;
+    let samples: Vec<Sample> = match args.base {
+        Some(ref base) => {
+            let base = read_profile(base)?;
+            profile
+                .diff(&base)
+                .stacks
+                .iter()
+                .map(|stack| Sample {
+                    pcs: stack.pcs().collect(),
+                    value: args.unit.delta(stack),
+                })
+                .collect()
+        }
+        None => profile
+            .stacks
+            .iter()
+            .map(|stack| Sample {
+                pcs: stack.pcs().collect(),
+                value: args.unit.value(&profile.estimate(&stack.counts)),
+            })
+            .collect(),
+    };
+
+    let mut symbolizer = Symbolizer::new(&args.binary, &profile.mappings);
+    let stacks = report::symbolize(&samples, &mut symbolizer);
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match args.format {
+        Format::Text => report::write_text(&mut out, &stacks, args.unit, args.nodecount)?,
+        Format::Collapsed => report::write_collapsed(&mut out, &stacks)?,
+        Format::Svg => report::write_svg(&mut out, &stacks, args.unit)?,
+    }
+    out.flush()?;
+    for warning in symbolizer.warnings() {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parse_args() {
+        assert_eq!(
+            parse(&["app", "app.heap"]).unwrap(),
+            Some(Args {
+                format: Format::Text,
+                unit: Unit::Bytes,
+                nodecount: None,
+                base: None,
+                binary: "app".into(),
+                profile: "app.heap".into(),
+            })
+        );
+        assert_eq!(
+            parse(&[
+                "--svg",
+                "--inuse_objects",
+                "--nodecount=10",
+                "--base=base.heap",
+                "app",
+                "app.heap",
+            ])
+            .unwrap(),
+            Some(Args {
+                format: Format::Svg,
+                unit: Unit::Objects,
+                nodecount: Some(10),
+                base: Some("base.heap".into()),
+                binary: "app".into(),
+                profile: "app.heap".into(),
+            })
+        );
+        assert_eq!(parse(&["--help"]).unwrap(), None);
+        assert!(parse(&["app"]).is_err());
+        assert!(parse(&["--nodecount=x", "app", "app.heap"]).is_err());
+        assert!(parse(&["--pdf", "app", "app.heap"]).is_err());
+    }
+}
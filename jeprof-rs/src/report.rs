@@ -0,0 +1,249 @@
+//! Output formats.
+
+use crate::symbolize::Symbolizer;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+use tikv_jemalloc_ctl::profiling::{trim_allocator_frames, Estimate, StackDelta};
+
+/// What the stacks are weighted by.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Unit {
+    Bytes,
+    Objects,
+}
+
+impl Unit {
+    pub fn value(self, estimate: &Estimate) -> f64 {
+        match self {
+            Unit::Bytes => estimate.bytes,
+            Unit::Objects => estimate.objects,
+        }
+    }
+
+    pub fn delta(self, stack: &StackDelta) -> f64 {
+        match self {
+            Unit::Bytes => stack.bytes(),
+            Unit::Objects => stack.objects(),
+        }
+    }
+
+    fn format(self, value: f64) -> String {
+        match self {
+            Unit::Bytes => format!("{:.1}", value / (1024.0 * 1024.0)),
+            Unit::Objects => format!("{:.0}", value),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Unit::Bytes => "MB",
+            Unit::Objects => "objects",
+        }
+    }
+}
+
+/// Stack of instruction addresses, innermost frame first, and its weight.
+pub struct Sample {
+    pub pcs: Vec<u64>,
+    pub value: f64,
+}
+
+/// Stack of function names, innermost frame first, and its weight.
+pub struct Stack {
+    pub frames: Vec<String>,
+    pub value: f64,
+}
+
+/// Names the frames of `samples`, leaving out the frames of the allocator
+/// at the top of the stacks.
+pub fn symbolize(samples: &[Sample], symbolizer: &mut Symbolizer) -> Vec<Stack> {
+    samples
+        .iter()
+        .map(|sample| {
+            let mut frames = Vec::new();
+            for &pc in &sample.pcs {
+                frames.extend(symbolizer.names(pc).iter().cloned());
+            }
+            trim_allocator_frames(&mut frames, |f| f);
+            Stack {
+                frames,
+                value: sample.value,
+            }
+        })
+        .collect()
+}
+
+/// Writes the functions by decreasing flat value, like `jeprof --text`.
+pub fn write_text<W: Write>(
+    mut out: W,
+    stacks: &[Stack],
+    unit: Unit,
+    nodecount: Option<usize>,
+) -> io::Result<()> {
+    let mut flat: HashMap<&str, f64> = HashMap::new();
+    let mut cum: HashMap<&str, f64> = HashMap::new();
+    let mut total = 0.0;
+    for stack in stacks {
+        total += stack.value;
+        if let Some(leaf) = stack.frames.first() {
+            *flat.entry(leaf).or_insert(0.0) += stack.value;
+        }
+        // Recursive functions count once.
+        let functions: HashSet<&str> = stack.frames.iter().map(String::as_str).collect();
+        for function in functions {
+            *cum.entry(function).or_insert(0.0) += stack.value;
+        }
+    }
+
+    let mut functions: Vec<(&str, f64, f64)> = cum
+        .iter()
+        .map(|(&name, &cum)| (name, flat.get(name).copied().unwrap_or(0.0), cum))
+        .collect();
+    functions.sort_by(|a, b| {
+        b.1.total_cmp(&a.1)
+            .then(b.2.total_cmp(&a.2))
+            .then(a.0.cmp(b.0))
+    });
+
+    let percent = |value: f64| {
+        if total == 0.0 {
+            0.0
+        } else {
+            value / total * 100.0
+        }
+    };
+    writeln!(out, "Total: {} {}", unit.format(total), unit.name())?;
+    let mut sum = 0.0;
+    for (name, flat, cum) in functions.iter().take(nodecount.unwrap_or(usize::MAX)) {
+        sum += flat;
+        writeln!(
+            out,
+            "{:>8} {:>5.1}% {:>5.1}% {:>8} {:>5.1}% {}",
+            unit.format(*flat),
+            percent(*flat),
+            percent(sum),
+            unit.format(*cum),
+            percent(*cum),
+            name
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the collapsed stacks, sorted and with the values of identical
+/// stacks added up. Only positive values are kept, since flamegraphs cannot
+/// show the stacks that shrank in a diff.
+fn collapse(stacks: &[Stack]) -> BTreeMap<String, u64> {
+    let mut collapsed = BTreeMap::new();
+    for stack in stacks {
+        let value = stack.value.round();
+        if value < 1.0 {
+            continue;
+        }
+        let frames: Vec<String> = stack
+            .frames
+            .iter()
+            .rev()
+            // `;` separates frames, but appears in names such as `[u8; 4]`.
+            .map(|f| f.replace(';', ","))
+            .collect();
+        *collapsed.entry(frames.join(";")).or_insert(0) += value as u64;
+    }
+    collapsed
+}
+
+/// Writes the stacks in the collapsed format of `flamegraph.pl`.
+pub fn write_collapsed<W: Write>(mut out: W, stacks: &[Stack]) -> io::Result<()> {
+    for (stack, value) in collapse(stacks) {
+        writeln!(out, "{} {}", stack, value)?;
+    }
+    Ok(())
+}
+
+/// Writes a flamegraph of the stacks.
+pub fn write_svg<W: Write>(out: W, stacks: &[Stack], unit: Unit) -> io::Result<()> {
+    let lines: Vec<String> = collapse(stacks)
+        .into_iter()
+        .map(|(stack, value)| format!("{} {}", stack, value))
+        .collect();
+    let mut options = inferno::flamegraph::Options::default();
+    options.title = "Heap profile".to_owned();
+    options.count_name = match unit {
+        Unit::Bytes => "bytes",
+        Unit::Objects => "objects",
+    }
+    .to_owned();
+    inferno::flamegraph::from_lines(&mut options, lines.iter().map(String::as_str), out)
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stacks() -> Vec<Stack> {
+        let stack = |frames: &[&str], value| Stack {
+            frames: frames.iter().map(|f| f.to_string()).collect(),
+            value,
+        };
+        vec![
+            stack(&["leaf", "parse", "main"], 3.0 * 1024.0 * 1024.0),
+            stack(&["parse", "main"], 1024.0 * 1024.0),
+            stack(&["recurse", "recurse", "main"], -1024.0 * 1024.0),
+        ]
+    }
+
+    #[test]
+    fn allocator_frames() {
+        let mut symbolizer = Symbolizer::with_names(&[
+            (0x1, &["_rjem_malloc"]),
+            (0x2, &["alloc::alloc::alloc", "__rust_alloc"]),
+            (0x3, &["app::parse"]),
+            (0x4, &["app::main"]),
+        ]);
+        let samples = [
+            Sample {
+                pcs: vec![0x1, 0x2, 0x3, 0x4],
+                value: 1.0,
+            },
+            Sample {
+                pcs: vec![0x1, 0x2],
+                value: 1.0,
+            },
+        ];
+        let stacks = symbolize(&samples, &mut symbolizer);
+        assert_eq!(stacks[0].frames, ["app::parse", "app::main"]);
+        assert_eq!(stacks[1].frames, ["__rust_alloc"]);
+    }
+
+    #[test]
+    fn text() {
+        let mut out = Vec::new();
+        write_text(&mut out, &stacks(), Unit::Bytes, Some(3)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Total: 3.0 MB
+     3.0 100.0% 100.0%      3.0 100.0% leaf
+     1.0  33.3% 133.3%      4.0 133.3% parse
+     0.0   0.0% 133.3%      3.0 100.0% main
+"
+        );
+    }
+
+    #[test]
+    fn collapsed() {
+        let mut out = Vec::new();
+        write_collapsed(&mut out, &stacks()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main;parse 1048576\nmain;parse;leaf 3145728\n"
+        );
+    }
+
+    #[test]
+    fn svg() {
+        let mut out = Vec::new();
+        write_svg(&mut out, &stacks(), Unit::Objects).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("parse"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Offline symbolization through the ELF/DWARF of the profiled binaries.
+
+use object::{Object as _, ObjectSegment as _};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tikv_jemalloc_ctl::profiling::Mapping;
+
+/// Names the addresses of a profile.
+///
+/// Addresses in the mapping of the main program are resolved with the
+/// binary given on the command line, which may have been moved since the
+/// profile was dumped; other addresses are resolved with the files of their
+/// mappings.
+pub struct Symbolizer<'a> {
+    binary: &'a Path,
+    mappings: &'a [Mapping],
+    objects: HashMap<PathBuf, Option<Object>>,
+    names: HashMap<u64, Vec<String>>,
+    warnings: Vec<String>,
+}
+
+/// Loaded object file.
+struct Object {
+    loader: addr2line::Loader,
+    /// `(file offset, file size, address)` of the loadable segments.
+    segments: Vec<(u64, u64, u64)>,
+}
+
+impl Object {
+    fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let loader = addr2line::Loader::new(path)?;
+        let file = File::open(path)?;
+        // This is safe as long as the file is not modified while mapped,
+        // which is also what `addr2line` assumes.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        let object = object::File::parse(&*map)?;
+        let segments = object
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                (offset, size, segment.address())
+            })
+            .collect();
+        Ok(Object { loader, segments })
+    }
+
+    /// Translates an offset in the file to the address the debug
+    /// information refers to.
+    fn address(&self, file_offset: u64) -> Option<u64> {
+        self.segments
+            .iter()
+            .find(|&&(offset, size, _)| offset <= file_offset && file_offset - offset < size)
+            .map(|&(offset, _, address)| file_offset - offset + address)
+    }
+
+    /// Returns the names of the frames of `probe`, innermost inlined frame
+    /// first.
+    fn names(&self, probe: u64) -> Vec<String> {
+        let mut names = Vec::new();
+        if let Ok(mut frames) = self.loader.find_frames(probe) {
+            while let Ok(Some(frame)) = frames.next() {
+                if let Some(function) = frame.function {
+                    if let Ok(raw) = function.raw_name() {
+                        names.push(demangle(raw, function.language));
+                    }
+                }
+            }
+        }
+        if names.is_empty() {
+            // No debug information, fall back to the symbol table.
+            if let Some(symbol) = self.loader.find_symbol(probe) {
+                names.push(demangle(Cow::Borrowed(symbol), None));
+            }
+        }
+        names
+    }
+}
+
+fn demangle(name: Cow<str>, language: Option<addr2line::gimli::DwLang>) -> String {
+    // `{:#}` leaves out the hash of Rust symbols.
+    match rustc_demangle::try_demangle(&name) {
+        Ok(demangled) => format!("{:#}", demangled),
+        Err(_) => addr2line::demangle_auto(name, language).into_owned(),
+    }
+}
+
+impl<'a> Symbolizer<'a> {
+    pub fn new(binary: &'a Path, mappings: &'a [Mapping]) -> Self {
+        Symbolizer {
+            binary,
+            mappings,
+            objects: HashMap::new(),
+            names: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Returns the names of the frames of `pc`, innermost inlined frame
+    /// first, or its hexadecimal address if it cannot be resolved.
+    pub fn names(&mut self, pc: u64) -> &[String] {
+        if !self.names.contains_key(&pc) {
+            let mut names = self.resolve(pc);
+            if names.is_empty() {
+                names.push(format!("{:#x}", pc));
+            }
+            self.names.insert(pc, names);
+        }
+        &self.names[&pc]
+    }
+
+    /// Problems met while loading object files.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    fn resolve(&mut self, pc: u64) -> Vec<String> {
+        let mappings = self.mappings;
+        let mapping = match mappings.iter().find(|m| m.contains(pc)) {
+            Some(mapping) => mapping,
+            None => return Vec::new(),
+        };
+        let path = match mapping.path {
+            // Pseudo-paths such as `[vdso]` cannot be loaded.
+            Some(ref path) if !path.starts_with('[') => Path::new(path),
+            _ => return Vec::new(),
+        };
+        let path = if path.file_name() == self.binary.file_name() {
+            self.binary
+        } else {
+            path
+        };
+        let file_offset = pc - mapping.start + mapping.offset;
+
+        let warnings = &mut self.warnings;
+        let object =
+            self.objects
+                .entry(path.to_owned())
+                .or_insert_with(|| match Object::open(path) {
+                    Ok(object) => Some(object),
+                    Err(e) => {
+                        warnings.push(format!("cannot load {}: {}", path.display(), e));
+                        None
+                    }
+                });
+        match object {
+            Some(object) => match object.address(file_offset) {
+                Some(probe) => object.names(probe),
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns a symbolizer with fixed names, for tests.
+    #[cfg(test)]
+    pub fn with_names(names: &[(u64, &[&str])]) -> Self {
+        let mut symbolizer = Symbolizer::new(Path::new(""), &[]);
+        for &(pc, pc_names) in names {
+            let pc_names = pc_names.iter().map(|n| n.to_string()).collect();
+            symbolizer.names.insert(pc, pc_names);
+        }
+        symbolizer
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tikv_jemalloc_ctl::profiling::HeapProfile;
+
+    #[inline(never)]
+    fn symbolized_function() -> u64 {
+        symbolized_function as fn() -> u64 as usize as u64
+    }
+
+    #[test]
+    fn symbolize_self() {
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let profile = format!(
+            "heap_v2/1\n  t*: 0: 0 [0: 0]\n\nMAPPED_LIBRARIES:\n{}",
+            maps
+        );
+        let profile = HeapProfile::parse(profile.as_bytes()).unwrap();
+        let binary = std::env::current_exe().unwrap();
+        let mut symbolizer = Symbolizer::new(&binary, &profile.mappings);
+
+        let names = symbolizer.names(symbolized_function()).to_vec();
+        assert!(
+            names.iter().any(|n| n.ends_with("::symbolized_function")),
+            "{:?}",
+            names
+        );
+        assert!(symbolizer.warnings().is_empty());
+        assert_eq!(symbolizer.names(1), ["0x1"]);
+    }
+}